@@ -136,7 +136,7 @@ fn block_from_prevblock_message(c: &mut Criterion) {
     c.bench_function_over_inputs(
         "Block::from_prevblock_message",
         |b, loops| {
-            use std::collections::HashSet;
+            use std::collections::BTreeSet;
 
             use core_cbc_casper::justification::{Justification, LatestMessages};
 
@@ -156,7 +156,7 @@ fn block_from_prevblock_message(c: &mut Criterion) {
                 0.0,
                 LatestMessages::empty(),
                 1.0,
-                HashSet::new(),
+                BTreeSet::new(),
             );
             let block = Block::new(None, ValidatorNameBlockData::new(0));
             let mut message = Message::new(1, Justification::empty(), Value::One);
@@ -191,7 +191,7 @@ fn block_is_member(c: &mut Criterion) {
 fn block_estimate(c: &mut Criterion) {
     use core_cbc_casper::justification::{Justification, LatestMessages};
     use core_cbc_casper::message::Message;
-    use std::collections::HashSet;
+    use std::collections::BTreeSet;
 
     let weights = validator::Weights::new(
         vec![(0, 1.0), (1, 2.0), (2, 4.0), (3, 8.0), (4, 16.0)]
@@ -222,7 +222,7 @@ fn block_estimate(c: &mut Criterion) {
     justification.insert(Message::new(4, justification.clone(), block_8));
     let latest_messages = LatestMessages::from(&justification);
     let latest_honest_messages =
-        LatestMessagesHonest::from_latest_messages(&latest_messages, &HashSet::new());
+        LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new());
 
     c.bench_function("Block::estimate", move |b| {
         b.iter(|| Block::estimate(black_box(&latest_honest_messages), black_box(&weights)));
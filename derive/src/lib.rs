@@ -0,0 +1,226 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+//! Derive macros for the estimators of [`core_cbc_casper`], re-exported in its `estimator`
+//! module.
+//!
+//! [`core_cbc_casper`]: https://docs.rs/core_cbc_casper
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Error, Fields, Lit, Meta, NestedMeta};
+
+/// Derives an `Estimator` choosing the value of a fieldless enum estimated by the highest total
+/// weight of validators in their latest honest messages, with
+/// `core_cbc_casper::estimator::weighted_plurality`.
+///
+/// The enum is configured with the `plurality` attribute:
+///
+/// * `validator_name = "Type"` (required) is the type of the validators' names;
+/// * `tie_breaking = "..."` chooses between the values with the same highest weight:
+///   * `"declaration_order"` (default): the variant declared first wins;
+///   * `"priority"`: the variant with the highest `#[plurality(priority = n)]` wins, variants
+///     without priority having a priority of 0 and equal priorities falling back to the
+///     declaration order;
+///   * `"hash"`: the variant with the smallest hash of its serialization wins.
+///
+/// The enum must also implement the other traits required by an `Estimator`.
+///
+/// # Example
+///
+/// ```
+/// use core_cbc_casper::estimator::PluralityEstimator;
+///
+/// #[derive(
+///     Clone, Copy, Debug, Hash, PartialEq, Eq, PluralityEstimator, serde_derive::Serialize,
+/// )]
+/// #[plurality(validator_name = "u32", tie_breaking = "priority")]
+/// enum Vote {
+///     Yes,
+///     No,
+///     #[plurality(priority = 1)]
+///     Abstain,
+/// }
+/// ```
+#[proc_macro_derive(PluralityEstimator, attributes(plurality))]
+pub fn derive_plurality_estimator(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match plurality_estimator(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+enum TieBreaking {
+    DeclarationOrder,
+    Priority,
+    Hash,
+}
+
+/// Returns the name-value pairs of the `plurality` attributes.
+fn plurality_arguments(attributes: &[Attribute]) -> Result<Vec<(String, Lit, Span)>, Error> {
+    let mut arguments = vec![];
+    for attribute in attributes {
+        if !attribute.path.is_ident("plurality") {
+            continue;
+        }
+        let list = match attribute.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(Error::new(meta.span(), "expected #[plurality(...)]")),
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(ref name_value)) => {
+                    let name = match name_value.path.get_ident() {
+                        Some(ident) => ident.to_string(),
+                        None => return Err(Error::new(name_value.path.span(), "expected a name")),
+                    };
+                    arguments.push((name, name_value.lit.clone(), nested.span()));
+                }
+                _ => return Err(Error::new(nested.span(), "expected `name = value`")),
+            }
+        }
+    }
+    Ok(arguments)
+}
+
+fn plurality_estimator(input: &DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let data = match input.data {
+        Data::Enum(ref data) => data,
+        _ => {
+            return Err(Error::new(
+                input.ident.span(),
+                "PluralityEstimator can only be derived for enums",
+            ))
+        }
+    };
+    if data.variants.is_empty() {
+        return Err(Error::new(
+            input.ident.span(),
+            "PluralityEstimator needs at least one variant",
+        ));
+    }
+
+    let mut validator_name = None;
+    let mut tie_breaking = TieBreaking::DeclarationOrder;
+    for (name, lit, span) in plurality_arguments(&input.attrs)? {
+        match (name.as_str(), lit) {
+            ("validator_name", Lit::Str(ref lit)) => {
+                validator_name = Some(lit.parse::<syn::Type>()?)
+            }
+            ("tie_breaking", Lit::Str(ref lit)) => {
+                tie_breaking = match lit.value().as_str() {
+                    "declaration_order" => TieBreaking::DeclarationOrder,
+                    "priority" => TieBreaking::Priority,
+                    "hash" => TieBreaking::Hash,
+                    _ => {
+                        return Err(Error::new(
+                            lit.span(),
+                            "expected \"declaration_order\", \"priority\" or \"hash\"",
+                        ))
+                    }
+                }
+            }
+            _ => {
+                return Err(Error::new(
+                    span,
+                    "expected `validator_name = \"Type\"` or `tie_breaking = \"...\"`",
+                ))
+            }
+        }
+    }
+    let validator_name = match validator_name {
+        Some(validator_name) => validator_name,
+        None => {
+            return Err(Error::new(
+                input.ident.span(),
+                "missing #[plurality(validator_name = \"Type\")]",
+            ))
+        }
+    };
+
+    let mut variants = vec![];
+    for variant in data.variants.iter() {
+        match variant.fields {
+            Fields::Unit => (),
+            _ => {
+                return Err(Error::new(
+                    variant.span(),
+                    "PluralityEstimator can only be derived for fieldless enums",
+                ))
+            }
+        }
+        let mut priority = 0i64;
+        for (name, lit, span) in plurality_arguments(&variant.attrs)? {
+            match (name.as_str(), lit, &tie_breaking) {
+                ("priority", Lit::Int(ref lit), TieBreaking::Priority) => {
+                    priority = lit.base10_parse()?
+                }
+                ("priority", _, TieBreaking::Priority) => {
+                    return Err(Error::new(span, "expected an integer priority"))
+                }
+                ("priority", _, _) => {
+                    return Err(Error::new(
+                        span,
+                        "priorities need #[plurality(tie_breaking = \"priority\")]",
+                    ))
+                }
+                _ => return Err(Error::new(span, "expected `priority = n`")),
+            }
+        }
+        variants.push((priority, &variant.ident));
+    }
+    // The sort is stable, so equal priorities stay in declaration order.
+    if let TieBreaking::Priority = tie_breaking {
+        variants.sort_by_key(|&(priority, _)| std::cmp::Reverse(priority));
+    }
+    let variants = variants.into_iter().map(|(_, variant)| variant);
+
+    let tie_breaking = match tie_breaking {
+        TieBreaking::Hash => quote!(::core_cbc_casper::estimator::TieBreaking::SmallestHash),
+        _ => quote!(::core_cbc_casper::estimator::TieBreaking::First),
+    };
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::core_cbc_casper::estimator::Estimator for #name #type_generics
+        #where_clause
+        {
+            type ValidatorName = #validator_name;
+            type Error = ::core_cbc_casper::estimator::PluralityError;
+
+            fn estimate<U: ::core_cbc_casper::util::weight::WeightUnit>(
+                latest_messages: &::core_cbc_casper::justification::LatestMessagesHonest<Self>,
+                validators_weights: &::core_cbc_casper::validator::Weights<Self::ValidatorName, U>,
+            ) -> ::std::result::Result<Self, Self::Error> {
+                ::core_cbc_casper::estimator::weighted_plurality(
+                    latest_messages,
+                    validators_weights,
+                    &[#(#name::#variants),*],
+                    #tie_breaking,
+                )
+            }
+        }
+    })
+}
@@ -0,0 +1,195 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+extern crate core_cbc_casper;
+
+use std::collections::{BTreeSet, HashSet, VecDeque};
+
+use core_cbc_casper::blockchain::{Block, BlockData, PayloadSource, Proposer};
+use core_cbc_casper::justification::{Justification, LatestMessages};
+use core_cbc_casper::message::Message;
+use core_cbc_casper::validator;
+
+type Validator = u32;
+
+#[derive(Debug, Hash, Clone, PartialEq, Eq, serde_derive::Serialize)]
+struct Transaction {
+    from: String,
+    to: String,
+    amount: u64,
+}
+
+/// Block data holding a list of transactions.
+#[derive(Debug, Hash, Clone, PartialEq, Eq, Default, serde_derive::Serialize)]
+struct Transactions {
+    proposer: Validator,
+    transactions: Vec<Transaction>,
+}
+
+impl BlockData for Transactions {
+    type ValidatorName = Validator;
+
+    fn validator_name(&self) -> &Self::ValidatorName {
+        &self.proposer
+    }
+}
+
+#[derive(Debug)]
+struct EmptyMempool;
+
+impl std::fmt::Display for EmptyMempool {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "No pending transaction")
+    }
+}
+
+impl std::error::Error for EmptyMempool {}
+
+/// Pool of pending transactions of a validator. Transactions already included in the chain of
+/// the parent block are skipped.
+struct Mempool {
+    proposer: Validator,
+    pending: VecDeque<Transaction>,
+    max_transactions: usize,
+}
+
+impl PayloadSource<Transactions> for Mempool {
+    type Error = EmptyMempool;
+
+    fn payload(&mut self, parent: &Block<Transactions>) -> Result<Transactions, Self::Error> {
+        let mut included = HashSet::new();
+        let mut block = Some(parent.clone());
+        while let Some(current) = block {
+            included.extend(current.data().transactions);
+            block = current.prevblock();
+        }
+
+        let mut transactions = Vec::new();
+        while transactions.len() < self.max_transactions {
+            match self.pending.pop_front() {
+                Some(transaction) if included.contains(&transaction) => (),
+                Some(transaction) => transactions.push(transaction),
+                None => break,
+            }
+        }
+
+        if transactions.is_empty() {
+            Err(EmptyMempool)
+        } else {
+            Ok(Transactions {
+                proposer: self.proposer,
+                transactions,
+            })
+        }
+    }
+}
+
+fn transfer(from: &str, to: &str, amount: u64) -> Transaction {
+    Transaction {
+        from: from.to_string(),
+        to: to.to_string(),
+        amount,
+    }
+}
+
+fn main() {
+    let validators: Vec<Validator> = vec![0, 1, 2];
+    let weights = validator::Weights::new(validators.iter().map(|v| (*v, 1.0)).collect());
+    let mut states: Vec<_> = validators
+        .iter()
+        .map(|_| {
+            validator::State::new(
+                weights.clone(),
+                0.0,
+                LatestMessages::empty(),
+                1.0,
+                BTreeSet::new(),
+            )
+        })
+        .collect();
+
+    let genesis = Message::new(
+        0,
+        Justification::empty(),
+        Block::new(None, Transactions::default()),
+    );
+    for state in states.iter_mut() {
+        state.update(&[&genesis]);
+    }
+
+    // Every validator received the same transactions, in a different order.
+    let transactions = [
+        transfer("alice", "bob", 10),
+        transfer("bob", "carol", 4),
+        transfer("carol", "alice", 1),
+        transfer("alice", "carol", 7),
+    ];
+    let mut mempools: Vec<_> = validators
+        .iter()
+        .map(|validator| {
+            let mut pending: VecDeque<_> = transactions.iter().cloned().collect();
+            pending.rotate_left(*validator as usize);
+            Mempool {
+                proposer: *validator,
+                pending,
+                max_transactions: 1,
+            }
+        })
+        .collect();
+
+    // Validators propose in turn and broadcast their block to everyone.
+    for round in 0..transactions.len() {
+        let validator = validators[round % validators.len()];
+        let proposer = Proposer::new(validator);
+        let message = match proposer.propose(
+            &states[validator as usize],
+            &mut mempools[validator as usize],
+        ) {
+            Ok(message) => message,
+            Err(error) => {
+                println!("Validator {} did not propose: {}", validator, error);
+                continue;
+            }
+        };
+        println!(
+            "Validator {} proposed block {:?} at height {} with {:?}",
+            validator,
+            message.estimate(),
+            message.estimate().height(),
+            message.estimate().data().transactions,
+        );
+        for state in states.iter_mut() {
+            state.update(&[&message]);
+        }
+    }
+
+    let head = Proposer::new(0)
+        .propose(
+            &states[0],
+            &mut Mempool {
+                proposer: 0,
+                pending: transactions.iter().cloned().collect(),
+                max_transactions: transactions.len(),
+            },
+        )
+        .map(|message| message.estimate().height());
+    println!("Every transaction is in the chain: {:?}", head.is_err());
+    assert!(head.is_err());
+}
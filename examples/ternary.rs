@@ -20,101 +20,37 @@
 
 extern crate core_cbc_casper;
 
-use std::convert::From;
-
-use core_cbc_casper::estimator::Estimator;
-use core_cbc_casper::justification::LatestMessagesHonest;
+use core_cbc_casper::estimator::PluralityEstimator;
 use core_cbc_casper::message;
-use core_cbc_casper::util::weight::{WeightUnit, Zero};
 use core_cbc_casper::validator;
 
-type Validator = u32;
-
 pub type Message = message::Message<Value>;
 
-#[derive(Debug, Hash, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, serde_derive::Serialize)]
+/// The estimate is the value with the highest weight. If several values have the same highest
+/// weight, the last value is preferred, then the second value, and the first value.
+#[derive(
+    Debug,
+    Hash,
+    Clone,
+    Copy,
+    Ord,
+    PartialOrd,
+    Eq,
+    PartialEq,
+    PluralityEstimator,
+    serde_derive::Serialize,
+)]
+#[plurality(validator_name = "u32", tie_breaking = "priority")]
 pub enum Value {
     Zero = 0,
+    #[plurality(priority = 1)]
     One = 1,
+    #[plurality(priority = 2)]
     Two = 2,
 }
 
-impl<U: WeightUnit> From<((Value, U), (Value, U), (Value, U))> for Value {
-    /// If equality between two or tree values exists, last value is
-    /// prefered, then second value, and first value
-    ///
-    /// v1: w1 > w2,  w1 > w3
-    /// v2: w2 >= w1, w2 > w3
-    /// v3: w3 >= w1, w3 >= w1
-    ///
-    fn from(values: ((Value, U), (Value, U), (Value, U))) -> Self {
-        let ((v1, w1), (v2, w2), (v3, w3)) = values;
-        let mut max = v3;
-        let mut weight = w3;
-        if w2 > weight {
-            max = v2;
-            weight = w2;
-        }
-        if w1 > weight {
-            max = v1;
-        }
-        max
-    }
-}
-
-#[derive(Debug)]
-pub struct Error(&'static str);
-
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        writeln!(f, "{}", self.0)
-    }
-}
-
-impl std::error::Error for Error {}
-
-impl std::convert::From<&'static str> for Error {
-    fn from(string: &'static str) -> Self {
-        Error(string)
-    }
-}
-
-impl Estimator for Value {
-    type ValidatorName = Validator;
-    type Error = Error;
-
-    fn estimate<U: WeightUnit>(
-        latest_messages: &LatestMessagesHonest<Value>,
-        validators_weights: &validator::Weights<Validator, U>,
-    ) -> Result<Self, Self::Error> {
-        let res: Self = latest_messages
-            .iter()
-            .map(|message| {
-                (
-                    message.estimate(),
-                    validators_weights.weight(message.sender()),
-                )
-            })
-            .fold(
-                (
-                    (Value::Zero, <U as Zero<U>>::ZERO),
-                    (Value::One, <U as Zero<U>>::ZERO),
-                    (Value::Two, <U as Zero<U>>::ZERO),
-                ),
-                |acc, tuple| match tuple {
-                    (Value::Zero, Ok(weight)) => (((acc.0).0, (acc.0).1 + weight), acc.1, acc.2),
-                    (Value::One, Ok(weight)) => (acc.0, ((acc.1).0, (acc.1).1 + weight), acc.2),
-                    (Value::Two, Ok(weight)) => (acc.0, acc.1, ((acc.2).0, (acc.2).1 + weight)),
-                    _ => acc, // No weight for the given validator, do nothing
-                },
-            )
-            .into();
-        Ok(res)
-    }
-}
-
 fn main() {
-    use std::collections::HashSet;
+    use std::collections::BTreeSet;
 
     use core_cbc_casper::justification::{Justification, LatestMessages};
 
@@ -134,7 +70,7 @@ fn main() {
         0.0,
         LatestMessages::empty(),
         1.0,
-        HashSet::new(),
+        BTreeSet::new(),
     );
 
     // 1: (1)  (2)
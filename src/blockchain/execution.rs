@@ -0,0 +1,353 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::blockchain::{Block, BlockData, Error};
+use crate::justification::LatestMessagesHonest;
+use crate::util::weight::WeightUnit;
+use crate::validator;
+
+/// A deterministic application run over the data of the blocks of a chain, e.g. a key-value
+/// store. Applying data does not mutate the state but returns a new one, so that states can be
+/// kept as snapshots of the non-finalized blocks.
+pub trait StateMachine<D: BlockData>: Clone {
+    /// Returns the state resulting from applying the data of a block to `self`.
+    fn apply(&self, data: &D) -> Self;
+}
+
+#[derive(Debug)]
+pub enum ExecutionError {
+    /// The fork choice rule could not select a head block.
+    ForkChoice(Error),
+    /// The head block does not descend from the last finalized block. This is a safety fault.
+    FinalizedBlockReverted,
+}
+
+impl std::fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ExecutionError::ForkChoice(err) => std::fmt::Display::fmt(&err, f),
+            ExecutionError::FinalizedBlockReverted => {
+                writeln!(
+                    f,
+                    "The head block does not descend from the finalized block"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExecutionError {}
+
+/// Drives a [`StateMachine`] along the chain selected by the fork choice rule of a
+/// [`validator::State`].
+///
+/// Blocks are applied in order, from the genesis block up to the head block, each one exactly
+/// once as long as it stays on the selected chain. The finalized blocks, i.e. the blocks having a
+/// safety oracle above the threshold, are applied to the finalized state which is never rolled
+/// back. The other blocks of the selected chain are kept along with the state obtained after
+/// applying them; when the head moves to another fork, the snapshots of the reverted blocks are
+/// dropped and the blocks of the new fork are applied from the last common ancestor.
+///
+/// [`StateMachine`]: ./trait.StateMachine.html
+/// [`validator::State`]: ../validator/struct.State.html
+#[derive(Clone, Debug)]
+pub struct Executor<D: BlockData, S: StateMachine<D>, U: WeightUnit> {
+    safety_oracle_threshold: U,
+    finalized_block: Option<Block<D>>,
+    finalized_state: S,
+    pending: Vec<(Block<D>, S)>,
+}
+
+impl<D: BlockData, S: StateMachine<D>, U: WeightUnit> Executor<D, S, U> {
+    /// Creates an executor starting from `initial_state`, on which the genesis block will be
+    /// applied. A block is finalized once a clique of validators weighting more than
+    /// `safety_oracle_threshold` is found for it.
+    pub fn new(initial_state: S, safety_oracle_threshold: U) -> Self {
+        Executor {
+            safety_oracle_threshold,
+            finalized_block: None,
+            finalized_state: initial_state,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Returns the last finalized block, None if no block has been finalized yet.
+    pub fn finalized_block(&self) -> Option<&Block<D>> {
+        self.finalized_block.as_ref()
+    }
+
+    /// Returns the state after applying every finalized block.
+    pub fn finalized_state(&self) -> &S {
+        &self.finalized_state
+    }
+
+    /// Returns the head block of the selected chain at the last update.
+    pub fn head_block(&self) -> Option<&Block<D>> {
+        self.pending
+            .last()
+            .map(|(block, _)| block)
+            .or_else(|| self.finalized_block())
+    }
+
+    /// Returns the state after applying every block of the selected chain, up to the head block.
+    pub fn head_state(&self) -> &S {
+        self.pending
+            .last()
+            .map(|(_, state)| state)
+            .unwrap_or(&self.finalized_state)
+    }
+
+    /// Follows the latest honest messages of `validator_state`: selects the head block, applies
+    /// the blocks leading to it and advances the finalized state. Returns the newly finalized
+    /// blocks, in chain order.
+    pub fn update(
+        &mut self,
+        validator_state: &validator::State<Block<D>, U>,
+    ) -> Result<Vec<Block<D>>, ExecutionError> {
+        let latest_messages_honest = LatestMessagesHonest::from_latest_messages(
+            validator_state.latests_messages(),
+            validator_state.equivocators(),
+        );
+        if latest_messages_honest.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let head = Block::optimized_ghost(
+            &latest_messages_honest,
+            validator_state.validators_weights(),
+        )
+        .map_err(ExecutionError::ForkChoice)?;
+
+        // Height of the first block that is not finalized yet.
+        let first_height = match &self.finalized_block {
+            Some(finalized_block) if !finalized_block.is_member(&head) => {
+                return Err(ExecutionError::FinalizedBlockReverted)
+            }
+            Some(finalized_block) => finalized_block.height() + 1,
+            None => 0,
+        };
+
+        self.follow(&head, first_height);
+
+        let low = finalized_height(
+            &head,
+            first_height,
+            &latest_messages_honest,
+            validator_state,
+            self.safety_oracle_threshold,
+        );
+
+        let mut newly_finalized = Vec::new();
+        for (block, state) in self.pending.drain(..(low - first_height) as usize) {
+            self.finalized_state = state;
+            self.finalized_block = Some(block.clone());
+            newly_finalized.push(block);
+        }
+        Ok(newly_finalized)
+    }
+
+    /// Replaces the pending blocks by the chain from `first_height` to `head`, reusing the
+    /// snapshots of the blocks still on this chain.
+    fn follow(&mut self, head: &Block<D>, first_height: u64) {
+        let mut chain = Vec::new();
+        let mut block = Some(head.clone());
+        while let Some(current) = block {
+            if current.height() < first_height {
+                break;
+            }
+            block = current.prevblock();
+            chain.push(current);
+        }
+        chain.reverse();
+
+        let common = self
+            .pending
+            .iter()
+            .zip(chain.iter())
+            .take_while(|((pending_block, _), block)| pending_block == *block)
+            .count();
+        self.pending.truncate(common);
+
+        for block in chain.into_iter().skip(common) {
+            let state = self.head_state().apply(&block.arc().data);
+            self.pending.push((block, state));
+        }
+    }
+}
+
+/// Returns the height of the first block of the chain of `head` that is not finalized, given
+/// that the blocks below `first_height` are.
+pub(crate) fn finalized_height<D: BlockData, U: WeightUnit>(
+    head: &Block<D>,
+    first_height: u64,
+    latest_messages_honest: &LatestMessagesHonest<Block<D>>,
+    validator_state: &validator::State<Block<D>, U>,
+    safety_oracle_threshold: U,
+) -> u64 {
+    let is_finalized = |block: &Block<D>| {
+        !Block::safety_oracles(
+            block.clone(),
+            latest_messages_honest,
+            validator_state.equivocators(),
+            safety_oracle_threshold,
+            validator_state.validators_weights(),
+        )
+        .is_empty()
+    };
+    // The blocks of a chain accepted by a clique are accepted by the same clique, so the highest
+    // finalized height is found by a binary search.
+    let (mut low, mut high) = (first_height, head.height() + 1);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        match head.ancestor_at_height(middle) {
+            Some(ref block) if is_finalized(block) => low = middle + 1,
+            _ => high = middle,
+        }
+    }
+    low
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use crate::justification::{Justification, LatestMessages};
+    use crate::message::Message;
+    use crate::ValidatorNameBlockData;
+
+    /// Records the names of the validators of the applied blocks.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    struct History(Vec<u32>);
+
+    impl StateMachine<ValidatorNameBlockData<u32>> for History {
+        fn apply(&self, data: &ValidatorNameBlockData<u32>) -> Self {
+            let mut history = self.0.clone();
+            history.push(*data.validator_name());
+            History(history)
+        }
+    }
+
+    fn message(
+        sender: u32,
+        justification: &[&Message<Block<ValidatorNameBlockData<u32>>>],
+        prevblock: Option<&Message<Block<ValidatorNameBlockData<u32>>>>,
+    ) -> Message<Block<ValidatorNameBlockData<u32>>> {
+        let mut j = Justification::empty();
+        for message in justification {
+            j.insert((*message).clone());
+        }
+        Message::new(
+            sender,
+            j,
+            Block::new(
+                prevblock.map(Block::from),
+                ValidatorNameBlockData::new(sender),
+            ),
+        )
+    }
+
+    fn state(
+        weights: Vec<(u32, f64)>,
+    ) -> validator::State<Block<ValidatorNameBlockData<u32>>, f64> {
+        validator::State::new(
+            validator::Weights::new(weights.into_iter().collect()),
+            0.0,
+            LatestMessages::empty(),
+            1.0,
+            BTreeSet::new(),
+        )
+    }
+
+    #[test]
+    fn finalization() {
+        let mut state = state(vec![(0, 1.0), (1, 1.0)]);
+        let mut executor = Executor::new(History::default(), 1.0);
+        assert_eq!(executor.update(&state).unwrap(), vec![]);
+        assert_eq!(executor.head_block(), None);
+
+        let m0 = message(0, &[], None);
+        let m1 = message(1, &[&m0], Some(&m0));
+        let m2 = message(0, &[&m0, &m1], Some(&m1));
+        let m3 = message(1, &[&m1, &m2], Some(&m2));
+
+        state.update(&[&m0, &m1]);
+        assert_eq!(executor.update(&state).unwrap(), vec![]);
+        assert_eq!(executor.finalized_state(), &History(vec![]));
+        assert_eq!(executor.head_state(), &History(vec![0, 1]));
+
+        state.update(&[&m2, &m3]);
+        assert_eq!(
+            executor.update(&state).unwrap(),
+            vec![Block::from(&m0), Block::from(&m1)]
+        );
+        assert_eq!(executor.finalized_block(), Some(&Block::from(&m1)));
+        assert_eq!(executor.finalized_state(), &History(vec![0, 1]));
+        assert_eq!(executor.head_block(), Some(&Block::from(&m3)));
+        assert_eq!(executor.head_state(), &History(vec![0, 1, 0, 1]));
+
+        assert_eq!(executor.update(&state).unwrap(), vec![]);
+        assert_eq!(executor.finalized_state(), &History(vec![0, 1]));
+        assert_eq!(executor.head_state(), &History(vec![0, 1, 0, 1]));
+    }
+
+    #[test]
+    fn reorg() {
+        let mut state = state(vec![(0, 1.0), (1, 1.0), (2, 2.0)]);
+        let mut executor = Executor::new(History::default(), 10.0);
+
+        let genesis = message(0, &[], None);
+        let fork_1 = message(1, &[&genesis], Some(&genesis));
+        let fork_2 = message(2, &[&genesis], Some(&genesis));
+
+        state.update(&[&genesis, &fork_1]);
+        executor.update(&state).unwrap();
+        assert_eq!(executor.head_block(), Some(&Block::from(&fork_1)));
+        assert_eq!(executor.head_state(), &History(vec![0, 1]));
+
+        state.update(&[&fork_2]);
+        assert_eq!(executor.update(&state).unwrap(), vec![]);
+        assert_eq!(executor.head_block(), Some(&Block::from(&fork_2)));
+        assert_eq!(executor.head_state(), &History(vec![0, 2]));
+        assert_eq!(executor.finalized_state(), &History(vec![]));
+    }
+
+    #[test]
+    fn finalized_block_reverted() {
+        let mut state = state(vec![(0, 1.0), (1, 1.0)]);
+        let mut executor = Executor::new(History::default(), 1.0);
+
+        let m0 = message(0, &[], None);
+        let m1 = message(1, &[&m0], Some(&m0));
+        let m2 = message(0, &[&m0, &m1], Some(&m1));
+        let m3 = message(1, &[&m1, &m2], Some(&m2));
+        state.update(&[&m0, &m1, &m2, &m3]);
+        assert_eq!(executor.update(&state).unwrap().len(), 2);
+
+        let mut other_state = self::state(vec![(0, 1.0), (1, 1.0)]);
+        other_state.update(&[&message(1, &[], None)]);
+        match executor.update(&other_state) {
+            Err(ExecutionError::FinalizedBlockReverted) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert_eq!(executor.finalized_block(), Some(&Block::from(&m1)));
+    }
+}
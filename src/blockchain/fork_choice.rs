@@ -0,0 +1,624 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use crate::blockchain::{Block, BlockData, Error, ProtoBlock, Ranking, TieBreaker};
+use crate::estimator::ContextualEstimator;
+use crate::justification::LatestMessagesHonest;
+use crate::message::Message;
+use crate::util::id::Id;
+use crate::util::weight::WeightUnit;
+use crate::validator;
+
+/// Rule selecting the head of the blockchain, i.e. the block on top of which the next block is
+/// built, from the latest honest messages of the validators.
+///
+/// [`Block`] is a [`ContextualEstimator`] given a [`BlockContext`] holding the rule, so that
+/// simulations can compare the rules on the same messages with
+/// [`Message::from_validator_state_with_context`] or with a [`Proposer`]. The [`Estimator`]
+/// implementation of [`Block`] uses [`Ghost`].
+///
+/// [`Block`]: ./struct.Block.html
+/// [`ContextualEstimator`]: ../estimator/trait.ContextualEstimator.html
+/// [`BlockContext`]: ./struct.BlockContext.html
+/// [`Message::from_validator_state_with_context`]: ../message/struct.Message.html#method.from_validator_state_with_context
+/// [`Proposer`]: ./struct.Proposer.html
+/// [`Estimator`]: ../estimator/trait.Estimator.html
+/// [`Ghost`]: ./struct.Ghost.html
+///
+/// # Example
+///
+/// ```
+/// use std::collections::BTreeSet;
+///
+/// use core_cbc_casper::blockchain::{Block, ForkChoice, Ghost, LongestChain};
+/// use core_cbc_casper::justification::{Justification, LatestMessages, LatestMessagesHonest};
+/// use core_cbc_casper::message::Message;
+/// use core_cbc_casper::validator;
+/// use core_cbc_casper::ValidatorNameBlockData;
+///
+/// let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+/// // Validator 1 builds a long chain on its own while validators 2 and 3 agree on a short fork.
+/// let long = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(1));
+/// let long = Block::new(Some(long), ValidatorNameBlockData::new(1));
+/// let short = Block::new(Some(genesis), ValidatorNameBlockData::new(2));
+///
+/// let mut latest_messages = LatestMessages::empty();
+/// latest_messages.update(&Message::new(1, Justification::empty(), long.clone()));
+/// latest_messages.update(&Message::new(2, Justification::empty(), short.clone()));
+/// latest_messages.update(&Message::new(3, Justification::empty(), short.clone()));
+/// let latest_messages_honest =
+///     LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new());
+/// let weights = validator::Weights::new(vec![(1, 1.0), (2, 1.0), (3, 1.0)].into_iter().collect());
+///
+/// assert_eq!(Ghost.head(&latest_messages_honest, &weights).unwrap(), short);
+/// assert_eq!(LongestChain.head(&latest_messages_honest, &weights).unwrap(), long);
+/// ```
+pub trait ForkChoice: Send + Sync {
+    /// Selects the head among the blocks reachable from the latest honest messages, breaking the
+    /// ties with `tie_breaker`.
+    fn head_with_tie_breaker<D: BlockData, U: WeightUnit>(
+        &self,
+        latest_messages_honest: &LatestMessagesHonest<Block<D>>,
+        validators_weights: &validator::Weights<D::ValidatorName, U>,
+        tie_breaker: &TieBreaker,
+    ) -> Result<Block<D>, Error>;
+
+    /// Selects the head among the blocks reachable from the latest honest messages, breaking the
+    /// ties with the lowest block hash.
+    fn head<D: BlockData, U: WeightUnit>(
+        &self,
+        latest_messages_honest: &LatestMessagesHonest<Block<D>>,
+        validators_weights: &validator::Weights<D::ValidatorName, U>,
+    ) -> Result<Block<D>, Error> {
+        self.head_with_tie_breaker(
+            latest_messages_honest,
+            validators_weights,
+            &TieBreaker::LowestHash,
+        )
+    }
+
+    /// Returns this rule with its ties broken by `tie_breaker`, e.g. to give it to a
+    /// [`Proposer`].
+    ///
+    /// [`Proposer`]: ./struct.Proposer.html
+    fn with_tie_breaker(self, tie_breaker: TieBreaker) -> WithTieBreaker<Self>
+    where
+        Self: Sized,
+    {
+        WithTieBreaker {
+            fork_choice: self,
+            tie_breaker,
+        }
+    }
+}
+
+/// A [`ForkChoice`] rule whose `head` breaks the ties with the given [`TieBreaker`] rather than
+/// with the lowest block hash. See [`ForkChoice::with_tie_breaker`].
+///
+/// [`ForkChoice`]: ./trait.ForkChoice.html
+/// [`TieBreaker`]: ./enum.TieBreaker.html
+/// [`ForkChoice::with_tie_breaker`]: ./trait.ForkChoice.html#method.with_tie_breaker
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WithTieBreaker<F: ForkChoice> {
+    fork_choice: F,
+    tie_breaker: TieBreaker,
+}
+
+impl<F: ForkChoice> WithTieBreaker<F> {
+    pub fn fork_choice(&self) -> &F {
+        &self.fork_choice
+    }
+
+    pub fn tie_breaker(&self) -> &TieBreaker {
+        &self.tie_breaker
+    }
+}
+
+impl<F: ForkChoice> ForkChoice for WithTieBreaker<F> {
+    fn head_with_tie_breaker<D: BlockData, U: WeightUnit>(
+        &self,
+        latest_messages_honest: &LatestMessagesHonest<Block<D>>,
+        validators_weights: &validator::Weights<D::ValidatorName, U>,
+        tie_breaker: &TieBreaker,
+    ) -> Result<Block<D>, Error> {
+        self.fork_choice.head_with_tie_breaker(
+            latest_messages_honest,
+            validators_weights,
+            tie_breaker,
+        )
+    }
+
+    fn head<D: BlockData, U: WeightUnit>(
+        &self,
+        latest_messages_honest: &LatestMessagesHonest<Block<D>>,
+        validators_weights: &validator::Weights<D::ValidatorName, U>,
+    ) -> Result<Block<D>, Error> {
+        self.head_with_tie_breaker(
+            latest_messages_honest,
+            validators_weights,
+            &self.tie_breaker,
+        )
+    }
+}
+
+/// The GHOST fork choice rule over the latest honest messages, computed with
+/// [`Block::optimized_ghost`]. This is the rule used by the [`Estimator`] implementation of
+/// [`Block`].
+///
+/// [`Block::optimized_ghost`]: ./struct.Block.html#method.optimized_ghost
+/// [`Estimator`]: ../estimator/trait.Estimator.html
+/// [`Block`]: ./struct.Block.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Ghost;
+
+impl ForkChoice for Ghost {
+    fn head_with_tie_breaker<D: BlockData, U: WeightUnit>(
+        &self,
+        latest_messages_honest: &LatestMessagesHonest<Block<D>>,
+        validators_weights: &validator::Weights<D::ValidatorName, U>,
+        tie_breaker: &TieBreaker,
+    ) -> Result<Block<D>, Error> {
+        Block::optimized_ghost_with_tie_breaker(
+            latest_messages_honest,
+            validators_weights,
+            tie_breaker,
+        )
+    }
+}
+
+/// The GHOST fork choice rule computed with [`Block::mathematical_ghost`], following the paper's
+/// definition.
+///
+/// [`Block::mathematical_ghost`]: ./struct.Block.html#method.mathematical_ghost
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MathematicalGhost;
+
+impl ForkChoice for MathematicalGhost {
+    fn head_with_tie_breaker<D: BlockData, U: WeightUnit>(
+        &self,
+        latest_messages_honest: &LatestMessagesHonest<Block<D>>,
+        validators_weights: &validator::Weights<D::ValidatorName, U>,
+        tie_breaker: &TieBreaker,
+    ) -> Result<Block<D>, Error> {
+        Block::mathematical_ghost_with_tie_breaker(
+            latest_messages_honest,
+            validators_weights,
+            tie_breaker,
+        )
+    }
+}
+
+/// The GHOST fork choice rule computed with [`Block::old_ghost`].
+///
+/// [`Block::old_ghost`]: ./struct.Block.html#method.old_ghost
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OldGhost;
+
+impl ForkChoice for OldGhost {
+    fn head_with_tie_breaker<D: BlockData, U: WeightUnit>(
+        &self,
+        latest_messages_honest: &LatestMessagesHonest<Block<D>>,
+        validators_weights: &validator::Weights<D::ValidatorName, U>,
+        tie_breaker: &TieBreaker,
+    ) -> Result<Block<D>, Error> {
+        Block::old_ghost_with_tie_breaker(latest_messages_honest, validators_weights, tie_breaker)
+    }
+}
+
+/// The longest chain fork choice rule: selects the highest block with valid data reachable from
+/// the latest honest messages, regardless of the weights of the validators.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LongestChain;
+
+impl ForkChoice for LongestChain {
+    fn head_with_tie_breaker<D: BlockData, U: WeightUnit>(
+        &self,
+        latest_messages_honest: &LatestMessagesHonest<Block<D>>,
+        validators_weights: &validator::Weights<D::ValidatorName, U>,
+        tie_breaker: &TieBreaker,
+    ) -> Result<Block<D>, Error> {
+        let ranking = Ranking::new(
+            tie_breaker,
+            validators_weights,
+            latest_messages_honest.iter(),
+        );
+        Block::find_all_accessible_blocks(latest_messages_honest)
+            .into_iter()
+            .min_by(|left, right| {
+                right
+                    .height()
+                    .cmp(&left.height())
+                    .then_with(|| ranking.cmp(left, right))
+            })
+            .ok_or(Error)
+    }
+}
+
+/// The IMD-GHOST fork choice rule: like GHOST, but each block is scored with every message of
+/// the honest validators reachable from their latest messages rather than only with the latest
+/// ones. A validator therefore adds its weight to a block once per message it sent on it or on
+/// its descendants.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImdGhost;
+
+impl ForkChoice for ImdGhost {
+    fn head_with_tie_breaker<D: BlockData, U: WeightUnit>(
+        &self,
+        latest_messages_honest: &LatestMessagesHonest<Block<D>>,
+        validators_weights: &validator::Weights<D::ValidatorName, U>,
+        tie_breaker: &TieBreaker,
+    ) -> Result<Block<D>, Error> {
+        let honest_validators: HashSet<_> =
+            latest_messages_honest.iter().map(Message::sender).collect();
+
+        // Weight of the messages sent directly on each block.
+        let mut scores: HashMap<Block<D>, U> = HashMap::new();
+        let mut visited = HashSet::new();
+        let mut stack: Vec<_> = latest_messages_honest.iter().collect();
+        while let Some(message) = stack.pop() {
+            if !visited.insert(message.id()) {
+                continue;
+            }
+            if honest_validators.contains(message.sender()) {
+                if let Ok(weight) = validators_weights.weight(message.sender()) {
+                    *scores.entry(message.estimate().clone()).or_insert(U::ZERO) += weight;
+                }
+            }
+            stack.extend(message.justification().iter());
+        }
+
+        let valid_blocks = Block::valid_ancestry(scores.keys());
+
+        let mut blocks: Vec<Block<D>> = vec![];
+        let mut known: HashSet<Block<D>> = HashSet::new();
+        for tip in scores.keys() {
+            let mut block = Some(tip);
+            while let Some(current) = block {
+                if !known.insert(current.clone()) {
+                    break;
+                }
+                blocks.push(current.clone());
+                block = current.prev_block_as_ref();
+            }
+        }
+
+        // Accumulates the scores of the subtrees from the leaves up; a block is always higher
+        // than its prevblock.
+        blocks.sort_by_key(|block| std::cmp::Reverse(block.height()));
+        for block in &blocks {
+            if let Some(prevblock) = block.prev_block_as_ref() {
+                let score = scores.get(block).cloned().unwrap_or(U::ZERO);
+                *scores.entry(prevblock.clone()).or_insert(U::ZERO) += score;
+            }
+        }
+
+        let mut children: HashMap<&Block<D>, Vec<&Block<D>>> = HashMap::new();
+        let mut genesis_blocks = vec![];
+        for block in &valid_blocks {
+            match block.prev_block_as_ref() {
+                Some(prevblock) => children.entry(prevblock).or_default().push(block),
+                None => genesis_blocks.push(block),
+            }
+        }
+
+        let ranking = Ranking::new(
+            tie_breaker,
+            validators_weights,
+            latest_messages_honest.iter(),
+        );
+        let mut head = heaviest(genesis_blocks, &scores, &ranking).ok_or(Error)?;
+        while let Some(child) = children
+            .remove(head)
+            .and_then(|children| heaviest(children, &scores, &ranking))
+        {
+            head = child;
+        }
+        Ok(head.clone())
+    }
+}
+
+/// Returns the block with the highest score, breaking ties with `ranking`.
+fn heaviest<'z, D: BlockData, U: WeightUnit>(
+    blocks: Vec<&'z Block<D>>,
+    scores: &HashMap<Block<D>, U>,
+    ranking: &Ranking<D, U>,
+) -> Option<&'z Block<D>> {
+    let score = |block: &Block<D>| scores.get(block).cloned().unwrap_or(U::ZERO);
+    blocks.into_iter().min_by(|left, right| {
+        score(right)
+            .partial_cmp(&score(left))
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| ranking.cmp(left, right))
+    })
+}
+
+/// Application state providing the data of an estimated block, e.g. from the pending
+/// transactions or the parameters of the current epoch. The unit state provides the default data.
+pub trait EstimatePayload<D: BlockData> {
+    /// Returns the data of the estimated block on top of `parent`, the block chosen by the fork
+    /// choice rule.
+    fn payload(&self, parent: &Block<D>) -> D;
+}
+
+impl<D: BlockData> EstimatePayload<D> for () {
+    fn payload(&self, _parent: &Block<D>) -> D {
+        D::default()
+    }
+}
+
+/// Context of the estimate of a [`Block`]: the [`ForkChoice`] rule selecting the parent of the
+/// estimated block, and the application state providing its data with [`EstimatePayload`].
+///
+/// [`Block`]: ./struct.Block.html
+/// [`ForkChoice`]: ./trait.ForkChoice.html
+/// [`EstimatePayload`]: ./trait.EstimatePayload.html
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockContext<F: ForkChoice, C = ()> {
+    fork_choice: F,
+    state: C,
+}
+
+impl<F: ForkChoice> BlockContext<F> {
+    pub fn new(fork_choice: F) -> Self {
+        BlockContext::with_state(fork_choice, ())
+    }
+}
+
+impl<F: ForkChoice, C> BlockContext<F, C> {
+    pub fn with_state(fork_choice: F, state: C) -> Self {
+        BlockContext { fork_choice, state }
+    }
+
+    pub fn fork_choice(&self) -> &F {
+        &self.fork_choice
+    }
+
+    pub fn state(&self) -> &C {
+        &self.state
+    }
+}
+
+impl<D, F, C> ContextualEstimator<BlockContext<F, C>> for Block<D>
+where
+    D: BlockData,
+    F: ForkChoice,
+    C: EstimatePayload<D>,
+{
+    fn estimate_with_context<U: WeightUnit>(
+        latest_messages_honest: &LatestMessagesHonest<Self>,
+        validators_weights: &validator::Weights<D::ValidatorName, U>,
+        context: &BlockContext<F, C>,
+    ) -> Result<Self, Self::Error> {
+        let prevblock = context
+            .fork_choice
+            .head(latest_messages_honest, validators_weights)?;
+        let data = context.state.payload(&prevblock);
+        Ok(Block::from(ProtoBlock::new(Some(prevblock), data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use crate::estimator::Estimator;
+    use crate::justification::{Justification, LatestMessages};
+    use crate::ValidatorNameBlockData;
+
+    type TestBlock = Block<ValidatorNameBlockData<u32>>;
+
+    fn weights() -> validator::Weights<u32, f64> {
+        validator::Weights::new(vec![(1, 1.0), (2, 1.0), (3, 1.0)].into_iter().collect())
+    }
+
+    fn honest(messages: &[&Message<TestBlock>]) -> LatestMessagesHonest<TestBlock> {
+        let mut latest_messages = LatestMessages::empty();
+        for message in messages {
+            latest_messages.update(message);
+        }
+        LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new())
+    }
+
+    fn block(prevblock: &TestBlock, name: u32) -> TestBlock {
+        Block::new(Some(prevblock.clone()), ValidatorNameBlockData::new(name))
+    }
+
+    fn justified(
+        sender: u32,
+        previous: &Message<TestBlock>,
+        block: TestBlock,
+    ) -> Message<TestBlock> {
+        let mut justification = Justification::empty();
+        justification.insert(previous.clone());
+        Message::new(sender, justification, block)
+    }
+
+    #[test]
+    fn rules_agree_on_a_single_chain() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_1 = block(&genesis, 1);
+        let block_2 = block(&block_1, 2);
+        let message_1 = Message::new(1, Justification::empty(), block_1);
+        let message_2 = justified(2, &message_1, block_2.clone());
+        let latest_messages_honest = honest(&[&message_1, &message_2]);
+        let weights = weights();
+
+        assert_eq!(
+            Ghost.head(&latest_messages_honest, &weights).unwrap(),
+            block_2
+        );
+        assert_eq!(
+            MathematicalGhost
+                .head(&latest_messages_honest, &weights)
+                .unwrap(),
+            block_2
+        );
+        assert_eq!(
+            OldGhost.head(&latest_messages_honest, &weights).unwrap(),
+            block_2
+        );
+        assert_eq!(
+            LongestChain
+                .head(&latest_messages_honest, &weights)
+                .unwrap(),
+            block_2
+        );
+        assert_eq!(
+            ImdGhost.head(&latest_messages_honest, &weights).unwrap(),
+            block_2
+        );
+    }
+
+    #[test]
+    fn no_messages() {
+        let latest_messages_honest = honest(&[]);
+        let weights = weights();
+
+        assert!(LongestChain
+            .head(&latest_messages_honest, &weights)
+            .is_err());
+        assert!(ImdGhost.head(&latest_messages_honest, &weights).is_err());
+    }
+
+    #[test]
+    fn imd_ghost_counts_all_messages() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        // Validator 1 sent three messages on the same branch while validators 2 and 3 only sent
+        // one each on the other branch.
+        let block_a1 = block(&genesis, 1);
+        let block_a2 = block(&block_a1, 1);
+        let block_a3 = block(&block_a2, 1);
+        let block_b = block(&genesis, 2);
+        let message_a1 = Message::new(1, Justification::empty(), block_a1);
+        let message_a2 = justified(1, &message_a1, block_a2);
+        let message_a3 = justified(1, &message_a2, block_a3.clone());
+        let message_b2 = Message::new(2, Justification::empty(), block_b.clone());
+        let message_b3 = Message::new(3, Justification::empty(), block_b.clone());
+        let latest_messages_honest = honest(&[&message_a3, &message_b2, &message_b3]);
+        let weights = weights();
+
+        assert_eq!(
+            Ghost.head(&latest_messages_honest, &weights).unwrap(),
+            block_b
+        );
+        assert_eq!(
+            ImdGhost.head(&latest_messages_honest, &weights).unwrap(),
+            block_a3
+        );
+    }
+
+    #[test]
+    fn imd_ghost_ignores_messages_of_absent_validators() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_a1 = block(&genesis, 1);
+        let block_a2 = block(&block_a1, 1);
+        let block_b = block(&genesis, 2);
+        // Validator 1's messages are only known through the justification of validator 2, whose
+        // latest message is on the other branch.
+        let message_a1 = Message::new(1, Justification::empty(), block_a1);
+        let message_a2 = justified(1, &message_a1, block_a2);
+        let message_b = justified(2, &message_a2, block_b.clone());
+        let mut latest_messages = LatestMessages::empty();
+        latest_messages.update(&message_b);
+        let latest_messages_honest =
+            LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new());
+        let weights = weights();
+
+        assert_eq!(
+            ImdGhost.head(&latest_messages_honest, &weights).unwrap(),
+            block_b
+        );
+    }
+
+    #[test]
+    fn estimate_with_fork_choice() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_long = block(&block(&genesis, 1), 1);
+        let block_short = block(&genesis, 2);
+        let latest_messages_honest = honest(&[
+            &Message::new(1, Justification::empty(), block_long.clone()),
+            &Message::new(2, Justification::empty(), block_short.clone()),
+            &Message::new(3, Justification::empty(), block_short.clone()),
+        ]);
+        let weights = weights();
+
+        assert_eq!(
+            Block::estimate_with_context(
+                &latest_messages_honest,
+                &weights,
+                &BlockContext::new(LongestChain)
+            )
+            .unwrap()
+            .prevblock(),
+            Some(block_long)
+        );
+        assert_eq!(
+            Block::estimate_with_context(
+                &latest_messages_honest,
+                &weights,
+                &BlockContext::new(Ghost)
+            )
+            .unwrap(),
+            Block::estimate(&latest_messages_honest, &weights).unwrap()
+        );
+        assert_eq!(
+            latest_messages_honest
+                .make_estimate_with_context(&weights, &BlockContext::new(Ghost))
+                .unwrap()
+                .prevblock(),
+            Some(block_short)
+        );
+    }
+
+    /// Application state naming the proposer of the estimated blocks.
+    struct ProposerName(u32);
+
+    impl EstimatePayload<ValidatorNameBlockData<u32>> for ProposerName {
+        fn payload(&self, _parent: &TestBlock) -> ValidatorNameBlockData<u32> {
+            ValidatorNameBlockData::new(self.0)
+        }
+    }
+
+    #[test]
+    fn estimate_with_fork_choice_and_state() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_long = block(&block(&genesis, 1), 1);
+        let block_short = block(&genesis, 2);
+        let latest_messages_honest = honest(&[
+            &Message::new(1, Justification::empty(), block_long.clone()),
+            &Message::new(2, Justification::empty(), block_short),
+            &Message::new(3, Justification::empty(), block_long.clone()),
+        ]);
+
+        let estimate = latest_messages_honest
+            .make_estimate_with_context(
+                &weights(),
+                &BlockContext::with_state(ImdGhost, ProposerName(3)),
+            )
+            .unwrap();
+        assert_eq!(estimate.prevblock(), Some(block_long));
+        assert_eq!(*estimate.proposer(), 3);
+    }
+}
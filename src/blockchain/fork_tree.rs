@@ -0,0 +1,520 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use serde_derive::Serialize;
+
+use crate::blockchain::{Block, BlockData, Error, ForkChoice};
+use crate::justification::LatestMessagesHonest;
+use crate::util::hash::Hash;
+use crate::util::id::Id;
+use crate::util::weight::WeightUnit;
+use crate::validator;
+
+/// Identifies a block of a [`ForkTree`]. Its hash is serialized as a hexadecimal string.
+///
+/// [`ForkTree`]: ./struct.ForkTree.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockSummary {
+    id: Hash,
+    height: u64,
+}
+
+impl BlockSummary {
+    fn new<D: BlockData>(block: &Block<D>) -> Self {
+        BlockSummary {
+            id: block.id(),
+            height: block.height(),
+        }
+    }
+
+    pub fn id(&self) -> Hash {
+        self.id
+    }
+
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+}
+
+impl serde::Serialize for BlockSummary {
+    fn serialize<T: serde::Serializer>(&self, serializer: T) -> Result<T::Ok, T::Error> {
+        use serde::ser::SerializeStruct;
+        let mut summary = serializer.serialize_struct("BlockSummary", 2)?;
+        summary.serialize_field("id", &format!("{:#x}", self.id))?;
+        summary.serialize_field("height", &self.height)?;
+        summary.end()
+    }
+}
+
+/// A block with several children in a [`ForkTree`].
+///
+/// [`ForkTree`]: ./struct.ForkTree.html
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BranchPoint {
+    block: BlockSummary,
+    children: Vec<BlockSummary>,
+}
+
+impl BranchPoint {
+    pub fn block(&self) -> &BlockSummary {
+        &self.block
+    }
+
+    /// Returns the children of the block, ordered by hash.
+    pub fn children(&self) -> &[BlockSummary] {
+        &self.children
+    }
+}
+
+/// The blocks of a [`ForkTree`] from its closest branch point, excluded, to one of its tips.
+///
+/// [`ForkTree`]: ./struct.ForkTree.html
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Branch<U: WeightUnit> {
+    tip: BlockSummary,
+    fork_point: Option<BlockSummary>,
+    weight: U,
+    depth: u64,
+}
+
+impl<U: WeightUnit> Branch<U> {
+    pub fn tip(&self) -> &BlockSummary {
+        &self.tip
+    }
+
+    /// Returns the branch point the branch starts from, or `None` if the branch starts from a
+    /// genesis block.
+    pub fn fork_point(&self) -> Option<&BlockSummary> {
+        self.fork_point.as_ref()
+    }
+
+    /// Returns the cumulative weight of the validators whose latest honest message is on a block
+    /// of the branch.
+    pub fn weight(&self) -> U {
+        self.weight
+    }
+
+    /// Returns the number of blocks from the common ancestor of all the tips to the tip of the
+    /// branch, or from its genesis block if the tips do not share an ancestor.
+    pub fn depth(&self) -> u64 {
+        self.depth
+    }
+}
+
+/// Serializable view of the blocks tree seen by a validator, e.g. to monitor the competing
+/// branches. It is built from the chains of the latest honest messages.
+///
+/// # Example
+///
+/// ```
+/// use std::collections::BTreeSet;
+///
+/// use core_cbc_casper::blockchain::{Block, ForkTree, Ghost};
+/// use core_cbc_casper::justification::{Justification, LatestMessages};
+/// use core_cbc_casper::message::Message;
+/// use core_cbc_casper::util::id::Id;
+/// use core_cbc_casper::validator;
+/// use core_cbc_casper::ValidatorNameBlockData;
+///
+/// let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+/// let block_1 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(1));
+/// let block_2 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(2));
+///
+/// let mut state = validator::State::new(
+///     validator::Weights::new(vec![(1, 1.0), (2, 2.0)].into_iter().collect()),
+///     0.0,
+///     LatestMessages::empty(),
+///     1.0,
+///     BTreeSet::new(),
+/// );
+/// state.update(&[
+///     &Message::new(1, Justification::empty(), block_1),
+///     &Message::new(2, Justification::empty(), block_2.clone()),
+/// ]);
+///
+/// let fork_tree = ForkTree::from_state(&state, &Ghost).unwrap();
+/// assert_eq!(fork_tree.head().id(), block_2.id());
+/// assert_eq!(fork_tree.tips().count(), 2);
+/// assert_eq!(fork_tree.branch_points()[0].block().id(), genesis.id());
+/// assert_eq!(fork_tree.branches()[0].tip().id(), block_2.id());
+/// assert_eq!(fork_tree.branches()[0].weight(), 2.0);
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ForkTree<U: WeightUnit> {
+    head: BlockSummary,
+    common_ancestor: Option<BlockSummary>,
+    branch_points: Vec<BranchPoint>,
+    branches: Vec<Branch<U>>,
+}
+
+impl<U: WeightUnit> ForkTree<U> {
+    /// Builds the tree of the chains of the latest honest messages, whose head is selected with
+    /// `fork_choice`.
+    pub fn new<D, F>(
+        latest_messages_honest: &LatestMessagesHonest<Block<D>>,
+        validators_weights: &validator::Weights<D::ValidatorName, U>,
+        fork_choice: &F,
+    ) -> Result<Self, Error>
+    where
+        D: BlockData,
+        F: ForkChoice,
+    {
+        let head = fork_choice.head(latest_messages_honest, validators_weights)?;
+
+        let mut latest_weights: HashMap<&Block<D>, U> = HashMap::new();
+        for message in latest_messages_honest.iter() {
+            if let Ok(weight) = validators_weights.weight(message.sender()) {
+                *latest_weights.entry(message.estimate()).or_insert(U::ZERO) += weight;
+            }
+        }
+
+        let mut children: HashMap<&Block<D>, Vec<&Block<D>>> = HashMap::new();
+        let mut genesis_blocks = vec![];
+        for message in latest_messages_honest.iter() {
+            let mut block = message.estimate();
+            if children.contains_key(block) {
+                continue;
+            }
+            children.insert(block, vec![]);
+            // Climbs the chain until reaching a block already in the tree.
+            loop {
+                match block.prev_block_as_ref() {
+                    Some(prevblock) => {
+                        let known = children.contains_key(prevblock);
+                        children.entry(prevblock).or_default().push(block);
+                        if known {
+                            break;
+                        }
+                        block = prevblock;
+                    }
+                    None => {
+                        genesis_blocks.push(block);
+                        break;
+                    }
+                }
+            }
+        }
+
+        let common_ancestor = match genesis_blocks.as_slice() {
+            [genesis] => {
+                let mut block = *genesis;
+                while let [child] = children[block].as_slice() {
+                    block = child;
+                }
+                Some(block)
+            }
+            _ => None,
+        };
+
+        let mut branch_points: Vec<_> = children
+            .iter()
+            .filter(|(_, block_children)| block_children.len() > 1)
+            .map(|(block, block_children)| {
+                let mut block_children: Vec<_> = block_children
+                    .iter()
+                    .map(|child| BlockSummary::new(*child))
+                    .collect();
+                block_children.sort_by_key(BlockSummary::id);
+                BranchPoint {
+                    block: BlockSummary::new(*block),
+                    children: block_children,
+                }
+            })
+            .collect();
+        branch_points
+            .sort_by_key(|branch_point| (branch_point.block.height, branch_point.block.id));
+
+        let mut branches: Vec<_> = children
+            .iter()
+            .filter(|(_, block_children)| block_children.is_empty())
+            .map(|(tip, _)| {
+                let mut weight = U::ZERO;
+                let mut block = *tip;
+                let fork_point = loop {
+                    weight += latest_weights.get(block).cloned().unwrap_or(U::ZERO);
+                    match block.prev_block_as_ref() {
+                        Some(prevblock) if children[prevblock].len() > 1 => break Some(prevblock),
+                        Some(prevblock) => block = prevblock,
+                        None => break None,
+                    }
+                };
+                Branch {
+                    tip: BlockSummary::new(*tip),
+                    fork_point: fork_point.map(BlockSummary::new),
+                    weight,
+                    depth: tip.height() - common_ancestor.map(Block::height).unwrap_or(0),
+                }
+            })
+            .collect();
+        // Heaviest branches first.
+        branches.sort_by(|left, right| {
+            right
+                .weight
+                .partial_cmp(&left.weight)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| left.tip.id.cmp(&right.tip.id))
+        });
+
+        Ok(ForkTree {
+            head: BlockSummary::new(&head),
+            common_ancestor: common_ancestor.map(BlockSummary::new),
+            branch_points,
+            branches,
+        })
+    }
+
+    /// Builds the tree seen by a validator, from its latest honest messages.
+    pub fn from_state<D, F>(
+        validator_state: &validator::State<Block<D>, U>,
+        fork_choice: &F,
+    ) -> Result<Self, Error>
+    where
+        D: BlockData,
+        F: ForkChoice,
+    {
+        let latest_messages_honest = LatestMessagesHonest::from_latest_messages(
+            validator_state.latests_messages(),
+            validator_state.equivocators(),
+        );
+        ForkTree::new(
+            &latest_messages_honest,
+            validator_state.validators_weights(),
+            fork_choice,
+        )
+    }
+
+    /// Returns the block selected by the fork choice rule.
+    pub fn head(&self) -> &BlockSummary {
+        &self.head
+    }
+
+    /// Returns the highest block all the tips descend from, or `None` if the tree has several
+    /// genesis blocks.
+    pub fn common_ancestor(&self) -> Option<&BlockSummary> {
+        self.common_ancestor.as_ref()
+    }
+
+    /// Returns the blocks with several children, by increasing height.
+    pub fn branch_points(&self) -> &[BranchPoint] {
+        &self.branch_points
+    }
+
+    /// Returns one branch per tip, from the heaviest to the lightest.
+    pub fn branches(&self) -> &[Branch<U>] {
+        &self.branches
+    }
+
+    /// Iterates over the blocks without children.
+    pub fn tips(&self) -> impl Iterator<Item = &BlockSummary> {
+        self.branches.iter().map(Branch::tip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use crate::blockchain::{Ghost, LongestChain};
+    use crate::justification::{Justification, LatestMessages};
+    use crate::message::Message;
+    use crate::ValidatorNameBlockData;
+
+    type TestBlock = Block<ValidatorNameBlockData<u32>>;
+
+    fn block(prevblock: &TestBlock, name: u32) -> TestBlock {
+        Block::new(Some(prevblock.clone()), ValidatorNameBlockData::new(name))
+    }
+
+    fn fork_tree<F: ForkChoice>(
+        latest_blocks: &[(u32, &TestBlock)],
+        fork_choice: &F,
+    ) -> ForkTree<f64> {
+        let mut latest_messages = LatestMessages::empty();
+        for (sender, block) in latest_blocks {
+            latest_messages.update(&Message::new(
+                *sender,
+                Justification::empty(),
+                (*block).clone(),
+            ));
+        }
+        let weights = validator::Weights::new(
+            vec![(1, 1.0), (2, 2.0), (3, 3.0), (4, 4.0)]
+                .into_iter()
+                .collect(),
+        );
+        ForkTree::new(
+            &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+            &weights,
+            fork_choice,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn single_chain() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_1 = block(&genesis, 1);
+        let block_2 = block(&block_1, 2);
+        let tree = fork_tree(&[(1, &block_1), (2, &block_2)], &Ghost);
+
+        assert_eq!(tree.head().id(), block_2.id());
+        assert_eq!(tree.common_ancestor().unwrap().id(), block_2.id());
+        assert!(tree.branch_points().is_empty());
+        assert_eq!(tree.branches().len(), 1);
+        let branch = &tree.branches()[0];
+        assert_eq!(branch.tip().id(), block_2.id());
+        assert_eq!(branch.tip().height(), 2);
+        assert_eq!(branch.fork_point(), None);
+        assert_eq!(branch.weight(), 3.0);
+        assert_eq!(branch.depth(), 0);
+    }
+
+    #[test]
+    fn competing_branches() {
+        // genesis - block_1 - block_2a - block_3a
+        //                   \ block_2b
+        //                   \ block_2c
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_1 = block(&genesis, 1);
+        let block_2a = block(&block_1, 1);
+        let block_3a = block(&block_2a, 1);
+        let block_2b = block(&block_1, 2);
+        let block_2c = block(&block_1, 3);
+        let tree = fork_tree(
+            &[
+                (1, &block_3a),
+                (2, &block_2b),
+                (3, &block_2c),
+                (4, &block_2a),
+            ],
+            &Ghost,
+        );
+
+        assert_eq!(tree.head().id(), block_3a.id());
+        assert_eq!(tree.common_ancestor().unwrap().id(), block_1.id());
+        assert_eq!(tree.branch_points().len(), 1);
+        assert_eq!(tree.branch_points()[0].block().id(), block_1.id());
+        let mut children = vec![block_2a.id(), block_2b.id(), block_2c.id()];
+        children.sort();
+        assert_eq!(
+            tree.branch_points()[0]
+                .children()
+                .iter()
+                .map(BlockSummary::id)
+                .collect::<Vec<_>>(),
+            children
+        );
+
+        let branches: Vec<_> = tree
+            .branches()
+            .iter()
+            .map(|branch| {
+                (
+                    branch.tip().id(),
+                    branch.fork_point().map(BlockSummary::id),
+                    branch.weight(),
+                    branch.depth(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            branches,
+            vec![
+                (block_3a.id(), Some(block_1.id()), 5.0, 2),
+                (block_2c.id(), Some(block_1.id()), 3.0, 1),
+                (block_2b.id(), Some(block_1.id()), 2.0, 1),
+            ]
+        );
+        assert_eq!(tree.tips().count(), 3);
+    }
+
+    #[test]
+    fn several_genesis_blocks() {
+        let genesis_a = Block::new(None, ValidatorNameBlockData::new(0));
+        let genesis_b = Block::new(None, ValidatorNameBlockData::new(1));
+        let block_a = block(&genesis_a, 2);
+        let tree = fork_tree(&[(1, &block_a), (2, &genesis_b)], &LongestChain);
+
+        assert_eq!(tree.head().id(), block_a.id());
+        assert_eq!(tree.common_ancestor(), None);
+        assert!(tree.branch_points().is_empty());
+        let branches: Vec<_> = tree
+            .branches()
+            .iter()
+            .map(|branch| (branch.tip().id(), branch.fork_point(), branch.depth()))
+            .collect();
+        assert_eq!(
+            branches,
+            vec![(genesis_b.id(), None, 0), (block_a.id(), None, 1)]
+        );
+    }
+
+    #[test]
+    fn from_state() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let message_1 = Message::new(1, Justification::empty(), block(&genesis, 1));
+        let message_2 = Message::new(2, Justification::empty(), block(&genesis, 2));
+        let equivocation = Message::new(1, Justification::empty(), block(&genesis, 3));
+        let mut state = validator::State::new(
+            validator::Weights::new(vec![(1, 1.0), (2, 1.0)].into_iter().collect()),
+            0.0,
+            LatestMessages::empty(),
+            1.0,
+            BTreeSet::new(),
+        );
+        state.update(&[&message_1, &message_2, &equivocation]);
+
+        // Validator 1 equivocated, so only the branch of validator 2 is left.
+        let tree = ForkTree::from_state(&state, &Ghost).unwrap();
+        assert_eq!(tree.branches().len(), 1);
+        assert_eq!(tree.head().id(), message_2.estimate().id());
+        assert_eq!(tree.head(), tree.branches()[0].tip());
+        assert_eq!(tree.branches()[0].weight(), 1.0);
+    }
+
+    #[test]
+    fn serialize_json() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_1 = block(&genesis, 1);
+        let block_2 = block(&genesis, 2);
+        let tree = fork_tree(&[(1, &block_1), (2, &block_2)], &Ghost);
+
+        let json = serde_json::to_value(&tree).unwrap();
+        let genesis_json = serde_json::json!({
+            "id": format!("{:#x}", genesis.id()),
+            "height": 0,
+        });
+        assert_eq!(
+            json["head"],
+            serde_json::json!({ "id": format!("{:#x}", block_2.id()), "height": 1 })
+        );
+        assert_eq!(json["common_ancestor"], genesis_json);
+        assert_eq!(json["branch_points"][0]["block"], genesis_json);
+        assert_eq!(json["branches"][0]["fork_point"], genesis_json);
+        assert_eq!(json["branches"][0]["weight"], 2.0);
+        assert_eq!(json["branches"][0]["depth"], 1);
+        assert_eq!(json["branches"][1]["weight"], 1.0);
+    }
+}
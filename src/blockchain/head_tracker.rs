@@ -0,0 +1,411 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::blockchain::execution::finalized_height;
+use crate::blockchain::{Block, BlockData, Error, ForkChoice, Ghost};
+use crate::justification::LatestMessagesHonest;
+use crate::util::weight::WeightUnit;
+use crate::validator;
+
+/// Event emitted by a [`HeadTracker`] when the head block changes.
+///
+/// [`HeadTracker`]: ./struct.HeadTracker.html
+#[derive(Clone, PartialEq, Eq)]
+pub struct HeadChanged<D: BlockData> {
+    /// The previous head, `None` for the first head.
+    pub old: Option<Block<D>>,
+    /// The new head.
+    pub new: Block<D>,
+    /// The highest block both heads descend from, `None` if they do not share a genesis block
+    /// or if there was no previous head.
+    pub common_ancestor: Option<Block<D>>,
+    /// The blocks of the previous chain left, from the previous head down to the common
+    /// ancestor, excluded, i.e. in the order they have to be rolled back.
+    pub reverted: Vec<Block<D>>,
+    /// The blocks of the new chain, from the common ancestor, excluded, up to the new head, i.e.
+    /// in the order they have to be applied.
+    pub applied: Vec<Block<D>>,
+    /// Whether the new head does not descend from the finalized block. This is a safety fault.
+    pub safety_fault: bool,
+}
+
+impl<D: BlockData> std::fmt::Debug for HeadChanged<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("HeadChanged")
+            .field("old", &self.old)
+            .field("new", &self.new)
+            .field("common_ancestor", &self.common_ancestor)
+            .field("reverted", &self.reverted)
+            .field("applied", &self.applied)
+            .field("safety_fault", &self.safety_fault)
+            .finish()
+    }
+}
+
+/// Follows the head block selected by a [`ForkChoice`] rule over the latest honest messages of
+/// a [`validator::State`], and reports how the head moves at each update.
+///
+/// As in the [`Executor`], a block is finalized once a clique of validators weighting more than
+/// the safety oracle threshold is found for it. A head change reverting the finalized block is
+/// flagged as a safety fault; the finalized block is then kept until the head comes back on its
+/// chain.
+///
+/// [`ForkChoice`]: ./trait.ForkChoice.html
+/// [`validator::State`]: ../validator/struct.State.html
+/// [`Executor`]: ./struct.Executor.html
+///
+/// # Example
+///
+/// ```
+/// use std::collections::BTreeSet;
+///
+/// use core_cbc_casper::blockchain::{Block, HeadTracker};
+/// use core_cbc_casper::justification::{Justification, LatestMessages};
+/// use core_cbc_casper::message::Message;
+/// use core_cbc_casper::validator;
+/// use core_cbc_casper::ValidatorNameBlockData;
+///
+/// let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+/// let fork_1 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(1));
+/// let fork_2 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(2));
+///
+/// let mut state = validator::State::new(
+///     validator::Weights::new(vec![(1, 1.0), (2, 2.0)].into_iter().collect()),
+///     0.0,
+///     LatestMessages::empty(),
+///     1.0,
+///     BTreeSet::new(),
+/// );
+/// let mut head_tracker = HeadTracker::new(10.0);
+///
+/// state.update(&[&Message::new(1, Justification::empty(), fork_1.clone())]);
+/// let event = head_tracker.update(&state).unwrap().unwrap();
+/// assert_eq!(event.applied, vec![genesis.clone(), fork_1.clone()]);
+///
+/// state.update(&[&Message::new(2, Justification::empty(), fork_2.clone())]);
+/// let event = head_tracker.update(&state).unwrap().unwrap();
+/// assert_eq!(event.old, Some(fork_1.clone()));
+/// assert_eq!(event.new, fork_2.clone());
+/// assert_eq!(event.common_ancestor, Some(genesis));
+/// assert_eq!(event.reverted, vec![fork_1]);
+/// assert_eq!(event.applied, vec![fork_2]);
+/// assert!(!event.safety_fault);
+///
+/// assert_eq!(head_tracker.update(&state).unwrap(), None);
+/// ```
+#[derive(Clone, Debug)]
+pub struct HeadTracker<D: BlockData, U: WeightUnit, F: ForkChoice = Ghost> {
+    safety_oracle_threshold: U,
+    fork_choice: F,
+    head: Option<Block<D>>,
+    finalized_block: Option<Block<D>>,
+}
+
+impl<D: BlockData, U: WeightUnit> HeadTracker<D, U, Ghost> {
+    /// Creates a head tracker following the GHOST fork choice rule.
+    pub fn new(safety_oracle_threshold: U) -> Self {
+        HeadTracker::with_fork_choice(safety_oracle_threshold, Ghost)
+    }
+}
+
+impl<D: BlockData, U: WeightUnit, F: ForkChoice> HeadTracker<D, U, F> {
+    /// Creates a head tracker following the given [`ForkChoice`] rule.
+    ///
+    /// [`ForkChoice`]: ./trait.ForkChoice.html
+    pub fn with_fork_choice(safety_oracle_threshold: U, fork_choice: F) -> Self {
+        HeadTracker {
+            safety_oracle_threshold,
+            fork_choice,
+            head: None,
+            finalized_block: None,
+        }
+    }
+
+    /// Returns the head block at the last update.
+    pub fn head(&self) -> Option<&Block<D>> {
+        self.head.as_ref()
+    }
+
+    /// Returns the last finalized block, None if no block has been finalized yet.
+    pub fn finalized_block(&self) -> Option<&Block<D>> {
+        self.finalized_block.as_ref()
+    }
+
+    /// Selects the head block from the latest honest messages of `validator_state` and advances
+    /// the finalized block. Returns the change of head, if any.
+    pub fn update(
+        &mut self,
+        validator_state: &validator::State<Block<D>, U>,
+    ) -> Result<Option<HeadChanged<D>>, Error> {
+        let latest_messages_honest = LatestMessagesHonest::from_latest_messages(
+            validator_state.latests_messages(),
+            validator_state.equivocators(),
+        );
+        if latest_messages_honest.is_empty() {
+            return Ok(None);
+        }
+
+        let new = self.fork_choice.head(
+            &latest_messages_honest,
+            validator_state.validators_weights(),
+        )?;
+
+        let safety_fault = match &self.finalized_block {
+            Some(finalized_block) => !finalized_block.is_member(&new),
+            None => false,
+        };
+        if !safety_fault {
+            let first_height = self
+                .finalized_block
+                .as_ref()
+                .map(|finalized_block| finalized_block.height() + 1)
+                .unwrap_or(0);
+            let height = finalized_height(
+                &new,
+                first_height,
+                &latest_messages_honest,
+                validator_state,
+                self.safety_oracle_threshold,
+            );
+            if height > first_height {
+                self.finalized_block = new.ancestor_at_height(height - 1);
+            }
+        }
+
+        if self.head.as_ref() == Some(&new) {
+            return Ok(None);
+        }
+        let old = self.head.replace(new.clone());
+        Ok(Some(HeadChanged::new(old, new, safety_fault)))
+    }
+}
+
+impl<D: BlockData> HeadChanged<D> {
+    /// Walks down the chains of `old` and `new` to their common ancestor, only visiting the
+    /// blocks above it, to collect the blocks left and the blocks entered.
+    fn new(old: Option<Block<D>>, new: Block<D>, safety_fault: bool) -> Self {
+        let mut reverted = vec![];
+        let mut applied = vec![];
+        let mut old_block = old.as_ref();
+        let mut new_block = Some(&new);
+        let common_ancestor = loop {
+            match (old_block, new_block) {
+                (Some(old_block), Some(new_block)) if old_block == new_block => {
+                    break Some(old_block.clone())
+                }
+                (None, None) => break None,
+                _ => (),
+            }
+            // None is lower than any height, so the highest block is always stepped down first.
+            let old_height = old_block.map(Block::height);
+            let new_height = new_block.map(Block::height);
+            if old_height >= new_height {
+                if let Some(block) = old_block {
+                    reverted.push(block.clone());
+                    old_block = block.prev_block_as_ref();
+                }
+            }
+            if new_height >= old_height {
+                if let Some(block) = new_block {
+                    applied.push(block.clone());
+                    new_block = block.prev_block_as_ref();
+                }
+            }
+        };
+        applied.reverse();
+
+        HeadChanged {
+            old,
+            new,
+            common_ancestor,
+            reverted,
+            applied,
+            safety_fault,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use crate::justification::{Justification, LatestMessages};
+    use crate::message::Message;
+    use crate::ValidatorNameBlockData;
+
+    type TestBlock = Block<ValidatorNameBlockData<u32>>;
+
+    fn message(
+        sender: u32,
+        justification: &[&Message<TestBlock>],
+        prevblock: Option<&Message<TestBlock>>,
+    ) -> Message<TestBlock> {
+        let mut j = Justification::empty();
+        for message in justification {
+            j.insert((*message).clone());
+        }
+        Message::new(
+            sender,
+            j,
+            Block::new(
+                prevblock.map(Block::from),
+                ValidatorNameBlockData::new(sender),
+            ),
+        )
+    }
+
+    fn state(weights: Vec<(u32, f64)>) -> validator::State<TestBlock, f64> {
+        validator::State::new(
+            validator::Weights::new(weights.into_iter().collect()),
+            0.0,
+            LatestMessages::empty(),
+            1.0,
+            BTreeSet::new(),
+        )
+    }
+
+    #[test]
+    fn no_messages() {
+        let state = state(vec![(0, 1.0)]);
+        let mut head_tracker = HeadTracker::new(1.0);
+
+        assert_eq!(head_tracker.update(&state).unwrap(), None);
+        assert_eq!(head_tracker.head(), None);
+    }
+
+    #[test]
+    fn extension() {
+        let mut state = state(vec![(0, 1.0), (1, 1.0)]);
+        let mut head_tracker = HeadTracker::new(10.0);
+
+        let m0 = message(0, &[], None);
+        let m1 = message(1, &[&m0], Some(&m0));
+        let m2 = message(0, &[&m0, &m1], Some(&m1));
+
+        state.update(&[&m0, &m1]);
+        assert_eq!(
+            head_tracker.update(&state).unwrap(),
+            Some(HeadChanged {
+                old: None,
+                new: Block::from(&m1),
+                common_ancestor: None,
+                reverted: vec![],
+                applied: vec![Block::from(&m0), Block::from(&m1)],
+                safety_fault: false,
+            })
+        );
+
+        state.update(&[&m2]);
+        assert_eq!(
+            head_tracker.update(&state).unwrap(),
+            Some(HeadChanged {
+                old: Some(Block::from(&m1)),
+                new: Block::from(&m2),
+                common_ancestor: Some(Block::from(&m1)),
+                reverted: vec![],
+                applied: vec![Block::from(&m2)],
+                safety_fault: false,
+            })
+        );
+        assert_eq!(head_tracker.head(), Some(&Block::from(&m2)));
+        assert_eq!(head_tracker.update(&state).unwrap(), None);
+    }
+
+    #[test]
+    fn deep_reorg() {
+        let mut state = state(vec![(1, 1.0), (2, 2.0)]);
+        let mut head_tracker = HeadTracker::new(10.0);
+
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let mut fork_1 = vec![genesis.clone()];
+        for _ in 0..1000 {
+            let block = Block::new(fork_1.last().cloned(), ValidatorNameBlockData::new(1));
+            fork_1.push(block);
+        }
+        let mut fork_2 = vec![genesis.clone()];
+        for _ in 0..500 {
+            let block = Block::new(fork_2.last().cloned(), ValidatorNameBlockData::new(2));
+            fork_2.push(block);
+        }
+
+        state.update(&[&Message::new(
+            1,
+            Justification::empty(),
+            fork_1.last().unwrap().clone(),
+        )]);
+        head_tracker.update(&state).unwrap();
+        state.update(&[&Message::new(
+            2,
+            Justification::empty(),
+            fork_2.last().unwrap().clone(),
+        )]);
+        let event = head_tracker.update(&state).unwrap().unwrap();
+
+        assert_eq!(event.old.as_ref(), fork_1.last());
+        assert_eq!(Some(&event.new), fork_2.last());
+        assert_eq!(event.common_ancestor, Some(genesis));
+        fork_1.reverse();
+        assert_eq!(event.reverted, &fork_1[..1000]);
+        assert_eq!(event.applied, &fork_2[1..]);
+        assert!(!event.safety_fault);
+    }
+
+    #[test]
+    fn finalized_block_reverted() {
+        let mut state = state(vec![(0, 1.0), (1, 1.0)]);
+        let mut head_tracker = HeadTracker::new(1.0);
+
+        let m0 = message(0, &[], None);
+        let m1 = message(1, &[&m0], Some(&m0));
+        let m2 = message(0, &[&m0, &m1], Some(&m1));
+        let m3 = message(1, &[&m1, &m2], Some(&m2));
+        state.update(&[&m0, &m1, &m2, &m3]);
+        assert!(!head_tracker.update(&state).unwrap().unwrap().safety_fault);
+        assert_eq!(head_tracker.finalized_block(), Some(&Block::from(&m1)));
+
+        let other_genesis = message(1, &[], None);
+        let mut other_state = self::state(vec![(0, 1.0), (1, 1.0)]);
+        other_state.update(&[&other_genesis]);
+        assert_eq!(
+            head_tracker.update(&other_state).unwrap(),
+            Some(HeadChanged {
+                old: Some(Block::from(&m3)),
+                new: Block::from(&other_genesis),
+                common_ancestor: None,
+                reverted: vec![
+                    Block::from(&m3),
+                    Block::from(&m2),
+                    Block::from(&m1),
+                    Block::from(&m0),
+                ],
+                applied: vec![Block::from(&other_genesis)],
+                safety_fault: true,
+            })
+        );
+        assert_eq!(head_tracker.finalized_block(), Some(&Block::from(&m1)));
+
+        // Coming back to the finalized chain is not a fault.
+        let event = head_tracker.update(&state).unwrap().unwrap();
+        assert_eq!(event.new, Block::from(&m3));
+        assert!(!event.safety_fault);
+    }
+}
@@ -0,0 +1,2531 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::convert::From;
+use std::iter::Iterator;
+use std::sync::{Arc, RwLock};
+
+use serde_derive::Serialize;
+
+use crate::estimator::Estimator;
+use crate::justification::LatestMessagesHonest;
+use crate::message::Message;
+use crate::safety_oracle;
+use crate::util::hash::Hash;
+use crate::util::id::Id;
+use crate::util::skip_list::{follow_skip, skip_height};
+use crate::util::weight::{WeightUnit, Zero};
+use crate::validator;
+
+mod execution;
+mod fork_choice;
+mod fork_tree;
+mod head_tracker;
+mod proposer;
+mod tie_breaker;
+
+pub use self::execution::{ExecutionError, Executor, StateMachine};
+pub use self::fork_choice::{
+    BlockContext, EstimatePayload, ForkChoice, Ghost, ImdGhost, LongestChain, MathematicalGhost,
+    OldGhost, WithTieBreaker,
+};
+pub use self::fork_tree::{BlockSummary, Branch, BranchPoint, ForkTree};
+pub use self::head_tracker::{HeadChanged, HeadTracker};
+pub use self::proposer::{PayloadSource, ProposalError, Proposer};
+pub use self::tie_breaker::TieBreaker;
+
+pub(crate) use self::tie_breaker::Ranking;
+
+/// This trait must be implemented on a data type that is to be held by a [`Block<D>`].
+///
+/// [`Block<D>`]: ./struct.Block.html
+///
+/// # Example
+///
+/// ```
+/// use core_cbc_casper::blockchain::{Block, BlockData};
+/// use core_cbc_casper::validator;
+///
+/// type ValidatorName = i32;
+///
+/// #[derive(Hash, Clone, PartialEq, Eq, Default, Debug, serde_derive::Serialize)]
+/// struct StringBlockData {
+///     data: String,
+///     validator: ValidatorName,
+/// }
+///
+/// impl BlockData for StringBlockData {
+///     type ValidatorName = ValidatorName;
+///
+///     fn validator_name(&self) -> &Self::ValidatorName {
+///         &self.validator
+///     }
+/// }
+///
+/// assert_eq!(
+///     Block::new(
+///         None,
+///         StringBlockData { data: "Hello, World!".to_string(), validator: 0 },
+///     ).data(),
+///     StringBlockData { data: "Hello, World!".to_string(), validator: 0 },
+/// );
+/// ```
+pub trait BlockData:
+    std::hash::Hash + Clone + Eq + Send + Sync + Default + serde::Serialize
+{
+    /// The type of validators that can produce data of this type.
+    type ValidatorName: validator::ValidatorName;
+
+    /// The name of the validator that produced the block containing this data.
+    fn validator_name(&self) -> &Self::ValidatorName;
+
+    /// Checks that the data is valid on top of the `parent` block, None for a genesis block, e.g.
+    /// that its transactions can be applied. Invalid blocks and their descendants are ignored by
+    /// the fork choice and rejected by [`ChainSpec::validate`]. Any data is valid by default.
+    ///
+    /// [`ChainSpec::validate`]: ./struct.ChainSpec.html#method.validate
+    fn validate(&self, _parent: Option<&Block<Self>>) -> Result<(), DataError> {
+        Ok(())
+    }
+}
+
+/// Reason for which [`BlockData::validate`] rejects the data of a block.
+///
+/// [`BlockData::validate`]: ./trait.BlockData.html#method.validate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataError(pub &'static str);
+
+impl std::fmt::Display for DataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for DataError {}
+
+/// Identifier of a chain. It is held by every block and is part of the blocks serialization, so
+/// that messages created for a chain cannot be replayed on another one.
+pub type ChainId = u64;
+
+/// The chain identifier given to genesis blocks created without a [`ChainSpec`].
+///
+/// [`ChainSpec`]: ./struct.ChainSpec.html
+pub const DEFAULT_CHAIN_ID: ChainId = 0;
+
+#[derive(Clone)]
+struct ProtoBlock<D: BlockData> {
+    chain_id: ChainId,
+    prevblock: Option<Block<D>>,
+    data: D,
+    /// Optional slot number or timestamp, strictly increasing along a chain.
+    slot: Option<u64>,
+    /// Number of ancestors, 0 for a genesis block.
+    height: u64,
+    /// Ancestor at height `skip_height(height)`, None for genesis blocks.
+    skip: Option<Block<D>>,
+}
+
+/// Dropping the last reference to the tip of a long chain would drop its ancestors recursively
+/// and overflow the stack. The ancestors that are not referenced elsewhere are instead released
+/// one after the other.
+impl<D: BlockData> Drop for ProtoBlock<D> {
+    fn drop(&mut self) {
+        let mut stack: Vec<Block<D>> = self.prevblock.take().into_iter().collect();
+        stack.extend(self.skip.take());
+        while let Some(block) = stack.pop() {
+            if let Ok(mut protoblock) = Arc::try_unwrap(block.0) {
+                stack.extend(protoblock.prevblock.take());
+                stack.extend(protoblock.skip.take());
+            }
+        }
+    }
+}
+
+impl<D: BlockData> ProtoBlock<D> {
+    /// Creates a block on top of `prevblock`, inheriting its chain identifier. A block without
+    /// prevblock is a genesis block of the default chain.
+    pub fn new(prevblock: Option<Block<D>>, data: D) -> ProtoBlock<D> {
+        ProtoBlock::with_slot(prevblock, None, data)
+    }
+
+    /// Creates a block on top of `prevblock` with the given slot. The slot is not validated
+    /// against the prevblock's slot.
+    pub fn with_slot(prevblock: Option<Block<D>>, slot: Option<u64>, data: D) -> ProtoBlock<D> {
+        match prevblock {
+            Some(prevblock) => {
+                let height = prevblock.height() + 1;
+                ProtoBlock {
+                    chain_id: prevblock.chain_id(),
+                    skip: prevblock.ancestor_at_height(skip_height(height)),
+                    prevblock: Some(prevblock),
+                    data,
+                    slot,
+                    height,
+                }
+            }
+            None => {
+                let mut genesis = ProtoBlock::genesis(DEFAULT_CHAIN_ID, data);
+                genesis.slot = slot;
+                genesis
+            }
+        }
+    }
+
+    pub fn genesis(chain_id: ChainId, data: D) -> ProtoBlock<D> {
+        ProtoBlock {
+            chain_id,
+            prevblock: None,
+            data,
+            slot: None,
+            height: 0,
+            skip: None,
+        }
+    }
+}
+
+/// Simplest structure of a block with a `prevblock` pointer for running Casper on a blockchain.
+/// The data contained in these blocks must implement the [`BlockData`] trait.
+///
+/// This structure implements the [`Estimator`] trait and uses the GHOST algorithm defined in
+/// the paper for the estimate function.
+///
+/// [`BlockData`]: ./trait.BlockData.html
+/// [`Estimator`]: ../estimator/trait.Estimator.html
+///
+/// # Example
+///
+/// Using the [`ValidatorNameBlockData`] type message type for brevity's sake.
+///
+/// [`ValidatorNameBlockData`]: ../struct.ValidatorNameBlockData.html
+///
+/// ```
+/// use core_cbc_casper::blockchain::Block;
+/// use core_cbc_casper::justification::Justification;
+/// use core_cbc_casper::message::Message;
+/// use core_cbc_casper::ValidatorNameBlockData;
+///
+/// let message = Message::new(
+///     0,
+///     Justification::empty(),
+///     Block::new(None, ValidatorNameBlockData::new(1)),
+/// );
+/// let block_1 = Block::from(&message);
+/// let block_2 = Block::new(Some(block_1.clone()), ValidatorNameBlockData::new(2));
+/// assert!(block_1.is_member(&block_2));
+/// assert!(Block::from(&message).is_member(&block_1));
+/// assert!(Block::from(&message).is_member(&block_2));
+/// assert!(!block_2.is_member(&block_1));
+/// assert!(!block_2.is_member(&Block::from(&message)));
+/// ```
+#[derive(Clone)]
+pub struct Block<D: BlockData>(Arc<ProtoBlock<D>>, Hash);
+
+impl<D: BlockData> std::fmt::Debug for Block<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} -> {:?}",
+            self.id(),
+            self.prevblock_id().unwrap_or_default()
+        )
+    }
+}
+
+impl<D: BlockData> serde::Serialize for ProtoBlock<D> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut block = serializer.serialize_struct("Block", 4)?;
+        block.serialize_field("chain_id", &self.chain_id)?;
+        block.serialize_field("prevblock", &self.prevblock.as_ref().map(Block::id))?;
+        block.serialize_field("slot", &self.slot)?;
+        block.serialize_field("data", &self.data)?;
+        block.end()
+    }
+}
+
+impl<D: BlockData> Id for ProtoBlock<D> {
+    type ID = Hash;
+}
+
+/// Blocks are serialized without their ancestors: the prevblock is only referenced by its
+/// identifier. The serialization is the same as that of the [`DetachedBlock`].
+///
+/// [`DetachedBlock`]: ./struct.DetachedBlock.html
+impl<D: BlockData> serde::Serialize for Block<D> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(&self.0, serializer)
+    }
+}
+
+impl<D: BlockData> Id for Block<D> {
+    type ID = Hash;
+
+    // Redefine id to not recompute the hash every time
+    fn id(&self) -> Self::ID {
+        self.1
+    }
+}
+
+impl<D: BlockData> std::hash::Hash for Block<D> {
+    fn hash<H: std::hash::Hasher>(&self, hasher: &mut H) {
+        self.id().hash(hasher);
+    }
+}
+
+impl<D: BlockData> PartialEq for Block<D> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(self.arc(), other.arc()) || self.id() == other.id()
+    }
+}
+
+impl<D: BlockData> Eq for Block<D> {}
+
+impl<D: BlockData> From<ProtoBlock<D>> for Block<D> {
+    fn from(protoblock: ProtoBlock<D>) -> Self {
+        // Block is not mutable, id is computed only once at creation
+        let id = protoblock.id();
+        Block(Arc::new(protoblock), id)
+    }
+}
+
+impl<D: BlockData> From<&Message<Block<D>>> for Block<D> {
+    fn from(message: &Message<Block<D>>) -> Self {
+        message.estimate().clone()
+    }
+}
+
+/// A block detached from its ancestors, e.g. to be sent over the network. The prevblock is only
+/// referenced by its identifier, so the serialization and the identifier of a detached block are
+/// those of the [`Block`] it was detached from.
+///
+/// [`Block`]: ./struct.Block.html
+///
+/// # Example
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// use core_cbc_casper::blockchain::Block;
+/// use core_cbc_casper::util::id::Id;
+/// use core_cbc_casper::ValidatorNameBlockData;
+///
+/// let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+/// let block = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(1));
+/// let detached = block.detach();
+/// assert_eq!(detached.id(), block.id());
+///
+/// let known_blocks: HashMap<_, _> = vec![(genesis.id(), genesis)].into_iter().collect();
+/// assert_eq!(detached.attach(|id| known_blocks.get(id).cloned()), Ok(block));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename = "Block")]
+pub struct DetachedBlock<D: BlockData> {
+    pub chain_id: ChainId,
+    pub prevblock: Option<Hash>,
+    pub slot: Option<u64>,
+    pub data: D,
+}
+
+impl<D: BlockData> Id for DetachedBlock<D> {
+    type ID = Hash;
+}
+
+impl<D: BlockData> DetachedBlock<D> {
+    /// Rebuilds the block by looking up its prevblock with `known_blocks`. Checks that the block
+    /// belongs to the chain of its prevblock and that its slot is after the prevblock's.
+    pub fn attach<F>(self, known_blocks: F) -> Result<Block<D>, ValidationError>
+    where
+        F: FnOnce(&Hash) -> Option<Block<D>>,
+    {
+        let block = match self.prevblock {
+            None => {
+                let mut genesis = ProtoBlock::genesis(self.chain_id, self.data);
+                genesis.slot = self.slot;
+                Block::from(genesis)
+            }
+            Some(id) => {
+                let prevblock = known_blocks(&id)
+                    .filter(|prevblock| prevblock.id() == id)
+                    .ok_or(ValidationError::UnknownPrevblock(id))?;
+                if prevblock.chain_id() != self.chain_id {
+                    return Err(ValidationError::WrongChainId(self.chain_id));
+                }
+                Block::from(ProtoBlock::with_slot(Some(prevblock), self.slot, self.data))
+            }
+        };
+        block.validate_slot()?;
+        block.validate_data().map(|()| block)
+    }
+}
+
+#[derive(Debug)]
+pub struct Error;
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Failed to get prevblock using ghost")
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Reasons for which a [`ChainSpec`] rejects a [`message`].
+///
+/// [`ChainSpec`]: ./struct.ChainSpec.html
+/// [`message`]: ../message/struct.Message.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The block, or a block from the message's justification, belongs to another chain.
+    WrongChainId(ChainId),
+    /// The block ancestry does not end at the chain's genesis block.
+    UnknownGenesis,
+    /// The block's slot is not strictly greater than the slot of its nearest ancestor that has
+    /// one.
+    InvalidSlot(u64),
+    /// The prevblock of a [`DetachedBlock`] is not known.
+    ///
+    /// [`DetachedBlock`]: ./struct.DetachedBlock.html
+    UnknownPrevblock(Hash),
+    /// The data of the block, or of a block the message builds on, is rejected by
+    /// [`BlockData::validate`].
+    ///
+    /// [`BlockData::validate`]: ./trait.BlockData.html#method.validate
+    InvalidData(Hash, DataError),
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ValidationError::WrongChainId(chain_id) => {
+                writeln!(f, "Block belongs to the chain {}", chain_id)
+            }
+            ValidationError::UnknownGenesis => {
+                writeln!(f, "Block ancestry does not end at the chain genesis")
+            }
+            ValidationError::InvalidSlot(slot) => {
+                writeln!(f, "Block slot {} is not after its ancestors slots", slot)
+            }
+            ValidationError::UnknownPrevblock(id) => writeln!(f, "Unknown prevblock {:?}", id),
+            ValidationError::InvalidData(id, error) => {
+                writeln!(f, "Block {:?} has invalid data: {}", id, error.0)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Configuration of a chain: its identifier and its single valid genesis block.
+///
+/// The chain identifier is held by every block of the chain and is part of their serialization,
+/// thus of the hash of any [`message`] carrying them. A [`validator::State`] updated through
+/// [`update_state`] only accepts messages whose blocks descend from the genesis block.
+///
+/// [`message`]: ../message/struct.Message.html
+/// [`validator::State`]: ../validator/struct.State.html
+/// [`update_state`]: #method.update_state
+///
+/// # Example
+///
+/// ```
+/// use core_cbc_casper::blockchain::{Block, ChainSpec, ValidationError};
+/// use core_cbc_casper::justification::Justification;
+/// use core_cbc_casper::message::Message;
+/// use core_cbc_casper::ValidatorNameBlockData;
+///
+/// let chain_spec = ChainSpec::new(1, ValidatorNameBlockData::new(0));
+/// let genesis_message = Message::new(0, Justification::empty(), chain_spec.genesis().clone());
+/// assert_eq!(chain_spec.validate(&genesis_message), Ok(()));
+///
+/// let other_chain_message = Message::new(
+///     0,
+///     Justification::empty(),
+///     Block::new(None, ValidatorNameBlockData::new(0)),
+/// );
+/// assert_eq!(
+///     chain_spec.validate(&other_chain_message),
+///     Err(ValidationError::WrongChainId(0)),
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainSpec<D: BlockData> {
+    genesis: Block<D>,
+    validated: ValidatedBlocks,
+}
+
+/// Identifiers of the blocks whose whole ancestry was found valid by a [`ChainSpec`], shared by
+/// its clones, so that validating a message only checks the blocks it introduces.
+///
+/// [`ChainSpec`]: ./struct.ChainSpec.html
+#[derive(Clone, Default)]
+struct ValidatedBlocks(Arc<RwLock<HashSet<Hash>>>);
+
+impl ValidatedBlocks {
+    fn contains(&self, id: &Hash) -> bool {
+        self.0
+            .read()
+            .map(|validated| validated.contains(id))
+            .unwrap_or(false)
+    }
+
+    fn extend(&self, ids: impl Iterator<Item = Hash>) {
+        if let Ok(mut validated) = self.0.write() {
+            validated.extend(ids);
+        }
+    }
+}
+
+impl std::fmt::Debug for ValidatedBlocks {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("ValidatedBlocks")
+    }
+}
+
+/// The validated blocks are a cache, two specifications with the same genesis are equal.
+impl PartialEq for ValidatedBlocks {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for ValidatedBlocks {}
+
+impl<D: BlockData> ChainSpec<D> {
+    /// Creates the specification of the chain `chain_id` along with its genesis block holding
+    /// `genesis_data`.
+    pub fn new(chain_id: ChainId, genesis_data: D) -> Self {
+        let genesis = Block::from(ProtoBlock::genesis(chain_id, genesis_data));
+        let validated = ValidatedBlocks::default();
+        validated.extend(std::iter::once(genesis.id()));
+        ChainSpec { genesis, validated }
+    }
+
+    /// Returns the identifier of the chain, shared by all of its blocks.
+    pub fn chain_id(&self) -> ChainId {
+        self.genesis.chain_id()
+    }
+
+    /// Returns the genesis block every block of the chain descends from.
+    pub fn genesis(&self) -> &Block<D> {
+        &self.genesis
+    }
+
+    /// Checks that the block of the message and the blocks of the messages in its
+    /// [`justification`] belong to this chain, and that they and their ancestors have slots
+    /// consistent with their ancestors and data [valid] on top of them, so that messages building
+    /// on an invalid block are rejected. The blocks found valid are remembered, so that only the
+    /// blocks not validated yet are checked. Messages further down the justification are expected
+    /// to have been validated when they were received.
+    ///
+    /// [valid]: ./trait.BlockData.html#method.validate
+    ///
+    /// [`justification`]: ../justification/struct.Justification.html
+    pub fn validate(&self, message: &Message<Block<D>>) -> Result<(), ValidationError> {
+        std::iter::once(message)
+            .chain(message.justification().iter())
+            .try_for_each(|message| self.validate_block(message.estimate()))
+    }
+
+    fn validate_block(&self, block: &Block<D>) -> Result<(), ValidationError> {
+        if block.chain_id() != self.chain_id() {
+            Err(ValidationError::WrongChainId(block.chain_id()))
+        } else if block.genesis() != self.genesis {
+            Err(ValidationError::UnknownGenesis)
+        } else {
+            // Climbs to the first validated block, then validates the blocks on the way back down.
+            let unknown: Vec<&Block<D>> =
+                std::iter::successors(Some(block), |block| block.prev_block_as_ref())
+                    .take_while(|block| !self.validated.contains(&block.id()))
+                    .collect();
+            for block in unknown.iter().rev() {
+                block.validate_slot()?;
+                block.validate_data()?;
+            }
+            self.validated
+                .extend(unknown.iter().map(|block| block.id()));
+            Ok(())
+        }
+    }
+
+    /// Adds the valid messages to the state using [`validator::State::update`]. Returns the
+    /// rejected messages along with the reason of their rejection.
+    ///
+    /// [`validator::State::update`]: ../validator/struct.State.html#method.update
+    pub fn update_state<U: WeightUnit>(
+        &self,
+        state: &mut validator::State<Block<D>, U>,
+        messages: &[&Message<Block<D>>],
+    ) -> Vec<(Message<Block<D>>, ValidationError)> {
+        let mut rejected = Vec::new();
+        let valid_messages: Vec<_> = messages
+            .iter()
+            .cloned()
+            .filter(|message| match self.validate(message) {
+                Ok(()) => true,
+                Err(error) => {
+                    rejected.push(((*message).clone(), error));
+                    false
+                }
+            })
+            .collect();
+        state.update(&valid_messages);
+        rejected
+    }
+}
+
+impl<D: BlockData> Estimator for Block<D> {
+    type Error = Error;
+    type ValidatorName = D::ValidatorName;
+
+    fn estimate<U: WeightUnit>(
+        latest_messages: &LatestMessagesHonest<Self>,
+        validators_weights: &validator::Weights<D::ValidatorName, U>,
+    ) -> Result<Self, Self::Error> {
+        let prevblock = Block::optimized_ghost(latest_messages, validators_weights)?;
+        Ok(Block::from(ProtoBlock::new(Some(prevblock), D::default())))
+    }
+}
+
+type BlocksChildrenMap<D> = HashMap<Block<D>, HashSet<Block<D>>>;
+type GenesisBlocks<D> = HashSet<Block<D>>;
+type BlocksValidatorsMap<D> = HashMap<Block<D>, <D as BlockData>::ValidatorName>;
+
+impl<D: BlockData> Block<D> {
+    pub fn new(prevblock: Option<Block<D>>, data: D) -> Self {
+        Block::from(ProtoBlock::new(prevblock, data))
+    }
+
+    /// Creates a new block at `slot` on top of `prevblock`. The slot, which can be a slot number
+    /// or a timestamp, must be strictly greater than the slot of its nearest ancestor that has one.
+    pub fn new_at_slot(
+        prevblock: Option<Block<D>>,
+        slot: u64,
+        data: D,
+    ) -> Result<Self, ValidationError> {
+        let block = Block::from(ProtoBlock::with_slot(prevblock, Some(slot), data));
+        block.validate_slot().map(|()| block)
+    }
+
+    /// Checks the data of the block on top of its prevblock with [`BlockData::validate`].
+    ///
+    /// [`BlockData::validate`]: ./trait.BlockData.html#method.validate
+    pub(crate) fn validate_data(&self) -> Result<(), ValidationError> {
+        self.arc()
+            .data
+            .validate(self.prev_block_as_ref())
+            .map_err(|error| ValidationError::InvalidData(self.id(), error))
+    }
+
+    /// Checks that the slot of the block, if any, is strictly greater than the slot of its
+    /// nearest ancestor that has one.
+    fn validate_slot(&self) -> Result<(), ValidationError> {
+        let slot = match self.slot() {
+            Some(slot) => slot,
+            None => return Ok(()),
+        };
+        let mut ancestor = self.prev_block_as_ref();
+        while let Some(block) = ancestor {
+            if let Some(ancestor_slot) = block.slot() {
+                return if slot <= ancestor_slot {
+                    Err(ValidationError::InvalidSlot(slot))
+                } else {
+                    Ok(())
+                };
+            }
+            ancestor = block.prev_block_as_ref();
+        }
+        Ok(())
+    }
+
+    fn arc(&self) -> &Arc<ProtoBlock<D>> {
+        &self.0
+    }
+
+    /// Creates a new block from a prevblock message and an incomplete block. See [`Proposer`] for
+    /// a higher level way of creating blocks.
+    /// An incomplete_block is a block with a None prevblock (i.e., Estimator) AND is not a
+    /// genesis_block. The slot of the incomplete block is kept as is and is checked against the
+    /// prevblock by [`ChainSpec::validate`].
+    ///
+    /// [`Proposer`]: ./struct.Proposer.html
+    /// [`ChainSpec::validate`]: ./struct.ChainSpec.html#method.validate
+    pub fn from_prevblock_message(
+        prevblock_message: Option<Message<Block<D>>>,
+        incomplete_block: Block<D>,
+    ) -> Self {
+        let prevblock = prevblock_message.map(|message| Block::from(&message));
+        let mut incomplete_block = (**incomplete_block.arc()).clone();
+        match prevblock {
+            Some(_) => Block::from(ProtoBlock::with_slot(
+                prevblock,
+                incomplete_block.slot,
+                incomplete_block.data.clone(),
+            )),
+            None => {
+                incomplete_block.prevblock = None;
+                incomplete_block.height = 0;
+                incomplete_block.skip = None;
+                Block::from(incomplete_block)
+            }
+        }
+    }
+
+    /// Mathematical definition of blockchain membership. Runs in O(log n) using the cached
+    /// heights and skip pointers.
+    pub fn is_member(&self, other: &Self) -> bool {
+        other
+            .ancestor_at_height(self.height())
+            .map(|ancestor| &ancestor == self)
+            .unwrap_or(false)
+    }
+
+    /// Direct implementation of the score function from the paper. Contrary to the paper's
+    /// definition, score directly uses the latest honest messages rather than the latest honest
+    /// estimates and a protocol state. Source:
+    /// https://github.com/cbc-casper/cbc-casper-paper/blob/acc66e2ba4461a005262e2d5f434fd2e30ef0ff3/examples.tex#L568
+    pub fn score<U: WeightUnit>(
+        &self,
+        latest_messages_honest: &LatestMessagesHonest<Self>,
+        weights: &validator::Weights<D::ValidatorName, U>,
+    ) -> U {
+        latest_messages_honest
+            .iter()
+            .filter(|message| self.is_member(&message.estimate()))
+            .fold(<U as Zero<U>>::ZERO, |acc, message| {
+                match weights.weight(&message.sender()) {
+                    Err(_) => acc,
+                    Ok(weight) => acc + weight,
+                }
+            })
+    }
+
+    /// Direct implementation of the children function from the paper. Contrary to the paper's
+    /// definition, we are directly parsing blocks to filter children out of rather than taking
+    /// the estimates out of messages. Source:
+    /// https://github.com/cbc-casper/cbc-casper-paper/blob/acc66e2ba4461a005262e2d5f434fd2e30ef0ff3/examples.tex#L578
+    pub fn children<'z>(&self, protocol_state: &HashSet<&'z Self>) -> HashSet<&'z Self> {
+        protocol_state
+            .iter()
+            .cloned()
+            .filter(|block| block.prevblock() == Some(self.clone()))
+            .collect()
+    }
+
+    /// Direct implementation of the argmax function from the paper. Source:
+    /// https://github.com/cbc-casper/cbc-casper-paper/blob/acc66e2ba4461a005262e2d5f434fd2e30ef0ff3/examples.tex#L395
+    fn argmax<'z, F, U>(items: HashSet<&'z Block<D>>, scoring_function: F) -> HashSet<&'z Block<D>>
+    where
+        F: std::ops::Fn(&Block<D>) -> U,
+        U: WeightUnit + std::cmp::PartialOrd,
+    {
+        let mut iterator = items.iter();
+
+        let item = iterator.next();
+        if item == None {
+            return HashSet::new();
+        }
+
+        let item = item.unwrap();
+
+        let mut max_score = scoring_function(item);
+        let mut max = HashSet::new();
+        max.insert(*item);
+
+        for item in iterator {
+            let block_score = scoring_function(*item);
+
+            match block_score.partial_cmp(&max_score) {
+                Some(Ordering::Equal) => {
+                    max.insert(*item);
+                }
+                Some(Ordering::Greater) => max.clear(),
+                Some(Ordering::Less) | None => (),
+            };
+
+            if max.is_empty() {
+                max.insert(*item);
+                max_score = block_score;
+            }
+        }
+
+        max
+    }
+
+    /// Direct implementation of the best children function from the paper. Source:
+    /// https://github.com/cbc-casper/cbc-casper-paper/blob/acc66e2ba4461a005262e2d5f434fd2e30ef0ff3/examples.tex#L587
+    pub fn best_children<'z, U: WeightUnit + std::cmp::PartialOrd>(
+        &self,
+        protocol_state: &HashSet<&'z Self>,
+        latest_messages_honest: &LatestMessagesHonest<Self>,
+        weights: &validator::Weights<D::ValidatorName, U>,
+    ) -> HashSet<&'z Self> {
+        let scoring_function = |block: &Self| block.score(latest_messages_honest, weights);
+        Block::argmax(self.children(&protocol_state), &scoring_function)
+    }
+
+    /// This function reconstructs the blocks tree from `latest_messages_honest` and uses those to
+    /// return the block with highest score according to the definition 4.30 of the paper. Contrary
+    /// to the paper's definition, it does not return a set in case of a tie but uses the blocks
+    /// hashes to tie break. Source:
+    /// https://github.com/cbc-casper/cbc-casper-paper/blob/acc66e2ba4461a005262e2d5f434fd2e30ef0ff3/examples.tex#L596
+    pub fn mathematical_ghost<U: WeightUnit + std::cmp::PartialOrd>(
+        latest_messages_honest: &LatestMessagesHonest<Self>,
+        weights: &validator::Weights<D::ValidatorName, U>,
+    ) -> Result<Self, Error> {
+        Block::mathematical_ghost_with_tie_breaker(
+            latest_messages_honest,
+            weights,
+            &TieBreaker::LowestHash,
+        )
+    }
+
+    /// Same as `mathematical_ghost`, with the ties broken by `tie_breaker`.
+    pub fn mathematical_ghost_with_tie_breaker<U: WeightUnit + std::cmp::PartialOrd>(
+        latest_messages_honest: &LatestMessagesHonest<Self>,
+        weights: &validator::Weights<D::ValidatorName, U>,
+        tie_breaker: &TieBreaker,
+    ) -> Result<Self, Error> {
+        let scoring_function = |block: &Self| block.score(latest_messages_honest, weights);
+
+        let protocol_state = Self::find_all_accessible_blocks(latest_messages_honest);
+        let genesis_blocks = protocol_state
+            .iter()
+            .filter(|block| block.prev_block_as_ref().is_none())
+            .collect();
+
+        // The children of every block, built once rather than looked up in the whole protocol
+        // state at each step of the descent.
+        let mut children: HashMap<&Block<D>, HashSet<&Block<D>>> = HashMap::new();
+        for block in protocol_state.iter() {
+            if let Some(prevblock) = block.prev_block_as_ref() {
+                children.entry(prevblock).or_default().insert(block);
+            }
+        }
+
+        let ranking = Ranking::new(tie_breaker, weights, latest_messages_honest.iter());
+        ranking
+            .descend(Block::argmax(genesis_blocks, scoring_function), |block| {
+                children
+                    .get(block)
+                    .map(|block_children| Block::argmax(block_children.clone(), scoring_function))
+                    .unwrap_or_default()
+            })
+            .cloned()
+            .ok_or(Error)
+    }
+
+    /// This function reconstructs the blocks tree from `latest_messages_honest` and uses those to
+    /// return the block with highest score according to the definition 4.30 of the paper. Contrary
+    /// to the paper's definition, it does not return a set in case of a tie but uses the blocks
+    /// hashes to tie break. It is an optimized version of `mathematical_ghost`.
+    pub fn optimized_ghost<U: WeightUnit + std::cmp::PartialOrd>(
+        latest_messages_honest: &LatestMessagesHonest<Self>,
+        weights: &validator::Weights<D::ValidatorName, U>,
+    ) -> Result<Self, Error> {
+        Block::optimized_ghost_with_tie_breaker(
+            latest_messages_honest,
+            weights,
+            &TieBreaker::LowestHash,
+        )
+    }
+
+    /// Same as `optimized_ghost`, with the ties broken by `tie_breaker`.
+    pub fn optimized_ghost_with_tie_breaker<U: WeightUnit + std::cmp::PartialOrd>(
+        latest_messages_honest: &LatestMessagesHonest<Self>,
+        weights: &validator::Weights<D::ValidatorName, U>,
+        tie_breaker: &TieBreaker,
+    ) -> Result<Self, Error> {
+        let latest_blocks: Vec<_> = latest_messages_honest
+            .iter()
+            .map(|message| (message.sender().clone(), message.estimate().clone()))
+            .collect();
+        let ranking = Ranking::new(tie_breaker, weights, latest_messages_honest.iter());
+        Block::filtered_ghost(&latest_blocks, weights, &ranking, |_| true)
+    }
+
+    /// Core of `optimized_ghost` working on the latest block of each validator rather than on
+    /// their latest messages. Only the blocks with valid data for which `is_candidate` returns
+    /// true can be selected; a block whose children are all rejected is considered a leaf. Ties
+    /// are broken with `ranking`, see `Ranking::descend`.
+    pub(crate) fn filtered_ghost<U, F>(
+        latest_blocks: &[(D::ValidatorName, Block<D>)],
+        weights: &validator::Weights<D::ValidatorName, U>,
+        ranking: &Ranking<D, U>,
+        is_candidate: F,
+    ) -> Result<Self, Error>
+    where
+        U: WeightUnit + std::cmp::PartialOrd,
+        F: Fn(&Block<D>) -> bool,
+    {
+        let is_candidate = |block: &Block<D>| is_candidate(block) && block.validate_data().is_ok();
+        let mut protocol_state = HashSet::new();
+        for (_, latest_block) in latest_blocks.iter() {
+            let mut block = Some(latest_block);
+            while let Some(current) = block {
+                if !protocol_state.insert(current.clone()) {
+                    break;
+                }
+                block = current.prev_block_as_ref();
+            }
+        }
+        let genesis_blocks: HashSet<&Block<D>> = protocol_state
+            .iter()
+            .filter(|block| block.prev_block_as_ref().is_none() && is_candidate(block))
+            .collect();
+
+        // Generating a hashset for each block containing each validator having its latest block
+        // as a child of that block. This will let us compute the scores of each block easily
+        // since the score is the sum of the validators having a latest block child of that block.
+        let mut latest_messages_validators = HashMap::new();
+        for (sender, latest_block) in latest_blocks.iter() {
+            let mut iterator = latest_block.clone();
+            while let Some(prev_block) = iterator.prevblock() {
+                let entry = latest_messages_validators
+                    .entry(iterator)
+                    .or_insert_with(BTreeSet::new);
+                entry.insert(sender.clone());
+
+                iterator = prev_block;
+            }
+            let entry = latest_messages_validators
+                .entry(iterator)
+                .or_insert_with(BTreeSet::new);
+            entry.insert(sender.clone());
+        }
+
+        let scores: HashMap<&Block<D>, U> = latest_messages_validators
+            .iter()
+            .map(|(block, validators)| {
+                (
+                    protocol_state.get(block).unwrap(),
+                    weights.sum_weight_validators(&validators),
+                )
+            })
+            .collect();
+
+        let scoring_function = |block: &Self| *scores.get(&block).unwrap();
+
+        let mut children: HashMap<&Block<D>, Vec<&Block<D>>> = HashMap::new();
+        for block in protocol_state.iter() {
+            if let Some(prevblock) = block.prev_block_as_ref() {
+                children.entry(prevblock).or_default().push(block);
+            }
+        }
+
+        // Several genesis blocks are only seen if the messages were not validated against a
+        // `ChainSpec`, in which case the heaviest ones are explored.
+        ranking
+            .descend(Block::argmax(genesis_blocks, scoring_function), |block| {
+                let candidates: HashSet<_> = children
+                    .get(block)
+                    .into_iter()
+                    .flatten()
+                    .cloned()
+                    .filter(|child| is_candidate(child))
+                    .collect();
+                Block::argmax(candidates, scoring_function)
+            })
+            .cloned()
+            .ok_or(Error)
+    }
+
+    /// Returns the cliques of validators weighting more than `safety_oracle_threshold` that have
+    /// `block` in their chain and have seen each other having it. See
+    /// [`safety_oracle::clique_oracles`].
+    ///
+    /// [`safety_oracle::clique_oracles`]: ../safety_oracle/fn.clique_oracles.html
+    pub fn safety_oracles<U: WeightUnit>(
+        block: Block<D>,
+        latest_messages_honest: &LatestMessagesHonest<Self>,
+        equivocators: &BTreeSet<D::ValidatorName>,
+        safety_oracle_threshold: U,
+        weights: &validator::Weights<D::ValidatorName, U>,
+    ) -> HashSet<BTreeSet<D::ValidatorName>> {
+        safety_oracle::clique_oracles(
+            latest_messages_honest,
+            equivocators,
+            safety_oracle_threshold,
+            weights,
+            |message| block.is_member(&Block::from(message)),
+        )
+    }
+
+    /// Contrary to the paper's definition 4.24, this does not return Self for a genesis block but
+    /// None. Source:
+    /// https://github.com/cbc-casper/cbc-casper-paper/blob/acc66e2ba4461a005262e2d5f434fd2e30ef0ff3/examples.tex#L525
+    pub fn prevblock(&self) -> Option<Self> {
+        self.arc().prevblock.as_ref().cloned()
+    }
+
+    pub fn prev_block_as_ref(&self) -> Option<&Self> {
+        self.arc().prevblock.as_ref()
+    }
+
+    /// Returns the identifier of the prevblock without cloning it.
+    pub fn prevblock_id(&self) -> Option<Hash> {
+        self.prev_block_as_ref().map(Block::id)
+    }
+
+    /// Detaches the block from its ancestors.
+    pub fn detach(&self) -> DetachedBlock<D> {
+        DetachedBlock {
+            chain_id: self.chain_id(),
+            prevblock: self.prevblock_id(),
+            slot: self.slot(),
+            data: self.data(),
+        }
+    }
+
+    pub fn data(&self) -> D {
+        self.arc().data.clone()
+    }
+
+    /// Returns a reference to the data of the block without cloning it.
+    pub fn data_as_ref(&self) -> &D {
+        &self.arc().data
+    }
+
+    pub fn chain_id(&self) -> ChainId {
+        self.arc().chain_id
+    }
+
+    /// Returns the number of ancestors of the block, 0 for a genesis block.
+    pub fn height(&self) -> u64 {
+        self.arc().height
+    }
+
+    /// Returns the slot number or timestamp of the block, if it was created with one.
+    pub fn slot(&self) -> Option<u64> {
+        self.arc().slot
+    }
+
+    /// Returns the name of the validator that proposed the block.
+    pub fn proposer(&self) -> &D::ValidatorName {
+        self.arc().data.validator_name()
+    }
+
+    /// Returns the genesis block this block descends from, i.e. the last block of its ancestry.
+    pub fn genesis(&self) -> Self {
+        self.ancestor_at_height(0)
+            .expect("every block has a genesis ancestor")
+    }
+
+    /// Returns the ancestor of the block at `height`, or None if `height` is above the block's
+    /// own height. Follows the skip pointers when they do not overshoot, thus visiting
+    /// O(log n) blocks.
+    pub fn ancestor_at_height(&self, height: u64) -> Option<Self> {
+        if height > self.height() {
+            return None;
+        }
+
+        let mut block = self;
+        while block.height() > height {
+            block = match &block.arc().skip {
+                Some(skip_block) if follow_skip(block.height(), height) => skip_block,
+                _ => block
+                    .prev_block_as_ref()
+                    .expect("a block above height 0 has a prevblock"),
+            };
+        }
+        Some(block.clone())
+    }
+
+    /// Contrary to the paper's definition 4.25, this does not return Self for a genesis block but
+    /// None. Source:
+    /// https://github.com/cbc-casper/cbc-casper-paper/blob/acc66e2ba4461a005262e2d5f434fd2e30ef0ff3/examples.tex#L544
+    pub fn ncestor(&self, n: u32) -> Option<Self> {
+        self.height()
+            .checked_sub(u64::from(n))
+            .and_then(|height| self.ancestor_at_height(height))
+    }
+
+    /// Parses latest_messages to return a tuple containing:
+    /// * a HashMap mapping blocks to their children;
+    /// * a HashSet containing blocks with None as their prevblock (aka genesis blocks or finalized
+    /// blocks);
+    /// * a HashMap mapping blocks to their senders.
+    pub fn parse_blockchains(
+        latest_messages: &LatestMessagesHonest<Self>,
+    ) -> (
+        BlocksChildrenMap<D>,
+        GenesisBlocks<D>,
+        BlocksValidatorsMap<D>,
+    ) {
+        let latest_blocks: HashMap<Block<D>, D::ValidatorName> = latest_messages
+            .iter()
+            .map(|message| (Block::from(message), message.sender().clone()))
+            .collect();
+        // start at the tip of the blockchain
+        let mut visited_parents: HashMap<Block<D>, HashSet<Block<D>>> = latest_messages
+            .iter()
+            .map(|message| {
+                let parent = Block::from(message);
+                let children = HashSet::new();
+                (parent, children)
+            })
+            .collect();
+
+        let mut queue: VecDeque<Block<D>> = visited_parents.keys().cloned().collect();
+        let mut genesis: HashSet<Block<D>> = HashSet::new();
+        let mut was_empty = false;
+
+        // while there are still unvisited blocks
+        while let Some(child) = queue.pop_front() {
+            match (child.prevblock(), was_empty && genesis.is_empty()) {
+                // if the prevblock is set, update the visited_parents map
+                (Some(parent), false) => {
+                    if queue.is_empty() {
+                        was_empty = true
+                    }
+                    if visited_parents.contains_key(&parent) {
+                        // visited parent before, fork found, add new child and don't add parent to
+                        // queue (since it is already in the queue)
+                        if let Some(parents_children) = visited_parents.get_mut(&parent) {
+                            parents_children.insert(child);
+                        }
+                    } else {
+                        // didn't visit parent before, add it with known child, and push to queue
+                        let mut parents_children = HashSet::new();
+                        parents_children.insert(child);
+                        visited_parents.insert(parent.clone(), parents_children);
+                        queue.push_back(parent);
+                    }
+                }
+                // if not, update the genesis set, as a None prevblock indicates the genesis
+                _ => {
+                    genesis.insert(child);
+                }
+            };
+        }
+        (visited_parents, genesis, latest_blocks)
+    }
+
+    /// Returns the blocks of the chains of the latest messages, leaving out the blocks with
+    /// [invalid data] and their descendants.
+    ///
+    /// [invalid data]: ./trait.BlockData.html#method.validate
+    pub fn find_all_accessible_blocks(
+        latest_messages: &LatestMessagesHonest<Self>,
+    ) -> HashSet<Block<D>> {
+        Block::valid_ancestry(latest_messages.iter().map(Message::estimate))
+    }
+
+    /// Returns the given blocks and their ancestors, leaving out the blocks with invalid data and
+    /// their descendants.
+    pub(crate) fn valid_ancestry<'z, I>(blocks: I) -> HashSet<Block<D>>
+    where
+        I: Iterator<Item = &'z Block<D>>,
+        D: 'z,
+    {
+        let mut validity: HashMap<Block<D>, bool> = HashMap::new();
+
+        for tip in blocks {
+            // Climbs to the first block of known validity, then validates the blocks on the way
+            // back down.
+            let mut unknown = vec![];
+            let mut block = Some(tip);
+            while let Some(current) = block {
+                if validity.contains_key(current) {
+                    break;
+                }
+                unknown.push(current);
+                block = current.prev_block_as_ref();
+            }
+            let mut valid = match block {
+                Some(block) => validity[block],
+                None => true,
+            };
+            for block in unknown.into_iter().rev() {
+                valid = valid && block.validate_data().is_ok();
+                validity.insert(block.clone(), valid);
+            }
+        }
+
+        validity
+            .into_iter()
+            .filter(|(_, valid)| *valid)
+            .map(|(block, _)| block)
+            .collect()
+    }
+
+    /// Collects the validators that produced blocks for each side of a fork. The blocks of the
+    /// subtree are visited in post-order with an explicit stack, so that the validators of a block
+    /// are known when its parent is visited.
+    fn collect_validators(
+        block: &Block<D>,
+        visited: &HashMap<Block<D>, HashSet<Block<D>>>,
+        latest_blocks: &HashMap<Block<D>, D::ValidatorName>,
+        b_in_lms_validators: &mut HashMap<Block<D>, BTreeSet<D::ValidatorName>>,
+    ) -> BTreeSet<D::ValidatorName> {
+        let mut stack = vec![(block, false)];
+        while let Some((current, children_collected)) = stack.pop() {
+            let children = visited.get(current);
+            if children_collected {
+                let mut validators = BTreeSet::new();
+                // Collect this validator if this block is his proposed one from his latest
+                // message.
+                latest_blocks
+                    .get(current)
+                    .map(|validator| validators.insert(validator.clone()));
+                for child in children.into_iter().flatten() {
+                    if let Some(child_validators) = b_in_lms_validators.get(child) {
+                        validators.extend(child_validators.iter().cloned());
+                    }
+                }
+                b_in_lms_validators.insert(current.clone(), validators);
+            } else {
+                stack.push((current, true));
+                stack.extend(children.into_iter().flatten().map(|child| (child, false)));
+            }
+        }
+        b_in_lms_validators.get(block).cloned().unwrap_or_default()
+    }
+
+    /// Find heaviest block, descending from the given blocks to a leaf. Only the `valid` blocks
+    /// can be selected; a block whose children are all invalid is considered a leaf.
+    fn pick_heaviest<U: WeightUnit>(
+        blocks: &HashSet<Block<D>>,
+        visited: &HashMap<Block<D>, HashSet<Block<D>>>,
+        valid: &HashSet<Block<D>>,
+        weights: &validator::Weights<D::ValidatorName, U>,
+        latest_blocks: &HashMap<Block<D>, D::ValidatorName>,
+        b_in_lms_validators: &mut HashMap<Block<D>, BTreeSet<D::ValidatorName>>,
+        ranking: &Ranking<D, U>,
+    ) -> Option<(Option<Self>, U, HashSet<Self>)> {
+        let is_valid = |block: &Block<D>| valid.contains(block);
+        let mut blocks: HashSet<_> = blocks.iter().filter(|b| is_valid(b)).cloned().collect();
+        loop {
+            let init = Some((None, <U as Zero<U>>::ZERO, HashSet::new()));
+            let heaviest_child = match blocks.len() {
+                // only one choice, no need to compute anything
+                length if length == 1 => blocks.iter().next().cloned().and_then(|block| {
+                    visited
+                        .get(&block)
+                        .map(|children| (Some(block), <U as Zero<U>>::ZERO, children.clone()))
+                }),
+                // fork, need to find best block
+                length if length > 1 => blocks.iter().fold(init, |best, block| {
+                    let best_children =
+                        best.and_then(|best| visited.get(&block).map(|children| (best, children)));
+                    best_children.and_then(|((b_block, b_weight, b_children), children)| {
+                        let referred_validators = match b_in_lms_validators.get(block).cloned() {
+                            Some(rs) => rs,
+                            None => Self::collect_validators(
+                                block,
+                                visited,
+                                latest_blocks,
+                                b_in_lms_validators,
+                            ),
+                        };
+                        let weight = weights.sum_weight_validators(&referred_validators);
+                        let res = Some((Some(block.clone()), weight, children.clone()));
+                        let b_res = Some((b_block.clone(), b_weight, b_children));
+                        match weight.partial_cmp(&b_weight) {
+                            Some(Ordering::Greater) => res,
+                            Some(Ordering::Less) => b_res,
+                            Some(Ordering::Equal) | None => {
+                                let ord = b_block.as_ref().map(|b| ranking.cmp(b, block));
+                                match ord {
+                                    Some(Ordering::Greater) => res,
+                                    Some(Ordering::Less) => b_res,
+                                    Some(Ordering::Equal) => b_res,
+                                    None => None,
+                                }
+                            }
+                        }
+                    })
+                }),
+                _ => None,
+            };
+            match heaviest_child {
+                Some((b_block, b_weight, b_children)) => {
+                    let b_children: HashSet<_> =
+                        b_children.into_iter().filter(|b| is_valid(b)).collect();
+                    if b_children.is_empty() {
+                        return Some((b_block, b_weight, b_children));
+                    }
+                    blocks = b_children;
+                }
+                None => return None,
+            }
+        }
+    }
+
+    pub fn old_ghost<U: WeightUnit>(
+        latest_messages: &LatestMessagesHonest<Self>,
+        validators_weights: &validator::Weights<D::ValidatorName, U>,
+    ) -> Result<Self, Error> {
+        Block::old_ghost_with_tie_breaker(
+            latest_messages,
+            validators_weights,
+            &TieBreaker::LowestHash,
+        )
+    }
+
+    /// Same as `old_ghost`, with the ties broken by `tie_breaker`.
+    pub fn old_ghost_with_tie_breaker<U: WeightUnit>(
+        latest_messages: &LatestMessagesHonest<Self>,
+        validators_weights: &validator::Weights<D::ValidatorName, U>,
+        tie_breaker: &TieBreaker,
+    ) -> Result<Self, Error> {
+        let (visited, genesis, latest_blocks) = Self::parse_blockchains(latest_messages);
+        // The invalid blocks still count in the scores of their ancestors but cannot be selected.
+        let valid = Self::valid_ancestry(latest_messages.iter().map(Message::estimate));
+
+        let mut b_in_lms_validators = HashMap::<Block<D>, BTreeSet<D::ValidatorName>>::new();
+        let ranking = Ranking::new(tie_breaker, validators_weights, latest_messages.iter());
+
+        Block::pick_heaviest(
+            &genesis,
+            &visited,
+            &valid,
+            validators_weights,
+            &latest_blocks,
+            &mut b_in_lms_validators,
+            &ranking,
+        )
+        .and_then(|(opt_block, ..)| opt_block)
+        .ok_or(Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::{BTreeSet, HashSet};
+    use std::iter;
+    use std::iter::FromIterator;
+
+    use crate::justification::{Justification, LatestMessages, LatestMessagesHonest};
+    use crate::validator;
+
+    use crate::ValidatorNameBlockData;
+
+    #[test]
+    fn ncestor() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_1 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(3));
+        let block_2 = Block::new(Some(block_1.clone()), ValidatorNameBlockData::new(5));
+
+        assert_eq!(genesis.ncestor(0), Some(genesis.clone()));
+        assert_eq!(genesis.ncestor(1), None);
+
+        assert_eq!(block_2.ncestor(0), Some(block_2.clone()));
+        assert_eq!(block_2.ncestor(1), Some(block_1));
+        assert_eq!(block_2.ncestor(2), Some(genesis));
+        assert_eq!(block_2.ncestor(3), None);
+    }
+
+    #[test]
+    fn from_prevblock_message() {
+        let incomplete_block = Block::new(None, ValidatorNameBlockData::new(0));
+        let message = Message::new(
+            1,
+            Justification::empty(),
+            Block::from(ProtoBlock::new(None, ValidatorNameBlockData::new(2))),
+        );
+
+        assert_eq!(
+            Block::from_prevblock_message(Some(message.clone()), incomplete_block),
+            Block::new(Some(Block::from(&message)), ValidatorNameBlockData::new(0)),
+        );
+    }
+
+    #[test]
+    fn from_prevblock_message_none() {
+        let incomplete_block = Block::new(None, ValidatorNameBlockData::new(0));
+
+        assert_eq!(
+            Block::from_prevblock_message(None, incomplete_block),
+            Block::new(None, ValidatorNameBlockData::new(0)),
+        );
+    }
+
+    #[test]
+    fn from_prevblock_message_none_resets_ancestry() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_1 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(1));
+        let block_2 = Block::new(Some(block_1), ValidatorNameBlockData::new(2));
+
+        let block = Block::from_prevblock_message(None, block_2);
+        assert_eq!(block.prevblock(), None);
+        assert_eq!(block.height(), 0);
+        assert_eq!(block.genesis(), block);
+        assert_ne!(block.genesis(), genesis);
+        assert_eq!(block.ancestor_at_height(0), Some(block.clone()));
+        assert_eq!(block.ancestor_at_height(1), None);
+        assert_eq!(block, Block::new(None, ValidatorNameBlockData::new(2)));
+    }
+
+    #[test]
+    fn is_member_self() {
+        let block = Block::new(
+            Some(Block::from(&Message::new(
+                0,
+                Justification::empty(),
+                Block::new(None, ValidatorNameBlockData::new(1)),
+            ))),
+            ValidatorNameBlockData::new(2),
+        );
+        assert!(block.is_member(&block));
+        assert!(Block::new(None, ValidatorNameBlockData::new(0))
+            .is_member(&Block::new(None, ValidatorNameBlockData::new(0))));
+        assert!(!Block::new(None, ValidatorNameBlockData::new(0))
+            .is_member(&Block::new(None, ValidatorNameBlockData::new(1))));
+    }
+
+    #[test]
+    fn is_member_ancestor() {
+        let message = Message::new(
+            0,
+            Justification::empty(),
+            Block::new(None, ValidatorNameBlockData::new(1)),
+        );
+        let block_1 = Block::from(&message);
+        let block_2 = Block::new(Some(block_1.clone()), ValidatorNameBlockData::new(2));
+
+        assert!(block_1.is_member(&block_2));
+        assert!(Block::from(&message).is_member(&block_1));
+        assert!(Block::from(&message).is_member(&block_2));
+
+        assert!(!block_2.is_member(&block_1));
+        assert!(!block_2.is_member(&Block::from(&message)));
+    }
+
+    #[test]
+    fn score() {
+        let weights = validator::Weights::new(
+            vec![(0, 1.0), (1, 2.0), (2, 4.0), (3, 8.0), (4, 16.0)]
+                .into_iter()
+                .collect(),
+        );
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_1 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(1));
+        let block_2 = Block::new(Some(block_1.clone()), ValidatorNameBlockData::new(2));
+        let block_3 = Block::new(Some(block_1.clone()), ValidatorNameBlockData::new(3));
+        let block_4 = Block::new(Some(block_3.clone()), ValidatorNameBlockData::new(0));
+        let block_5 = Block::new(Some(block_4.clone()), ValidatorNameBlockData::new(4));
+
+        let mut justification = Justification::empty();
+        justification.insert(Message::new(0, justification.clone(), genesis.clone()));
+        justification.insert(Message::new(1, justification.clone(), block_1.clone()));
+        justification.insert(Message::new(2, justification.clone(), block_2.clone()));
+        justification.insert(Message::new(3, justification.clone(), block_3.clone()));
+        justification.insert(Message::new(0, justification.clone(), block_4.clone()));
+        justification.insert(Message::new(4, justification.clone(), block_5.clone()));
+        let latest_messages = LatestMessages::from(&justification);
+
+        float_eq!(
+            genesis.score(
+                &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+                &weights
+            ),
+            31.0
+        );
+        float_eq!(
+            block_1.score(
+                &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+                &weights
+            ),
+            31.0
+        );
+        float_eq!(
+            block_2.score(
+                &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+                &weights
+            ),
+            4.0
+        );
+        float_eq!(
+            block_3.score(
+                &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+                &weights
+            ),
+            25.0
+        );
+        float_eq!(
+            block_4.score(
+                &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+                &weights
+            ),
+            17.0
+        );
+        float_eq!(
+            block_5.score(
+                &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+                &weights
+            ),
+            16.0
+        );
+    }
+
+    #[test]
+    fn children() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_1 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(1));
+        let block_2 = Block::new(Some(block_1.clone()), ValidatorNameBlockData::new(2));
+        let block_3 = Block::new(Some(block_1.clone()), ValidatorNameBlockData::new(3));
+        let block_4 = Block::new(Some(block_3.clone()), ValidatorNameBlockData::new(0));
+
+        assert_eq!(
+            genesis.children(
+                &vec![&genesis, &block_1, &block_2, &block_3, &block_4]
+                    .into_iter()
+                    .collect::<HashSet<_>>()
+            ),
+            vec![&block_1].into_iter().collect::<HashSet<_>>()
+        );
+        assert_eq!(
+            block_1.children(
+                &vec![&genesis, &block_1, &block_2, &block_3, &block_4]
+                    .into_iter()
+                    .collect::<HashSet<_>>()
+            ),
+            vec![&block_2, &block_3].into_iter().collect::<HashSet<_>>()
+        );
+        assert_eq!(
+            block_2.children(
+                &vec![&genesis, &block_1, &block_2, &block_3, &block_4]
+                    .into_iter()
+                    .collect::<HashSet<_>>()
+            ),
+            vec![].into_iter().collect::<HashSet<_>>()
+        );
+        assert_eq!(
+            block_3.children(
+                &vec![&genesis, &block_1, &block_2, &block_3, &block_4]
+                    .into_iter()
+                    .collect::<HashSet<_>>()
+            ),
+            vec![&block_4].into_iter().collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn children_missing_children_are_not_included() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_1 = Block::new(Some(genesis), ValidatorNameBlockData::new(1));
+        let block_2 = Block::new(Some(block_1.clone()), ValidatorNameBlockData::new(2));
+        let block_3 = Block::new(Some(block_1.clone()), ValidatorNameBlockData::new(3));
+        let block_4 = Block::new(Some(block_3), ValidatorNameBlockData::new(0));
+
+        assert_eq!(
+            block_1.children(&vec![&block_2, &block_4].into_iter().collect::<HashSet<_>>()),
+            vec![&block_2].into_iter().collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn best_children_trivial() {
+        let weights = validator::Weights::new(
+            vec![(0, 3.0), (1, 2.0), (2, 4.0), (3, 8.0), (4, 1.0)]
+                .into_iter()
+                .collect(),
+        );
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_1 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(1));
+        let block_2 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(2));
+        let block_3 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(3));
+        let block_4 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(4));
+
+        let mut justification = Justification::empty();
+        justification.insert(Message::new(0, justification.clone(), genesis.clone()));
+        justification.insert(Message::new(1, justification.clone(), block_1.clone()));
+        justification.insert(Message::new(2, justification.clone(), block_2.clone()));
+        justification.insert(Message::new(3, justification.clone(), block_3.clone()));
+        justification.insert(Message::new(4, justification.clone(), block_4.clone()));
+        let latest_messages = LatestMessages::from(&justification);
+
+        assert_eq!(
+            genesis.best_children(
+                &vec![&block_1, &block_2, &block_3, &block_4]
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+                &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+                &weights,
+            ),
+            vec![&block_3].into_iter().collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn best_children_indirect() {
+        let weights = validator::Weights::new(
+            vec![(0, 1.0), (1, 2.0), (2, 4.0), (3, 8.0), (4, 10.0)]
+                .into_iter()
+                .collect(),
+        );
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_1 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(1));
+        let block_2 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(2));
+        let block_3 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(3));
+        let block_4 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(4));
+        let block_5 = Block::new(Some(block_4.clone()), ValidatorNameBlockData::new(0));
+        let block_6 = Block::new(Some(block_2.clone()), ValidatorNameBlockData::new(3));
+
+        let mut justification = Justification::empty();
+        justification.insert(Message::new(0, justification.clone(), genesis.clone()));
+        justification.insert(Message::new(1, justification.clone(), block_1.clone()));
+        justification.insert(Message::new(2, justification.clone(), block_2.clone()));
+        justification.insert(Message::new(3, justification.clone(), block_3.clone()));
+        justification.insert(Message::new(4, justification.clone(), block_4.clone()));
+        justification.insert(Message::new(0, justification.clone(), block_5));
+        justification.insert(Message::new(3, justification.clone(), block_6));
+        let latest_messages = LatestMessages::from(&justification);
+
+        assert_eq!(
+            genesis.best_children(
+                &vec![&block_1, &block_2, &block_3, &block_4]
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+                &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+                &weights,
+            ),
+            vec![&block_2].into_iter().collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn best_children_tie() {
+        let weights = validator::Weights::new(
+            vec![(0, 1.0), (1, 2.0), (2, 4.0), (3, 8.0), (4, 10.0)]
+                .into_iter()
+                .collect(),
+        );
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_1 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(1));
+        let block_2 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(2));
+        let block_3 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(3));
+        let block_4 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(4));
+        let block_5 = Block::new(Some(block_2.clone()), ValidatorNameBlockData::new(0));
+        let block_6 = Block::new(Some(block_1.clone()), ValidatorNameBlockData::new(3));
+
+        let mut justification = Justification::empty();
+        justification.insert(Message::new(0, justification.clone(), genesis.clone()));
+        justification.insert(Message::new(1, justification.clone(), block_1.clone()));
+        justification.insert(Message::new(2, justification.clone(), block_2.clone()));
+        justification.insert(Message::new(3, justification.clone(), block_3.clone()));
+        justification.insert(Message::new(4, justification.clone(), block_4.clone()));
+        justification.insert(Message::new(0, justification.clone(), block_5));
+        justification.insert(Message::new(3, justification.clone(), block_6));
+        let latest_messages = LatestMessages::from(&justification);
+
+        assert_eq!(
+            genesis.best_children(
+                &vec![&block_1, &block_2, &block_3, &block_4]
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+                &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+                &weights,
+            ),
+            vec![&block_1, &block_4].into_iter().collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn best_children_empty() {
+        let weights = validator::Weights::new(vec![(0, 1.0)].into_iter().collect());
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        assert_eq!(
+            genesis.best_children(
+                &HashSet::new(),
+                &LatestMessagesHonest::from_latest_messages(
+                    &LatestMessages::empty(),
+                    &BTreeSet::new()
+                ),
+                &weights,
+            ),
+            HashSet::new()
+        );
+    }
+
+    #[test]
+    fn ghost() {
+        let weights = validator::Weights::new(
+            vec![(0, 1.0), (1, 2.0), (2, 4.0), (3, 8.0), (4, 16.0)]
+                .into_iter()
+                .collect(),
+        );
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_1 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(1));
+        let block_2 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(2));
+        let block_3 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(3));
+        let block_4 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(4));
+
+        let block_5 = Block::new(Some(block_4.clone()), ValidatorNameBlockData::new(0));
+        let block_6 = Block::new(Some(block_2.clone()), ValidatorNameBlockData::new(3));
+
+        let block_7 = Block::new(Some(block_5.clone()), ValidatorNameBlockData::new(2));
+        let block_8 = Block::new(Some(block_6.clone()), ValidatorNameBlockData::new(4));
+
+        let mut justification = Justification::empty();
+        justification.insert(Message::new(0, justification.clone(), genesis));
+        justification.insert(Message::new(1, justification.clone(), block_1));
+        justification.insert(Message::new(2, justification.clone(), block_2));
+        justification.insert(Message::new(3, justification.clone(), block_3));
+        justification.insert(Message::new(4, justification.clone(), block_4));
+        justification.insert(Message::new(0, justification.clone(), block_5));
+        justification.insert(Message::new(3, justification.clone(), block_6));
+        justification.insert(Message::new(2, justification.clone(), block_7));
+        justification.insert(Message::new(4, justification.clone(), block_8.clone()));
+        let latest_messages = LatestMessages::from(&justification);
+
+        assert_eq!(
+            Block::mathematical_ghost(
+                &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+                &weights,
+            )
+            .unwrap(),
+            block_8,
+        );
+        assert_eq!(
+            Block::optimized_ghost(
+                &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+                &weights,
+            )
+            .unwrap(),
+            block_8,
+        );
+        assert_eq!(
+            Block::old_ghost(
+                &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+                &weights,
+            )
+            .unwrap(),
+            block_8,
+        );
+    }
+
+    #[test]
+    fn ghost_tie() {
+        let weights = validator::Weights::new(
+            vec![(0, 1.0), (1, 2.0), (2, 4.0), (3, 8.0), (4, 10.0), (5, 0.0)]
+                .into_iter()
+                .collect(),
+        );
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_1 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(1));
+        let block_2 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(2));
+        let block_3 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(3));
+        let block_4 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(4));
+        let block_5 = Block::new(Some(block_2.clone()), ValidatorNameBlockData::new(0));
+        let block_6 = Block::new(Some(block_1.clone()), ValidatorNameBlockData::new(3));
+        let block_7 = Block::new(Some(block_6.clone()), ValidatorNameBlockData::new(5));
+
+        let mut justification = Justification::empty();
+        justification.insert(Message::new(0, justification.clone(), genesis));
+        justification.insert(Message::new(1, justification.clone(), block_1));
+        justification.insert(Message::new(2, justification.clone(), block_2));
+        justification.insert(Message::new(3, justification.clone(), block_3));
+        justification.insert(Message::new(4, justification.clone(), block_4.clone()));
+        justification.insert(Message::new(0, justification.clone(), block_5));
+        justification.insert(Message::new(3, justification.clone(), block_6));
+        justification.insert(Message::new(5, justification.clone(), block_7.clone()));
+        let latest_messages = LatestMessages::from(&justification);
+        let latest_honest_messages =
+            &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new());
+
+        // block_4 and block_7 are tied, the hash based tie breaker chooses the lowest hash.
+        let lowest_hash_block = if block_4.id() < block_7.id() {
+            block_4
+        } else {
+            block_7
+        };
+        assert_eq!(
+            Block::mathematical_ghost(latest_honest_messages, &weights,).unwrap(),
+            lowest_hash_block,
+        );
+        assert_eq!(
+            Block::optimized_ghost(latest_honest_messages, &weights,).unwrap(),
+            lowest_hash_block,
+        );
+        assert_eq!(
+            Block::old_ghost(latest_honest_messages, &weights,).unwrap(),
+            lowest_hash_block,
+        );
+    }
+
+    #[test]
+    fn ghost_multiple_genesis() {
+        let weights =
+            validator::Weights::new(vec![(0, 1.0), (1, 2.0), (2, 4.0)].into_iter().collect());
+        let genesis_0 = Block::new(None, ValidatorNameBlockData::new(0));
+        let genesis_1 = Block::new(None, ValidatorNameBlockData::new(1));
+        let block_2 = Block::new(Some(genesis_0.clone()), ValidatorNameBlockData::new(2));
+
+        let mut latest_messages = LatestMessages::empty();
+        latest_messages.update(&Message::new(0, Justification::empty(), block_2.clone()));
+        latest_messages.update(&Message::new(1, Justification::empty(), genesis_1.clone()));
+        latest_messages.update(&Message::new(2, Justification::empty(), genesis_1.clone()));
+        let latest_messages_honest =
+            LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new());
+
+        // The chain starting at genesis_1 is heavier, it must be chosen whatever genesis block
+        // is found first.
+        assert_eq!(
+            Block::optimized_ghost(&latest_messages_honest, &weights).unwrap(),
+            genesis_1,
+        );
+        assert_eq!(
+            Block::mathematical_ghost(&latest_messages_honest, &weights).unwrap(),
+            genesis_1,
+        );
+    }
+
+    #[test]
+    fn chain_id_inherited() {
+        let chain_spec = ChainSpec::new(7, ValidatorNameBlockData::new(0));
+        let block_1 = Block::new(
+            Some(chain_spec.genesis().clone()),
+            ValidatorNameBlockData::new(1),
+        );
+        let block_2 = Block::new(Some(block_1.clone()), ValidatorNameBlockData::new(2));
+
+        assert_eq!(chain_spec.chain_id(), 7);
+        assert_eq!(block_2.chain_id(), 7);
+        assert_eq!(block_2.genesis(), *chain_spec.genesis());
+        assert_eq!(
+            Block::new(None, ValidatorNameBlockData::new(0)).chain_id(),
+            DEFAULT_CHAIN_ID
+        );
+        assert_eq!(
+            Block::from_prevblock_message(
+                Some(Message::new(1, Justification::empty(), block_1)),
+                Block::new(None, ValidatorNameBlockData::new(3)),
+            )
+            .chain_id(),
+            7
+        );
+    }
+
+    #[test]
+    fn chain_id_in_message_hash() {
+        let chain_spec_1 = ChainSpec::new(1, ValidatorNameBlockData::new(0));
+        let chain_spec_2 = ChainSpec::new(2, ValidatorNameBlockData::new(0));
+
+        let message_1 = Message::new(0, Justification::empty(), chain_spec_1.genesis().clone());
+        let message_2 = Message::new(0, Justification::empty(), chain_spec_2.genesis().clone());
+
+        assert_ne!(chain_spec_1.genesis(), chain_spec_2.genesis());
+        assert_ne!(message_1, message_2);
+        assert_ne!(message_1.id(), message_2.id());
+    }
+
+    #[test]
+    fn chain_spec_validate() {
+        let chain_spec = ChainSpec::new(1, ValidatorNameBlockData::new(0));
+        let genesis_message = Message::new(0, Justification::empty(), chain_spec.genesis().clone());
+
+        let mut justification = Justification::empty();
+        justification.insert(genesis_message.clone());
+        let message = Message::new(
+            1,
+            justification,
+            Block::new(
+                Some(chain_spec.genesis().clone()),
+                ValidatorNameBlockData::new(1),
+            ),
+        );
+
+        // Same chain identifier but another genesis block.
+        let forged_genesis = ChainSpec::new(1, ValidatorNameBlockData::new(1));
+        let forged_message = Message::new(
+            1,
+            Justification::empty(),
+            Block::new(
+                Some(forged_genesis.genesis().clone()),
+                ValidatorNameBlockData::new(1),
+            ),
+        );
+
+        // Valid block built upon a message of the default chain.
+        let replayed_message = Message::new(
+            0,
+            Justification::empty(),
+            Block::new(None, ValidatorNameBlockData::new(0)),
+        );
+        let mut justification = Justification::empty();
+        justification.insert(replayed_message.clone());
+        let message_with_replay = Message::new(1, justification, chain_spec.genesis().clone());
+
+        assert_eq!(chain_spec.validate(&genesis_message), Ok(()));
+        assert_eq!(chain_spec.validate(&message), Ok(()));
+        assert_eq!(
+            chain_spec.validate(&forged_message),
+            Err(ValidationError::UnknownGenesis)
+        );
+        assert_eq!(
+            chain_spec.validate(&replayed_message),
+            Err(ValidationError::WrongChainId(DEFAULT_CHAIN_ID))
+        );
+        assert_eq!(
+            chain_spec.validate(&message_with_replay),
+            Err(ValidationError::WrongChainId(DEFAULT_CHAIN_ID))
+        );
+    }
+
+    #[test]
+    fn chain_spec_update_state() {
+        let mut state = validator::State::new(
+            validator::Weights::new(vec![(0, 1.0), (1, 1.0)].into_iter().collect()),
+            0.0,
+            LatestMessages::empty(),
+            1.0,
+            BTreeSet::new(),
+        );
+        let chain_spec = ChainSpec::new(1, ValidatorNameBlockData::new(0));
+        let genesis_message = Message::new(0, Justification::empty(), chain_spec.genesis().clone());
+        let other_genesis_message = Message::new(
+            1,
+            Justification::empty(),
+            ChainSpec::new(1, ValidatorNameBlockData::new(1))
+                .genesis()
+                .clone(),
+        );
+
+        let rejected =
+            chain_spec.update_state(&mut state, &[&genesis_message, &other_genesis_message]);
+
+        assert_eq!(
+            rejected,
+            vec![(other_genesis_message, ValidationError::UnknownGenesis)]
+        );
+        assert!(state.latests_messages().contains_key(&0));
+        assert!(!state.latests_messages().contains_key(&1));
+
+        let message = Message::from_validator_state(1, &state).unwrap();
+        assert_eq!(message.estimate().genesis(), *chain_spec.genesis());
+        assert_eq!(chain_spec.validate(&message), Ok(()));
+    }
+
+    #[test]
+    fn height_and_ancestors() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let chain: Vec<_> = (1..=200).fold(vec![genesis], |mut chain, i| {
+            let block = Block::new(chain.last().cloned(), ValidatorNameBlockData::new(i));
+            chain.push(block);
+            chain
+        });
+        let tip = chain.last().unwrap();
+
+        assert_eq!(tip.height(), 200);
+        assert_eq!(tip.genesis(), chain[0]);
+        for (height, block) in chain.iter().enumerate() {
+            assert_eq!(block.height(), height as u64);
+            assert_eq!(tip.ancestor_at_height(height as u64).as_ref(), Some(block));
+            assert_eq!(tip.ncestor(200 - height as u32).as_ref(), Some(block));
+            assert!(block.is_member(tip));
+        }
+        assert_eq!(tip.ancestor_at_height(201), None);
+        assert_eq!(tip.ncestor(201), None);
+
+        let fork = Block::new(Some(chain[100].clone()), ValidatorNameBlockData::new(0));
+        assert!(chain[100].is_member(&fork));
+        assert!(!chain[101].is_member(&fork));
+        assert!(!fork.is_member(tip));
+    }
+
+    #[test]
+    fn deep_chain() {
+        let depth = 100_000;
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let mut tip = genesis.clone();
+        for i in 1..=depth {
+            tip = Block::new(Some(tip), ValidatorNameBlockData::new((i + 1) % 2));
+        }
+        let fork = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(1));
+
+        assert_eq!(tip.height(), u64::from(depth));
+        assert_eq!(tip.genesis(), genesis);
+        assert!(genesis.is_member(&tip));
+        assert!(!fork.is_member(&tip));
+        assert_eq!(tip.ncestor(depth), Some(genesis.clone()));
+
+        let mut justification = Justification::empty();
+        justification.insert(Message::new(0, Justification::empty(), tip.clone()));
+        justification.insert(Message::new(1, Justification::empty(), fork));
+        let latest_messages_honest = LatestMessagesHonest::from_latest_messages(
+            &LatestMessages::from(&justification),
+            &BTreeSet::new(),
+        );
+        let weights = validator::Weights::new(vec![(0, 2.0), (1, 1.0)].into_iter().collect());
+        assert_eq!(
+            Block::optimized_ghost(&latest_messages_honest, &weights).unwrap(),
+            tip
+        );
+        assert_eq!(
+            Block::old_ghost(&latest_messages_honest, &weights).unwrap(),
+            tip
+        );
+    }
+
+    #[test]
+    fn deep_chain_mathematical_ghost() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let mut tip = genesis;
+        for i in 1..=100_000 {
+            tip = Block::new(Some(tip), ValidatorNameBlockData::new((i + 1) % 2));
+        }
+
+        let mut justification = Justification::empty();
+        justification.insert(Message::new(0, Justification::empty(), tip.clone()));
+        let latest_messages_honest = LatestMessagesHonest::from_latest_messages(
+            &LatestMessages::from(&justification),
+            &BTreeSet::new(),
+        );
+        let weights = validator::Weights::new(vec![(0, 1.0)].into_iter().collect());
+        assert_eq!(
+            Block::mathematical_ghost(&latest_messages_honest, &weights).unwrap(),
+            tip
+        );
+    }
+
+    #[test]
+    fn slot() {
+        let genesis = Block::new_at_slot(None, 3, ValidatorNameBlockData::new(0)).unwrap();
+        let block_1 = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(1));
+        let block_2 =
+            Block::new_at_slot(Some(genesis.clone()), 4, ValidatorNameBlockData::new(2)).unwrap();
+
+        assert_eq!(genesis.slot(), Some(3));
+        assert_eq!(block_1.slot(), None);
+        assert_eq!(block_2.slot(), Some(4));
+        assert_eq!(*block_2.proposer(), 2);
+        assert_eq!(
+            Block::new_at_slot(Some(genesis.clone()), 3, ValidatorNameBlockData::new(3)),
+            Err(ValidationError::InvalidSlot(3))
+        );
+        // The slotless block_1 is skipped, the slot is checked against the genesis slot.
+        assert_eq!(
+            Block::new_at_slot(Some(block_1.clone()), 1, ValidatorNameBlockData::new(3)),
+            Err(ValidationError::InvalidSlot(1))
+        );
+        assert_eq!(
+            Block::new_at_slot(Some(block_1), 4, ValidatorNameBlockData::new(3))
+                .map(|block| block.height()),
+            Ok(2)
+        );
+        assert_ne!(
+            Block::new_at_slot(Some(genesis.clone()), 5, ValidatorNameBlockData::new(2)),
+            Ok(block_2)
+        );
+
+        let chain_spec = ChainSpec::new(0, ValidatorNameBlockData::new(0));
+        let prevblock_message = Message::new(
+            0,
+            Justification::empty(),
+            Block::new_at_slot(
+                Some(chain_spec.genesis().clone()),
+                5,
+                ValidatorNameBlockData::new(0),
+            )
+            .unwrap(),
+        );
+        let incomplete_block = Block::new_at_slot(None, 5, ValidatorNameBlockData::new(1)).unwrap();
+        let message = Message::new(
+            1,
+            Justification::empty(),
+            Block::from_prevblock_message(Some(prevblock_message), incomplete_block),
+        );
+        assert_eq!(
+            chain_spec.validate(&message),
+            Err(ValidationError::InvalidSlot(5))
+        );
+    }
+
+    #[test]
+    fn serialization_without_ancestors() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let chain = (1..1000).fold(vec![genesis.clone()], |mut chain, _| {
+            let block = Block::new(chain.last().cloned(), ValidatorNameBlockData::new(1));
+            chain.push(block);
+            chain
+        });
+        let tip = chain.last().unwrap();
+
+        assert_eq!(Id::serialize(tip).len(), Id::serialize(&chain[1]).len());
+        assert_eq!(tip.id(), ProtoBlock::id(&**tip.arc()));
+        assert_eq!(tip.prevblock_id(), Some(chain[998].id()));
+        assert_eq!(genesis.prevblock_id(), None);
+        assert_ne!(tip.id(), chain[998].id());
+    }
+
+    #[test]
+    fn detached_block() {
+        let chain_spec = ChainSpec::new(3, ValidatorNameBlockData::new(0));
+        let genesis = chain_spec.genesis().clone();
+        let block =
+            Block::new_at_slot(Some(genesis.clone()), 2, ValidatorNameBlockData::new(1)).unwrap();
+        let lookup = |id: &Hash| {
+            if *id == genesis.id() {
+                Some(genesis.clone())
+            } else {
+                None
+            }
+        };
+
+        assert_eq!(block.detach().id(), block.id());
+        assert_eq!(Id::serialize(&block.detach()), Id::serialize(&block));
+        assert_eq!(block.detach().attach(lookup), Ok(block.clone()));
+        assert_eq!(genesis.detach().attach(|_| None), Ok(genesis.clone()));
+
+        assert_eq!(
+            block.detach().attach(|_| None),
+            Err(ValidationError::UnknownPrevblock(genesis.id()))
+        );
+        assert_eq!(
+            DetachedBlock {
+                chain_id: 4,
+                ..block.detach()
+            }
+            .attach(lookup),
+            Err(ValidationError::WrongChainId(4))
+        );
+
+        let genesis_at_slot = Block::new_at_slot(None, 2, ValidatorNameBlockData::new(0)).unwrap();
+        assert_eq!(
+            DetachedBlock {
+                chain_id: 0,
+                ..block.detach()
+            }
+            .attach(|_| Some(genesis_at_slot.clone())),
+            Err(ValidationError::UnknownPrevblock(genesis.id()))
+        );
+        assert_eq!(
+            DetachedBlock {
+                chain_id: 0,
+                prevblock: Some(genesis_at_slot.id()),
+                ..block.detach()
+            }
+            .attach(|_| Some(genesis_at_slot.clone())),
+            Err(ValidationError::InvalidSlot(2))
+        );
+    }
+
+    #[test]
+    fn from_message() {
+        let block_1 = Block::new(
+            Some(Block::new(None, ValidatorNameBlockData::new(0))),
+            ValidatorNameBlockData::new(1),
+        );
+        let message = Message::new(0, Justification::empty(), block_1.clone());
+        let block_2 = Block::from(&message);
+
+        assert_eq!(block_1, block_2);
+    }
+
+    #[test]
+    fn parse_blockchains() {
+        let genesis = Message::new(
+            0,
+            Justification::empty(),
+            Block::new(None, ValidatorNameBlockData::new(0)),
+        );
+        let mut justification = Justification::empty();
+        justification.insert(genesis.clone());
+        let block_1 = Message::new(
+            1,
+            justification.clone(),
+            Block::new(
+                Some(genesis.estimate().clone()),
+                ValidatorNameBlockData::new(3),
+            ),
+        );
+        let block_2 = Message::new(
+            2,
+            justification,
+            Block::new(
+                Some(genesis.estimate().clone()),
+                ValidatorNameBlockData::new(4),
+            ),
+        );
+
+        let mut latest_messages = LatestMessages::empty();
+        latest_messages.update(&genesis);
+        latest_messages.update(&block_1);
+        latest_messages.update(&block_2);
+        let latest_messages_honest =
+            LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new());
+
+        let (children_map, genesis_set, senders_map) =
+            Block::parse_blockchains(&latest_messages_honest);
+
+        assert_eq!(
+            children_map,
+            vec![
+                (
+                    genesis.estimate().clone(),
+                    HashSet::from_iter(vec![
+                        block_1.estimate().clone(),
+                        block_2.estimate().clone()
+                    ])
+                ),
+                (block_1.estimate().clone(), HashSet::new()),
+                (block_2.estimate().clone(), HashSet::new()),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        assert_eq!(
+            genesis_set,
+            HashSet::from_iter(vec![genesis.estimate().clone()]),
+        );
+
+        assert_eq!(
+            senders_map,
+            vec![
+                (genesis.estimate().clone(), 0),
+                (block_1.estimate().clone(), 1),
+                (block_2.estimate().clone(), 2)
+            ]
+            .into_iter()
+            .collect(),
+        );
+    }
+
+    #[test]
+    fn safety_oracles() {
+        let nodes = 3;
+        let validators: Vec<u32> = (0..nodes).collect();
+
+        let validators_weights =
+            validator::Weights::new(validators.iter().cloned().zip(iter::repeat(1.0)).collect());
+
+        let mut state = validator::State::new(
+            validators_weights.clone(),
+            0.0,
+            LatestMessages::empty(),
+            1.0,
+            BTreeSet::new(),
+        );
+
+        // block dag
+        let proto_b0 = Block::from(ProtoBlock::new(None, ValidatorNameBlockData::new(0)));
+        let latest_messages = Justification::empty();
+        let m0 = Message::new(validators[0], latest_messages, proto_b0.clone());
+
+        let proto_b1 = Block::new(Some(proto_b0.clone()), ValidatorNameBlockData::new(0));
+        state.update(&[&m0]);
+        let m1 = Message::from_validator_state(validators[1], &state).unwrap();
+
+        let proto_b2 = Block::new(Some(proto_b1.clone()), ValidatorNameBlockData::new(0));
+        state.update(&[&m1]);
+        let m2 = Message::from_validator_state(validators[0], &state).unwrap();
+
+        // no clique yet, since validators[1] has not seen validators[0] seeing validators[1]
+        // having proto_b0 in the chain
+        assert_eq!(
+            Block::safety_oracles(
+                proto_b0.clone(),
+                &LatestMessagesHonest::from_latest_messages(
+                    state.latests_messages(),
+                    state.equivocators()
+                ),
+                state.equivocators(),
+                2.0,
+                &validators_weights
+            ),
+            HashSet::new()
+        );
+
+        state.update(&[&m2]);
+        let m3 = Message::from_validator_state(validators[1], &state).unwrap();
+
+        // clique, since both validators have seen each other having proto_b0 in the chain
+        assert_eq!(
+            Block::safety_oracles(
+                proto_b0.clone(),
+                &LatestMessagesHonest::from_latest_messages(
+                    state.latests_messages(),
+                    state.equivocators()
+                ),
+                state.equivocators(),
+                1.0,
+                &validators_weights
+            ),
+            HashSet::from_iter(vec![BTreeSet::from_iter(vec![
+                validators[0],
+                validators[1]
+            ])])
+        );
+
+        state.update(&[&m3]);
+        let m4 = Message::from_validator_state(validators[2], &state).unwrap();
+
+        state.update(&[&m4]);
+        let m5 = Message::from_validator_state(validators[1], &state).unwrap();
+
+        // no second clique yet, since validators[2] has not seen validators[1] seeing
+        // validators[2] having proto_b0.clone() in the chain
+        assert_eq!(
+            Block::safety_oracles(
+                proto_b0.clone(),
+                &LatestMessagesHonest::from_latest_messages(
+                    state.latests_messages(),
+                    state.equivocators()
+                ),
+                state.equivocators(),
+                1.0,
+                &validators_weights
+            ),
+            HashSet::from_iter(vec![BTreeSet::from_iter(vec![
+                validators[0],
+                validators[1]
+            ])])
+        );
+
+        state.update(&[&m5]);
+        let m6 = Message::from_validator_state(validators[2], &state).unwrap();
+
+        // have two cliques on proto_b0 now
+        assert_eq!(
+            Block::safety_oracles(
+                proto_b0.clone(),
+                &LatestMessagesHonest::from_latest_messages(
+                    state.latests_messages(),
+                    state.equivocators()
+                ),
+                state.equivocators(),
+                1.0,
+                &validators_weights
+            ),
+            HashSet::from_iter(vec![
+                BTreeSet::from_iter(vec![validators[0], validators[1]]),
+                BTreeSet::from_iter(vec![validators[1], validators[2]]),
+            ])
+        );
+
+        // also have two cliques on proto_b1
+        assert_eq!(
+            Block::safety_oracles(
+                proto_b1,
+                &LatestMessagesHonest::from_latest_messages(
+                    state.latests_messages(),
+                    state.equivocators()
+                ),
+                state.equivocators(),
+                1.0,
+                &validators_weights
+            ),
+            HashSet::from_iter(vec![
+                BTreeSet::from_iter(vec![validators[0], validators[1]]),
+                BTreeSet::from_iter(vec![validators[1], validators[2]]),
+            ])
+        );
+
+        // on proto_b2, only have clique {1, 2}
+        assert_eq!(
+            Block::safety_oracles(
+                proto_b2.clone(),
+                &LatestMessagesHonest::from_latest_messages(
+                    state.latests_messages(),
+                    state.equivocators()
+                ),
+                state.equivocators(),
+                1.0,
+                &validators_weights
+            ),
+            HashSet::from_iter(vec![BTreeSet::from_iter(vec![
+                validators[1],
+                validators[2]
+            ])])
+        );
+
+        state.update(&[&m6]);
+        let m7 = Message::from_validator_state(validators[0], &state).unwrap();
+
+        state.update(&[&m7]);
+        let m8 = Message::from_validator_state(validators[2], &state).unwrap();
+
+        state.update(&[&m8]);
+        let _ = Message::from_validator_state(validators[0], &state).unwrap();
+
+        // now entire network is clique
+        assert_eq!(
+            Block::safety_oracles(
+                proto_b0,
+                &LatestMessagesHonest::from_latest_messages(
+                    state.latests_messages(),
+                    state.equivocators()
+                ),
+                state.equivocators(),
+                1.0,
+                &validators_weights
+            ),
+            HashSet::from_iter(vec![BTreeSet::from_iter(vec![
+                validators[0],
+                validators[1],
+                validators[2],
+            ])])
+        );
+        assert_eq!(
+            Block::safety_oracles(
+                proto_b2,
+                &LatestMessagesHonest::from_latest_messages(
+                    state.latests_messages(),
+                    state.equivocators()
+                ),
+                state.equivocators(),
+                1.0,
+                &validators_weights
+            ),
+            HashSet::from_iter(vec![BTreeSet::from_iter(vec![
+                validators[0],
+                validators[1],
+                validators[2],
+            ])])
+        );
+    }
+
+    /// Block data counting the blocks of the chain: only data with the height of its block is
+    /// valid.
+    #[derive(Hash, Clone, PartialEq, Eq, Default, Debug, serde_derive::Serialize)]
+    struct Counter {
+        validator: u32,
+        count: u64,
+    }
+
+    impl BlockData for Counter {
+        type ValidatorName = u32;
+
+        fn validator_name(&self) -> &u32 {
+            &self.validator
+        }
+
+        fn validate(&self, parent: Option<&Block<Self>>) -> Result<(), DataError> {
+            if self.count == parent.map_or(0, |parent| parent.data().count + 1) {
+                Ok(())
+            } else {
+                Err(DataError("wrong count"))
+            }
+        }
+    }
+
+    fn counter(prevblock: Option<&Block<Counter>>, validator: u32, count: u64) -> Block<Counter> {
+        Block::new(prevblock.cloned(), Counter { validator, count })
+    }
+
+    #[test]
+    fn fork_choice_skips_invalid_blocks() {
+        let weights = validator::Weights::new(vec![(0, 1.0), (1, 3.0)].into_iter().collect());
+        let genesis = counter(None, 0, 0);
+        let valid = counter(Some(&genesis), 0, 1);
+        let invalid = counter(Some(&genesis), 1, 5);
+        // Valid on top of its parent, but descending from an invalid block.
+        let descendant = counter(Some(&invalid), 1, 6);
+
+        let mut latest_messages = LatestMessages::empty();
+        latest_messages.update(&Message::new(0, Justification::empty(), valid.clone()));
+        latest_messages.update(&Message::new(1, Justification::empty(), descendant));
+        let latest_messages_honest =
+            LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new());
+
+        assert_eq!(
+            Block::find_all_accessible_blocks(&latest_messages_honest),
+            HashSet::from_iter(vec![genesis.clone(), valid.clone()])
+        );
+        assert_eq!(
+            Block::optimized_ghost(&latest_messages_honest, &weights).unwrap(),
+            valid
+        );
+        assert_eq!(
+            Block::mathematical_ghost(&latest_messages_honest, &weights).unwrap(),
+            valid
+        );
+        assert_eq!(
+            Block::old_ghost(&latest_messages_honest, &weights).unwrap(),
+            valid
+        );
+        assert_eq!(
+            LongestChain
+                .head(&latest_messages_honest, &weights)
+                .unwrap(),
+            valid
+        );
+        assert_eq!(
+            ImdGhost.head(&latest_messages_honest, &weights).unwrap(),
+            valid
+        );
+
+        let mut latest_messages = LatestMessages::empty();
+        latest_messages.update(&Message::new(
+            0,
+            Justification::empty(),
+            counter(None, 0, 1),
+        ));
+        let latest_messages_honest =
+            LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new());
+        assert!(Block::find_all_accessible_blocks(&latest_messages_honest).is_empty());
+        assert!(Block::optimized_ghost(&latest_messages_honest, &weights).is_err());
+    }
+
+    #[test]
+    fn chain_spec_invalid_data() {
+        let chain_spec = ChainSpec::new(DEFAULT_CHAIN_ID, Counter::default());
+        let genesis = chain_spec.genesis();
+        let invalid = counter(Some(genesis), 1, 5);
+        let invalid_message = Message::new(1, Justification::empty(), invalid.clone());
+        let error = ValidationError::InvalidData(invalid.id(), DataError("wrong count"));
+        assert_eq!(chain_spec.validate(&invalid_message), Err(error));
+
+        // A message building on the invalid block is rejected as well.
+        let mut justification = Justification::empty();
+        justification.insert(invalid_message.clone());
+        let message = Message::new(0, justification, counter(Some(&invalid), 0, 6));
+        assert_eq!(chain_spec.validate(&message), Err(error));
+
+        // Even when no message of its justification has the invalid block as estimate.
+        let on_invalid = counter(Some(&invalid), 0, 6);
+        let indirect_message =
+            Message::new(0, Justification::empty(), counter(Some(&on_invalid), 0, 7));
+        assert_eq!(chain_spec.validate(&indirect_message), Err(error));
+
+        // The ancestors of a valid block are only checked once.
+        let valid_1 = counter(Some(genesis), 0, 1);
+        let valid_2 = counter(Some(&valid_1), 0, 2);
+        let valid_2_message = Message::new(0, Justification::empty(), valid_2.clone());
+        assert_eq!(chain_spec.validate(&valid_2_message), Ok(()));
+        assert!(chain_spec.validated.contains(&valid_1.id()));
+        assert!(chain_spec.clone().validated.contains(&valid_2.id()));
+        assert!(!chain_spec.validated.contains(&invalid.id()));
+
+        let mut state = validator::State::new(
+            validator::Weights::new(vec![(0, 1.0), (1, 1.0)].into_iter().collect()),
+            0.0,
+            LatestMessages::empty(),
+            1.0,
+            BTreeSet::new(),
+        );
+        let valid_message = Message::new(0, Justification::empty(), counter(Some(genesis), 0, 1));
+        assert_eq!(
+            chain_spec.update_state(&mut state, &[&valid_message, &invalid_message, &message]),
+            vec![(invalid_message, error), (message, error)]
+        );
+        assert!(!state.latests_messages().contains_key(&1));
+
+        assert_eq!(
+            invalid.detach().attach(|_| Some(genesis.clone())),
+            Err(error)
+        );
+    }
+}
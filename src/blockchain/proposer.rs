@@ -0,0 +1,430 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::blockchain::{Block, BlockData, Error, ForkChoice, Ghost, ValidationError};
+use crate::justification::{Justification, LatestMessagesHonest};
+use crate::message::Message;
+use crate::util::weight::WeightUnit;
+use crate::validator;
+
+/// Provides the data of the blocks created by a [`Proposer`], e.g. by picking transactions
+/// from a pool.
+///
+/// [`Proposer`]: ./struct.Proposer.html
+pub trait PayloadSource<D: BlockData> {
+    type Error: std::error::Error;
+
+    /// Returns the data of a new block to be built on top of `parent`, the block chosen by the
+    /// fork choice rule.
+    fn payload(&mut self, parent: &Block<D>) -> Result<D, Self::Error>;
+}
+
+#[derive(Debug)]
+pub enum ProposalError<E: std::error::Error> {
+    /// The validator state does not contain any honest message to build upon.
+    NoNewMessage,
+    /// The fork choice rule could not select a parent block.
+    ForkChoice(Error),
+    /// The payload source failed to provide the block data.
+    Payload(E),
+    /// The payload designates another validator than the proposer as the block's producer.
+    WrongProposer,
+    /// The proposed block is not valid on top of its parent.
+    InvalidBlock(ValidationError),
+    /// The proposer's previous message is not part of the latest honest messages, so the
+    /// proposal would break the self-justification rule.
+    NotSelfJustified,
+}
+
+impl<E: std::error::Error> std::fmt::Display for ProposalError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ProposalError::NoNewMessage => writeln!(f, "No message to build a block upon"),
+            ProposalError::ForkChoice(err) => std::fmt::Display::fmt(&err, f),
+            ProposalError::Payload(err) => std::fmt::Display::fmt(&err, f),
+            ProposalError::WrongProposer => {
+                writeln!(f, "Block data was not produced by the proposer")
+            }
+            ProposalError::InvalidBlock(err) => std::fmt::Display::fmt(&err, f),
+            ProposalError::NotSelfJustified => {
+                writeln!(
+                    f,
+                    "Proposal does not reference the proposer's previous message"
+                )
+            }
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for ProposalError<E> {}
+
+/// Creates ready to send block messages for a validator.
+///
+/// A proposal selects the parent block with a [`ForkChoice`] rule, GHOST by default, over the
+/// latest honest messages of a [`validator::State`], asks a [`PayloadSource`] for the data of the
+/// new block and justifies the resulting [`message`] with these latest honest messages. This
+/// replaces building blocks through [`Block::from_prevblock_message`] from the default block
+/// returned by the [`Estimator`] implementation.
+///
+/// [`ForkChoice`]: ./trait.ForkChoice.html
+/// [`validator::State`]: ../validator/struct.State.html
+/// [`PayloadSource`]: ./trait.PayloadSource.html
+/// [`message`]: ../message/struct.Message.html
+/// [`Block::from_prevblock_message`]: ./struct.Block.html#method.from_prevblock_message
+/// [`Estimator`]: ../estimator/trait.Estimator.html
+///
+/// # Example
+///
+/// ```
+/// use std::collections::BTreeSet;
+///
+/// use core_cbc_casper::blockchain::{Block, PayloadSource, Proposer};
+/// use core_cbc_casper::justification::{Justification, LatestMessages};
+/// use core_cbc_casper::message::Message;
+/// use core_cbc_casper::validator;
+/// use core_cbc_casper::ValidatorNameBlockData;
+///
+/// struct NameSource(u32);
+///
+/// impl PayloadSource<ValidatorNameBlockData<u32>> for NameSource {
+///     type Error = std::fmt::Error;
+///
+///     fn payload(
+///         &mut self,
+///         _parent: &Block<ValidatorNameBlockData<u32>>,
+///     ) -> Result<ValidatorNameBlockData<u32>, Self::Error> {
+///         Ok(ValidatorNameBlockData::new(self.0))
+///     }
+/// }
+///
+/// let genesis = Message::new(
+///     0,
+///     Justification::empty(),
+///     Block::new(None, ValidatorNameBlockData::new(0)),
+/// );
+/// let mut state = validator::State::new(
+///     validator::Weights::new(vec![(0, 1.0), (1, 1.0)].into_iter().collect()),
+///     0.0,
+///     LatestMessages::empty(),
+///     1.0,
+///     BTreeSet::new(),
+/// );
+/// state.update(&[&genesis]);
+///
+/// let message = Proposer::new(1).propose(&state, &mut NameSource(1)).unwrap();
+/// assert_eq!(*message.sender(), 1);
+/// assert_eq!(message.estimate().prevblock().as_ref(), Some(genesis.estimate()));
+/// assert_eq!(*message.estimate().proposer(), 1);
+/// assert!(message.justification().contains(&genesis));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Proposer<V: validator::ValidatorName, F: ForkChoice = Ghost> {
+    sender: V,
+    fork_choice: F,
+}
+
+impl<V: validator::ValidatorName> Proposer<V, Ghost> {
+    pub fn new(sender: V) -> Self {
+        Proposer::with_fork_choice(sender, Ghost)
+    }
+}
+
+impl<V: validator::ValidatorName, F: ForkChoice> Proposer<V, F> {
+    /// Creates a proposer selecting the parent of its blocks with the given [`ForkChoice`] rule.
+    ///
+    /// [`ForkChoice`]: ./trait.ForkChoice.html
+    pub fn with_fork_choice(sender: V, fork_choice: F) -> Self {
+        Proposer {
+            sender,
+            fork_choice,
+        }
+    }
+
+    pub fn sender(&self) -> &V {
+        &self.sender
+    }
+
+    pub fn fork_choice(&self) -> &F {
+        &self.fork_choice
+    }
+
+    /// Proposes a new block on top of the block selected by the fork choice rule.
+    pub fn propose<D, U, P>(
+        &self,
+        validator_state: &validator::State<Block<D>, U>,
+        payload_source: &mut P,
+    ) -> Result<Message<Block<D>>, ProposalError<P::Error>>
+    where
+        D: BlockData<ValidatorName = V>,
+        U: WeightUnit,
+        P: PayloadSource<D>,
+    {
+        self.build(validator_state, payload_source, |parent, data| {
+            Ok(Block::new(Some(parent), data))
+        })
+    }
+
+    /// Proposes a new block at `slot` on top of the block selected by the fork choice rule. The
+    /// slot must be strictly greater than the parent's slot.
+    pub fn propose_at_slot<D, U, P>(
+        &self,
+        validator_state: &validator::State<Block<D>, U>,
+        slot: u64,
+        payload_source: &mut P,
+    ) -> Result<Message<Block<D>>, ProposalError<P::Error>>
+    where
+        D: BlockData<ValidatorName = V>,
+        U: WeightUnit,
+        P: PayloadSource<D>,
+    {
+        self.build(validator_state, payload_source, |parent, data| {
+            Block::new_at_slot(Some(parent), slot, data)
+        })
+    }
+
+    fn build<D, U, P, N>(
+        &self,
+        validator_state: &validator::State<Block<D>, U>,
+        payload_source: &mut P,
+        new_block: N,
+    ) -> Result<Message<Block<D>>, ProposalError<P::Error>>
+    where
+        D: BlockData<ValidatorName = V>,
+        U: WeightUnit,
+        P: PayloadSource<D>,
+        N: FnOnce(Block<D>, D) -> Result<Block<D>, ValidationError>,
+    {
+        let latest_messages_honest = LatestMessagesHonest::from_latest_messages(
+            validator_state.latests_messages(),
+            validator_state.equivocators(),
+        );
+        if latest_messages_honest.is_empty() {
+            return Err(ProposalError::NoNewMessage);
+        }
+
+        let parent = self
+            .fork_choice
+            .head(
+                &latest_messages_honest,
+                validator_state.validators_weights(),
+            )
+            .map_err(ProposalError::ForkChoice)?;
+        let data = payload_source
+            .payload(&parent)
+            .map_err(ProposalError::Payload)?;
+        if *data.validator_name() != self.sender {
+            return Err(ProposalError::WrongProposer);
+        }
+        let block = new_block(parent, data)
+            .and_then(|block| block.validate_data().map(|()| block))
+            .map_err(ProposalError::InvalidBlock)?;
+
+        let message = Message::new(
+            self.sender.clone(),
+            Justification::from(latest_messages_honest),
+            block,
+        );
+        if message.is_self_justified() {
+            Ok(message)
+        } else {
+            Err(ProposalError::NotSelfJustified)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use crate::blockchain::LongestChain;
+    use crate::justification::LatestMessages;
+    use crate::ValidatorNameBlockData;
+
+    #[derive(Debug)]
+    struct EmptySource;
+
+    impl std::fmt::Display for EmptySource {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            writeln!(f, "No payload")
+        }
+    }
+
+    impl std::error::Error for EmptySource {}
+
+    struct Source(Vec<u32>);
+
+    impl PayloadSource<ValidatorNameBlockData<u32>> for Source {
+        type Error = EmptySource;
+
+        fn payload(
+            &mut self,
+            _parent: &Block<ValidatorNameBlockData<u32>>,
+        ) -> Result<ValidatorNameBlockData<u32>, Self::Error> {
+            self.0
+                .pop()
+                .map(ValidatorNameBlockData::new)
+                .ok_or(EmptySource)
+        }
+    }
+
+    fn state() -> validator::State<Block<ValidatorNameBlockData<u32>>, f64> {
+        validator::State::new(
+            validator::Weights::new(vec![(0, 1.0), (1, 1.0), (2, 1.0)].into_iter().collect()),
+            0.0,
+            LatestMessages::empty(),
+            1.0,
+            BTreeSet::new(),
+        )
+    }
+
+    #[test]
+    fn propose_on_ghost() {
+        let mut state = state();
+        let genesis = Message::new(
+            0,
+            Justification::empty(),
+            Block::new(None, ValidatorNameBlockData::new(0)),
+        );
+        state.update(&[&genesis]);
+        let mut justification = Justification::empty();
+        justification.insert(genesis.clone());
+        let block_1 = Message::new(
+            1,
+            justification.clone(),
+            Block::new(
+                Some(genesis.estimate().clone()),
+                ValidatorNameBlockData::new(1),
+            ),
+        );
+        let block_2 = Message::new(
+            2,
+            justification,
+            Block::new(
+                Some(genesis.estimate().clone()),
+                ValidatorNameBlockData::new(2),
+            ),
+        );
+        state.update(&[&block_1, &block_2]);
+
+        let expected_parent = Block::optimized_ghost(
+            &LatestMessagesHonest::from_latest_messages(
+                state.latests_messages(),
+                state.equivocators(),
+            ),
+            state.validators_weights(),
+        )
+        .unwrap();
+        let message = Proposer::new(0)
+            .propose(&state, &mut Source(vec![0]))
+            .unwrap();
+
+        assert_eq!(*message.sender(), 0);
+        assert_eq!(message.estimate().prevblock(), Some(expected_parent));
+        assert_eq!(*message.estimate().proposer(), 0);
+        assert_eq!(message.justification().len(), 3);
+    }
+
+    #[test]
+    fn propose_with_fork_choice() {
+        let mut state = state();
+        let genesis = Message::new(
+            0,
+            Justification::empty(),
+            Block::new(None, ValidatorNameBlockData::new(0)),
+        );
+        state.update(&[&genesis]);
+        let mut justification = Justification::empty();
+        justification.insert(genesis.clone());
+        let block_1 = Message::new(
+            1,
+            justification.clone(),
+            Block::new(
+                Some(genesis.estimate().clone()),
+                ValidatorNameBlockData::new(1),
+            ),
+        );
+        let block_2 = Message::new(
+            2,
+            justification,
+            Block::new(
+                Some(genesis.estimate().clone()),
+                ValidatorNameBlockData::new(2),
+            ),
+        );
+        let mut justification = Justification::empty();
+        justification.insert(block_1.clone());
+        let block_3 = Message::new(
+            1,
+            justification,
+            Block::new(
+                Some(block_1.estimate().clone()),
+                ValidatorNameBlockData::new(1),
+            ),
+        );
+        state.update(&[&block_1, &block_2, &block_3]);
+
+        let proposer = Proposer::with_fork_choice(0, LongestChain);
+        assert_eq!(*proposer.fork_choice(), LongestChain);
+        let message = proposer.propose(&state, &mut Source(vec![0])).unwrap();
+
+        assert_eq!(
+            message.estimate().prevblock().as_ref(),
+            Some(block_3.estimate())
+        );
+    }
+
+    #[test]
+    fn propose_errors() {
+        let mut state = state();
+        match Proposer::new(0).propose(&state, &mut Source(vec![0])) {
+            Err(ProposalError::NoNewMessage) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        let genesis = Message::new(
+            0,
+            Justification::empty(),
+            Block::new_at_slot(None, 1, ValidatorNameBlockData::new(0)).unwrap(),
+        );
+        state.update(&[&genesis]);
+        match Proposer::new(0).propose(&state, &mut Source(vec![])) {
+            Err(ProposalError::Payload(EmptySource)) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match Proposer::new(0).propose(&state, &mut Source(vec![1])) {
+            Err(ProposalError::WrongProposer) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match Proposer::new(0).propose_at_slot(&state, 1, &mut Source(vec![0])) {
+            Err(ProposalError::InvalidBlock(ValidationError::InvalidSlot(1))) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert_eq!(
+            Proposer::new(0)
+                .propose_at_slot(&state, 2, &mut Source(vec![0]))
+                .unwrap()
+                .estimate()
+                .slot(),
+            Some(2)
+        );
+    }
+}
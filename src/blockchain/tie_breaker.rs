@@ -0,0 +1,642 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use crate::blockchain::{Block, BlockData};
+use crate::estimator::Estimator;
+use crate::message::Message;
+use crate::util::hash::Hash;
+use crate::util::id::Id;
+use crate::util::weight::WeightUnit;
+use crate::validator;
+
+/// Policy choosing between blocks the fork choice rules consider equally good.
+///
+/// Breaking ties with the lowest block hash lets a proposer grind its block until its hash wins
+/// the ties against the competing blocks. The other policies only depend on data every node
+/// shares, so that all the nodes having received the same messages select the same head.
+///
+/// With `LowestHash`, [`Ghost`] and [`MathematicalGhost`] explore every tied branch and select
+/// the leaf with the lowest hash among the leaves reached, as they always did. The other policies
+/// instead break their ties at each fork, between the heaviest children of a block, since they
+/// compare competing blocks rather than the leaves of competing branches. [`OldGhost`] and
+/// [`ImdGhost`] break the ties at each fork with every policy, and [`LongestChain`] between the
+/// highest blocks.
+///
+/// [`Ghost`]: ./struct.Ghost.html
+/// [`MathematicalGhost`]: ./struct.MathematicalGhost.html
+/// [`OldGhost`]: ./struct.OldGhost.html
+/// [`ImdGhost`]: ./struct.ImdGhost.html
+/// [`LongestChain`]: ./struct.LongestChain.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TieBreaker {
+    /// Selects the block with the lowest hash.
+    LowestHash,
+    /// Selects the block with the lowest hash of its own hash and the given value, which should
+    /// come from a public randomness beacon revealed after the blocks were proposed.
+    Beacon(Hash),
+    /// Selects the block seen the earliest after its parent block. Only the messages on a block
+    /// that depend on a message on its parent block are considered, so that a block cannot be
+    /// made to look early with a message skipping the history, e.g. with an empty justification.
+    /// Among these, the block whose first message has the shortest chain of justifications is
+    /// selected. Blocks without such a message come last, remaining ties are broken with the
+    /// lowest hash.
+    EarliestSeen,
+    /// Selects the block whose proposer has the highest weight. Remaining ties are broken with the
+    /// lowest hash.
+    ProposerWeight,
+}
+
+/// A [`TieBreaker`] along with the data it needs to compare the blocks of a fork choice.
+///
+/// [`TieBreaker`]: ./enum.TieBreaker.html
+pub(crate) struct Ranking<'z, D: BlockData, U: WeightUnit> {
+    tie_breaker: &'z TieBreaker,
+    weights: &'z validator::Weights<D::ValidatorName, U>,
+    seen: HashMap<Block<D>, u64>,
+}
+
+impl<'z, D: BlockData, U: WeightUnit> Ranking<'z, D, U> {
+    /// Prepares the comparison of the blocks reachable from `latest_messages`.
+    pub(crate) fn new<'m, I>(
+        tie_breaker: &'z TieBreaker,
+        weights: &'z validator::Weights<D::ValidatorName, U>,
+        latest_messages: I,
+    ) -> Self
+    where
+        I: Iterator<Item = &'m Message<Block<D>>>,
+        D: 'm,
+    {
+        Ranking::with_estimates(tie_breaker, weights, latest_messages, Some)
+    }
+
+    /// Prepares the comparison of the blocks reachable from `latest_messages`, whose estimates
+    /// hold the block returned by `block`, if any, e.g. the block of a shard.
+    pub(crate) fn with_estimates<'m, E, I, B>(
+        tie_breaker: &'z TieBreaker,
+        weights: &'z validator::Weights<D::ValidatorName, U>,
+        latest_messages: I,
+        block: B,
+    ) -> Self
+    where
+        E: Estimator + 'm,
+        I: Iterator<Item = &'m Message<E>>,
+        B: Fn(&'m E) -> Option<&'m Block<D>>,
+        D: 'm,
+    {
+        let seen = match tie_breaker {
+            TieBreaker::EarliestSeen => seen_times(latest_messages, block),
+            _ => HashMap::new(),
+        };
+        Ranking {
+            tie_breaker,
+            weights,
+            seen,
+        }
+    }
+
+    /// Orders the blocks from the preferred one to the least preferred one.
+    pub(crate) fn cmp(&self, left: &Block<D>, right: &Block<D>) -> Ordering {
+        let ordering = match self.tie_breaker {
+            TieBreaker::LowestHash => Ordering::Equal,
+            TieBreaker::Beacon(beacon) => mix(left, beacon).cmp(&mix(right, beacon)),
+            TieBreaker::EarliestSeen => match (self.seen.get(left), self.seen.get(right)) {
+                (Some(left), Some(right)) => left.cmp(right),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+            TieBreaker::ProposerWeight => {
+                let weight = |block: &Block<D>| self.weights.weight(block.proposer()).ok();
+                match (weight(left), weight(right)) {
+                    (Some(left), Some(right)) => {
+                        right.partial_cmp(&left).unwrap_or(Ordering::Equal)
+                    }
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                }
+            }
+        };
+        ordering.then_with(|| left.id().cmp(&right.id()))
+    }
+
+    /// Descends from the `roots` to a leaf, `best_children` returning the heaviest children of a
+    /// block, none for a leaf. With [`TieBreaker::LowestHash`], every tied block is explored and
+    /// the lowest leaf is returned; the other tie breakers choose between the tied blocks at each
+    /// fork.
+    ///
+    /// [`TieBreaker::LowestHash`]: ./enum.TieBreaker.html#variant.LowestHash
+    pub(crate) fn descend<'b, F>(
+        &self,
+        roots: HashSet<&'b Block<D>>,
+        best_children: F,
+    ) -> Option<&'b Block<D>>
+    where
+        F: Fn(&'b Block<D>) -> HashSet<&'b Block<D>>,
+    {
+        if let TieBreaker::LowestHash = self.tie_breaker {
+            let mut leaves = Vec::new();
+            let mut stack: Vec<_> = roots.into_iter().collect();
+            while let Some(block) = stack.pop() {
+                let children = best_children(block);
+                if children.is_empty() {
+                    leaves.push(block);
+                } else {
+                    stack.extend(children);
+                }
+            }
+            self.best(leaves)
+        } else {
+            let mut block = self.best(roots)?;
+            loop {
+                match self.best(best_children(block)) {
+                    Some(child) => block = child,
+                    None => return Some(block),
+                }
+            }
+        }
+    }
+
+    /// Returns the preferred block.
+    pub(crate) fn best<'b, I>(&self, blocks: I) -> Option<&'b Block<D>>
+    where
+        I: IntoIterator<Item = &'b Block<D>>,
+        D: 'b,
+    {
+        blocks
+            .into_iter()
+            .min_by(|left, right| self.cmp(left, right))
+    }
+}
+
+/// Hashes the block's hash together with the beacon value.
+fn mix<D: BlockData>(block: &Block<D>, beacon: &Hash) -> Hash {
+    let mut bytes = block.id().as_bytes().to_vec();
+    bytes.extend_from_slice(beacon.as_bytes());
+    <Block<D> as Id>::hash(&bytes)
+}
+
+/// Returns for each block the earliest logical time at which it was the `block` of the estimate
+/// of a message depending on a message on its parent block, for the blocks having such a message.
+/// Any message on a genesis block counts. The logical time of a message is the length of its
+/// longest chain of justifications, which is the same for every node.
+fn seen_times<'m, D, E, I, B>(latest_messages: I, block: B) -> HashMap<Block<D>, u64>
+where
+    D: BlockData + 'm,
+    E: Estimator + 'm,
+    I: Iterator<Item = &'m Message<E>>,
+    B: Fn(&'m E) -> Option<&'m Block<D>>,
+{
+    // Iterative post-order traversal of the justifications, as they can be arbitrarily deep.
+    let mut times = HashMap::new();
+    let mut messages_on: HashMap<&Block<D>, Vec<&Message<E>>> = HashMap::new();
+    let mut stack: Vec<_> = latest_messages.map(|message| (message, false)).collect();
+    while let Some((message, justified)) = stack.pop() {
+        if times.contains_key(&message.id()) {
+            continue;
+        }
+        if justified {
+            let time = message
+                .justification()
+                .iter()
+                .filter_map(|justification| times.get(&justification.id()))
+                .map(|time| time + 1)
+                .max()
+                .unwrap_or(0);
+            times.insert(message.id(), time);
+            if let Some(block) = block(message.estimate()) {
+                messages_on.entry(block).or_default().push(message);
+            }
+        } else {
+            stack.push((message, true));
+            stack.extend(
+                message
+                    .justification()
+                    .iter()
+                    .filter(|justification| !times.contains_key(&justification.id()))
+                    .map(|justification| (justification, false)),
+            );
+        }
+    }
+
+    // A message depends on a message on a block if and only if it depends on one of the first
+    // messages on that block, those not depending on another message on the block. A message is
+    // later than its dependencies, so sorting the messages by logical time finds the first ones
+    // and then the earliest message on each block depending on its parent block.
+    for messages in messages_on.values_mut() {
+        messages.sort_by_key(|message| times[&message.id()]);
+    }
+    let first_messages_on: HashMap<&Block<D>, Vec<&Message<E>>> = messages_on
+        .iter()
+        .map(|(&block, messages)| {
+            let mut first_messages: Vec<&Message<E>> = vec![];
+            for &message in messages {
+                if !first_messages.iter().any(|first| message.depends(first)) {
+                    first_messages.push(message);
+                }
+            }
+            (block, first_messages)
+        })
+        .collect();
+
+    messages_on
+        .iter()
+        .filter_map(|(&block, messages)| {
+            let parent_messages = block.prev_block_as_ref().map(|parent| {
+                first_messages_on
+                    .get(parent)
+                    .map(Vec::as_slice)
+                    .unwrap_or(&[])
+            });
+            messages
+                .iter()
+                .find(|message| match parent_messages {
+                    Some(parent_messages) => parent_messages
+                        .iter()
+                        .any(|parent_message| message.depends(parent_message)),
+                    None => true,
+                })
+                .map(|message| (block.clone(), times[&message.id()]))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::{BTreeSet, HashSet};
+
+    use crate::blockchain::{
+        ForkChoice, Ghost, ImdGhost, LongestChain, MathematicalGhost, OldGhost,
+    };
+    use crate::justification::{Justification, LatestMessages, LatestMessagesHonest};
+    use crate::tests_common::utils::Lcg;
+    use crate::ValidatorNameBlockData;
+
+    type TestBlock = Block<ValidatorNameBlockData<u32>>;
+
+    fn weights() -> validator::Weights<u32, f64> {
+        validator::Weights::new(
+            vec![(1, 1.0), (2, 1.0), (3, 1.0), (4, 2.0), (5, 3.0)]
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    /// Two competing blocks on top of the genesis block, proposed by `early` and `late` and
+    /// supported by validators 1 and 2 with the same weight. Both messages on these blocks are
+    /// justified by the message on the genesis block, the one on the block of `late` through
+    /// another message, so it is seen later.
+    fn fork(early: u32, late: u32) -> (Vec<Message<TestBlock>>, TestBlock, TestBlock) {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_early = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(early));
+        let block_late = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(late));
+        let genesis_message = Message::new(3, Justification::empty(), genesis.clone());
+        let mut justification = Justification::empty();
+        justification.insert(genesis_message.clone());
+        let relay_message = Message::new(4, justification.clone(), genesis);
+        let mut relay_justification = Justification::empty();
+        relay_justification.insert(relay_message);
+        let messages = vec![
+            Message::new(1, justification, block_early.clone()),
+            Message::new(2, relay_justification, block_late.clone()),
+            genesis_message,
+        ];
+        (messages, block_early, block_late)
+    }
+
+    /// Two competing branches on top of the genesis block: `first` followed by `second`, and
+    /// `other` alone. Validators 1 and 2 support the tips of the branches with the same weight.
+    fn two_level_fork(
+        first: u32,
+        second: u32,
+        other: u32,
+    ) -> (Vec<Message<TestBlock>>, TestBlock, TestBlock) {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_first = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(first));
+        let block_second = Block::new(Some(block_first), ValidatorNameBlockData::new(second));
+        let block_other = Block::new(Some(genesis), ValidatorNameBlockData::new(other));
+        let messages = vec![
+            Message::new(1, Justification::empty(), block_second.clone()),
+            Message::new(2, Justification::empty(), block_other.clone()),
+        ];
+        (messages, block_second, block_other)
+    }
+
+    /// Returns the head selected with `tie_breaker` by every fork choice rule on nodes having
+    /// received the messages in different orders, after checking they all agree.
+    fn head(messages: &[Message<TestBlock>], tie_breaker: &TieBreaker) -> TestBlock {
+        agreed_head(messages, tie_breaker, true)
+    }
+
+    /// Same as `head`, `LongestChain` being left out unless `longest_chain` is set.
+    fn agreed_head(
+        messages: &[Message<TestBlock>],
+        tie_breaker: &TieBreaker,
+        longest_chain: bool,
+    ) -> TestBlock {
+        let weights = weights();
+        let mut heads = HashSet::new();
+        for rotation in 0..messages.len() {
+            for reversed in &[false, true] {
+                let mut order: Vec<_> = messages.iter().collect();
+                order.rotate_left(rotation);
+                if *reversed {
+                    order.reverse();
+                }
+                let mut latest_messages = LatestMessages::empty();
+                for message in order {
+                    latest_messages.update(message);
+                }
+                let latest_messages_honest =
+                    LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new());
+
+                heads.insert(
+                    Ghost
+                        .head_with_tie_breaker(&latest_messages_honest, &weights, tie_breaker)
+                        .unwrap(),
+                );
+                heads.insert(
+                    MathematicalGhost
+                        .head_with_tie_breaker(&latest_messages_honest, &weights, tie_breaker)
+                        .unwrap(),
+                );
+                heads.insert(
+                    OldGhost
+                        .head_with_tie_breaker(&latest_messages_honest, &weights, tie_breaker)
+                        .unwrap(),
+                );
+                if longest_chain {
+                    heads.insert(
+                        LongestChain
+                            .head_with_tie_breaker(&latest_messages_honest, &weights, tie_breaker)
+                            .unwrap(),
+                    );
+                }
+                heads.insert(
+                    ImdGhost
+                        .head_with_tie_breaker(&latest_messages_honest, &weights, tie_breaker)
+                        .unwrap(),
+                );
+                heads.insert(
+                    Ghost
+                        .with_tie_breaker(tie_breaker.clone())
+                        .head(&latest_messages_honest, &weights)
+                        .unwrap(),
+                );
+            }
+        }
+        assert_eq!(heads.len(), 1, "nodes selected different heads");
+        heads.into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn lowest_hash() {
+        let (messages, block_early, block_late) = fork(1, 2);
+        let lowest = if block_early.id() < block_late.id() {
+            block_early
+        } else {
+            block_late
+        };
+
+        assert_eq!(head(&messages, &TieBreaker::LowestHash), lowest);
+    }
+
+    #[test]
+    fn beacon() {
+        let (messages, block_early, block_late) = fork(1, 2);
+        let heads: HashSet<_> = (0..16)
+            .map(|beacon| head(&messages, &TieBreaker::Beacon(Hash::repeat_byte(beacon))))
+            .collect();
+
+        // The beacon value, not the blocks hashes alone, decides which block wins.
+        assert_eq!(heads, [block_early, block_late].iter().cloned().collect());
+    }
+
+    #[test]
+    fn earliest_seen() {
+        let (messages, block_early, _) = fork(1, 2);
+        assert_eq!(head(&messages, &TieBreaker::EarliestSeen), block_early);
+
+        let (messages, block_early, _) = fork(2, 1);
+        assert_eq!(head(&messages, &TieBreaker::EarliestSeen), block_early);
+    }
+
+    #[test]
+    fn earliest_seen_requires_parent() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block_honest = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(1));
+        let block_rushed = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(2));
+        let genesis_message = Message::new(3, Justification::empty(), genesis);
+        let mut justification = Justification::empty();
+        justification.insert(genesis_message.clone());
+        // The message on block_rushed has the shortest possible chain of justifications, but
+        // does not show that its block was proposed after the genesis block was seen.
+        let messages = vec![
+            Message::new(1, justification, block_honest.clone()),
+            Message::new(2, Justification::empty(), block_rushed.clone()),
+            genesis_message,
+        ];
+
+        let seen = seen_times(messages.iter(), Some);
+        assert_eq!(seen.get(&block_honest), Some(&1));
+        assert_eq!(seen.get(&block_rushed), None);
+        assert_eq!(head(&messages, &TieBreaker::EarliestSeen), block_honest);
+    }
+
+    #[test]
+    fn proposer_weight() {
+        let (messages, _, block_heavy) = fork(1, 4);
+        assert_eq!(head(&messages, &TieBreaker::ProposerWeight), block_heavy);
+
+        let (messages, block_heavy, _) = fork(4, 1);
+        assert_eq!(head(&messages, &TieBreaker::ProposerWeight), block_heavy);
+    }
+
+    /// The ghost implementation that predates the tie breakers: the best leaves of every tied
+    /// branch are gathered and the one with the lowest hash is selected.
+    fn reference_ghost(
+        latest_messages_honest: &LatestMessagesHonest<TestBlock>,
+        weights: &validator::Weights<u32, f64>,
+    ) -> TestBlock {
+        let protocol_state = Block::find_all_accessible_blocks(latest_messages_honest);
+        let protocol_state: HashSet<_> = protocol_state.iter().collect();
+        let score = |block: &TestBlock| block.score(latest_messages_honest, weights);
+
+        let mut leaves = Vec::new();
+        let mut stack = vec![protocol_state
+            .iter()
+            .cloned()
+            .filter(|block| block.prevblock().is_none())
+            .collect::<Vec<_>>()];
+        while let Some(blocks) = stack.pop() {
+            // Scores are sums of weights, so they are never negative.
+            let best = blocks.iter().map(|block| score(block)).fold(0.0, f64::max);
+            for block in blocks.into_iter().filter(|block| score(block) == best) {
+                let children: Vec<_> = block.children(&protocol_state).into_iter().collect();
+                if children.is_empty() {
+                    leaves.push(block.clone());
+                } else {
+                    stack.push(children);
+                }
+            }
+        }
+        leaves.into_iter().min_by_key(Block::id).unwrap()
+    }
+
+    #[test]
+    fn lowest_hash_matches_reference() {
+        let mut random = Lcg::new(7);
+        let weights = validator::Weights::new((0..6).map(|validator| (validator, 1.0)).collect());
+
+        for _ in 0..200 {
+            let mut blocks = vec![Block::new(None, ValidatorNameBlockData::new(0))];
+            for _ in 0..12 {
+                let prevblock = blocks[random.below(blocks.len())].clone();
+                blocks.push(Block::new(
+                    Some(prevblock),
+                    ValidatorNameBlockData::new(random.below(6) as u32),
+                ));
+            }
+            let mut latest_messages = LatestMessages::empty();
+            for validator in 0..6 {
+                let block = blocks[random.below(blocks.len())].clone();
+                latest_messages.update(&Message::new(validator, Justification::empty(), block));
+            }
+            let latest_messages_honest =
+                LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new());
+
+            let expected = reference_ghost(&latest_messages_honest, &weights);
+            assert_eq!(
+                Block::optimized_ghost(&latest_messages_honest, &weights).unwrap(),
+                expected
+            );
+            assert_eq!(
+                Block::mathematical_ghost(&latest_messages_honest, &weights).unwrap(),
+                expected
+            );
+            assert_eq!(
+                Ghost
+                    .head_with_tie_breaker(
+                        &latest_messages_honest,
+                        &weights,
+                        &TieBreaker::LowestHash
+                    )
+                    .unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn lowest_hash_across_tied_branches() {
+        let weights = weights();
+        // Looks for a fork where the lowest leaf is not in the branch of the lowest child of the
+        // genesis block, which breaking the ties at each fork would select.
+        let (messages, block_second, block_other) = (1..=5)
+            .flat_map(|first| (1..=5).map(move |second| two_level_fork(first, second, 3)))
+            .find(|(_, block_second, block_other)| {
+                let block_first = block_second.prevblock().unwrap();
+                (block_first.id() < block_other.id()) != (block_second.id() < block_other.id())
+            })
+            .expect("a fork with tied branches ordered differently by their leaves");
+        let lowest = if block_second.id() < block_other.id() {
+            block_second
+        } else {
+            block_other
+        };
+
+        let mut latest_messages = LatestMessages::empty();
+        for message in messages.iter() {
+            latest_messages.update(message);
+        }
+        let latest_messages_honest =
+            LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new());
+        assert_eq!(
+            Block::optimized_ghost(&latest_messages_honest, &weights).unwrap(),
+            lowest
+        );
+        assert_eq!(
+            Block::mathematical_ghost(&latest_messages_honest, &weights).unwrap(),
+            lowest
+        );
+    }
+
+    #[test]
+    fn proposer_weight_at_each_fork() {
+        // The ties are broken between the children of the genesis block, not between the tips.
+        let (messages, _, block_other) = two_level_fork(1, 5, 4);
+        assert_eq!(
+            agreed_head(&messages, &TieBreaker::ProposerWeight, false),
+            block_other
+        );
+
+        let (messages, block_second, _) = two_level_fork(5, 1, 4);
+        assert_eq!(
+            agreed_head(&messages, &TieBreaker::ProposerWeight, false),
+            block_second
+        );
+    }
+
+    #[test]
+    fn seen_times_deep_justifications() {
+        let mut block = Block::new(None, ValidatorNameBlockData::new(0));
+        let mut message = Message::new(0, Justification::empty(), block.clone());
+        for _ in 0..100_000 {
+            block = Block::new(Some(block), ValidatorNameBlockData::new(0));
+            let mut justification = Justification::empty();
+            justification.insert(message);
+            message = Message::new(0, justification, block.clone());
+        }
+
+        let seen = seen_times(std::iter::once(&message), Some);
+        assert_eq!(seen.get(&block), Some(&100_000));
+        assert_eq!(seen.get(&block.genesis()), Some(&0));
+    }
+
+    #[test]
+    fn seen_times_many_messages_per_block() {
+        let genesis = Block::new(None, ValidatorNameBlockData::new(0));
+        let block = Block::new(Some(genesis.clone()), ValidatorNameBlockData::new(1));
+        let mut message = Message::new(0, Justification::empty(), genesis.clone());
+        for index in 1..10_000 {
+            let estimate = if index < 5_000 { &genesis } else { &block };
+            let mut justification = Justification::empty();
+            justification.insert(message);
+            message = Message::new(0, justification, estimate.clone());
+        }
+        // Messages skipping the history of the parent block are compared with all the first
+        // messages on the parent block, in vain.
+        let mut rushed = Message::new(1, Justification::empty(), block.clone());
+        for _ in 1..5_000 {
+            let mut justification = Justification::empty();
+            justification.insert(rushed);
+            rushed = Message::new(1, justification, block.clone());
+        }
+
+        let seen = seen_times(vec![&message, &rushed].into_iter(), Some);
+        assert_eq!(seen.get(&genesis), Some(&0));
+        assert_eq!(seen.get(&block), Some(&5_000));
+    }
+}
@@ -0,0 +1,623 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::Infallible;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use crate::estimator::{prefix_ghost, Estimator};
+use crate::justification::LatestMessagesHonest;
+use crate::safety_oracle;
+use crate::util::hash::Hash;
+use crate::util::id::Id;
+use crate::util::weight::{WeightUnit, Zero};
+use crate::validator;
+
+/// This trait must be implemented on the data of the transactions of a [`ConcurrentSchedule`].
+///
+/// [`ConcurrentSchedule`]: ./struct.ConcurrentSchedule.html
+pub trait TransactionData:
+    std::hash::Hash + Clone + Eq + Send + Sync + Debug + serde::Serialize
+{
+    /// The type of the keys of the state touched by the transactions.
+    type Key: Ord + std::hash::Hash + Clone + Send + Sync + Debug + serde::Serialize;
+
+    /// The keys of the state read or written by the transaction. Transactions touching disjoint
+    /// keys are ordered and finalized independently.
+    fn keys(&self) -> BTreeSet<Self::Key>;
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ScheduleError {
+    /// The transaction does not touch any key, so it cannot be ordered.
+    NoKey,
+}
+
+impl std::fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ScheduleError::NoKey => writeln!(f, "Transaction does not touch any key"),
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+struct ProtoTransaction<D: TransactionData> {
+    data: D,
+    /// The previous transaction on each of the keys of the data, if any.
+    parents: BTreeMap<D::Key, Transaction<D>>,
+}
+
+/// Dropping the last reference to the head of a long schedule would drop its ancestors
+/// recursively and overflow the stack. The ancestors that are not referenced elsewhere are
+/// instead released one after the other.
+impl<D: TransactionData> Drop for ProtoTransaction<D> {
+    fn drop(&mut self) {
+        let mut stack: Vec<Transaction<D>> = self.parents.values().cloned().collect();
+        self.parents.clear();
+        while let Some(transaction) = stack.pop() {
+            if let Ok(mut prototransaction) = Arc::try_unwrap(transaction.0) {
+                stack.extend(prototransaction.parents.values().cloned());
+                prototransaction.parents.clear();
+            }
+        }
+    }
+}
+
+impl<D: TransactionData> serde::Serialize for ProtoTransaction<D> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let parents: BTreeMap<&D::Key, Hash> = self
+            .parents
+            .iter()
+            .map(|(key, parent)| (key, parent.id()))
+            .collect();
+        let mut transaction = serializer.serialize_struct("Transaction", 2)?;
+        transaction.serialize_field("parents", &parents)?;
+        transaction.serialize_field("data", &self.data)?;
+        transaction.end()
+    }
+}
+
+impl<D: TransactionData> Id for ProtoTransaction<D> {
+    type ID = Hash;
+}
+
+/// Transaction of a [`ConcurrentSchedule`], pointing to the previous transaction on each of the
+/// keys it touches. The transactions of a schedule thus form a DAG, in which the transactions
+/// touching a key form a chain.
+///
+/// Like blocks, transactions are serialized without their ancestors, which are only referenced
+/// by their identifiers.
+///
+/// [`ConcurrentSchedule`]: ./struct.ConcurrentSchedule.html
+#[derive(Clone)]
+pub struct Transaction<D: TransactionData>(Arc<ProtoTransaction<D>>, Hash);
+
+impl<D: TransactionData> Transaction<D> {
+    pub fn data(&self) -> &D {
+        &self.0.data
+    }
+
+    /// Returns the previous transaction touching `key`, if any.
+    pub fn parent(&self, key: &D::Key) -> Option<&Transaction<D>> {
+        self.0.parents.get(key)
+    }
+
+    pub fn parents(&self) -> &BTreeMap<D::Key, Transaction<D>> {
+        &self.0.parents
+    }
+}
+
+impl<D: TransactionData> std::fmt::Debug for Transaction<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let parents: BTreeMap<&D::Key, Hash> = self
+            .parents()
+            .iter()
+            .map(|(key, parent)| (key, parent.id()))
+            .collect();
+        write!(f, "{:?} -> {:?}", self.id(), parents)
+    }
+}
+
+impl<D: TransactionData> serde::Serialize for Transaction<D> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(&self.0, serializer)
+    }
+}
+
+impl<D: TransactionData> Id for Transaction<D> {
+    type ID = Hash;
+
+    // Redefine id to not recompute the hash every time
+    fn id(&self) -> Self::ID {
+        self.1
+    }
+}
+
+impl<D: TransactionData> std::hash::Hash for Transaction<D> {
+    fn hash<H: std::hash::Hasher>(&self, hasher: &mut H) {
+        self.id().hash(hasher);
+    }
+}
+
+impl<D: TransactionData> PartialEq for Transaction<D> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.id() == other.id()
+    }
+}
+
+impl<D: TransactionData> Eq for Transaction<D> {}
+
+impl<D: TransactionData> From<ProtoTransaction<D>> for Transaction<D> {
+    fn from(prototransaction: ProtoTransaction<D>) -> Self {
+        // Transaction is not mutable, id is computed only once at creation
+        let id = prototransaction.id();
+        Transaction(Arc::new(prototransaction), id)
+    }
+}
+
+/// Partial order of transactions agreed upon with the concurrent schedule consensus of the CBC
+/// Casper family. Each transaction declares the keys of the state it touches, and only the
+/// transactions touching a common key are ordered. `V` is the type of the validators' names.
+///
+/// The schedule is represented by the last transaction on each key. The estimate runs the GHOST
+/// fork choice on the chain of transactions of each key independently, then removes from each
+/// chain the transactions, and their descendants, that were not chosen on all of their keys.
+/// Only validators with a positive weight are counted. Transactions touching disjoint keys are
+/// finalized independently, see [`safety_oracles`].
+///
+/// [`safety_oracles`]: #method.safety_oracles
+///
+/// # Example
+///
+/// ```
+/// use core_cbc_casper::concurrent::{ConcurrentSchedule, TransactionData};
+///
+/// use std::collections::BTreeSet;
+///
+/// #[derive(Hash, Clone, PartialEq, Eq, Debug, serde_derive::Serialize)]
+/// struct Transfer {
+///     from: &'static str,
+///     to: &'static str,
+/// }
+///
+/// impl TransactionData for Transfer {
+///     type Key = &'static str;
+///
+///     fn keys(&self) -> BTreeSet<Self::Key> {
+///         vec![self.from, self.to].into_iter().collect()
+///     }
+/// }
+///
+/// let mut schedule = ConcurrentSchedule::<Transfer, u32>::new();
+/// let first = schedule.push(Transfer { from: "alice", to: "bob" }).unwrap();
+/// let second = schedule.push(Transfer { from: "carol", to: "dave" }).unwrap();
+/// let third = schedule.push(Transfer { from: "bob", to: "carol" }).unwrap();
+///
+/// assert!(second.parents().is_empty());
+/// assert_eq!(third.parent(&"bob"), Some(&first));
+/// assert_eq!(third.parent(&"carol"), Some(&second));
+/// assert_eq!(schedule.chain(&"alice"), vec![first.clone()]);
+/// assert_eq!(schedule.chain(&"bob"), vec![first, third]);
+/// ```
+pub struct ConcurrentSchedule<D: TransactionData, V> {
+    heads: BTreeMap<D::Key, Transaction<D>>,
+    validator_name: PhantomData<V>,
+}
+
+impl<D: TransactionData, V> ConcurrentSchedule<D, V> {
+    pub fn new() -> Self {
+        ConcurrentSchedule {
+            heads: BTreeMap::new(),
+            validator_name: PhantomData,
+        }
+    }
+
+    /// Returns the last transaction of the schedule on each key.
+    pub fn heads(&self) -> &BTreeMap<D::Key, Transaction<D>> {
+        &self.heads
+    }
+
+    /// Returns the last transaction of the schedule touching `key`, if any.
+    pub fn head(&self, key: &D::Key) -> Option<&Transaction<D>> {
+        self.heads.get(key)
+    }
+
+    /// Appends a transaction after the last transactions on each of its keys and returns it.
+    pub fn push(&mut self, data: D) -> Result<Transaction<D>, ScheduleError> {
+        let keys = data.keys();
+        if keys.is_empty() {
+            return Err(ScheduleError::NoKey);
+        }
+        let parents = keys
+            .iter()
+            .filter_map(|key| self.heads.get(key).map(|head| (key.clone(), head.clone())))
+            .collect();
+        let transaction = Transaction::from(ProtoTransaction { data, parents });
+        for key in keys {
+            self.heads.insert(key, transaction.clone());
+        }
+        Ok(transaction)
+    }
+
+    /// Returns the transactions touching `key`, from the first to the last.
+    pub fn chain(&self, key: &D::Key) -> Vec<Transaction<D>> {
+        let mut chain = vec![];
+        let mut transaction = self.heads.get(key);
+        while let Some(current) = transaction {
+            chain.push(current.clone());
+            transaction = current.parent(key);
+        }
+        chain.reverse();
+        chain
+    }
+
+    /// Returns true if the transaction is part of the schedule.
+    pub fn contains(&self, transaction: &Transaction<D>) -> bool {
+        let key = match transaction.data().keys().into_iter().next() {
+            Some(key) => key,
+            None => return false,
+        };
+        let mut current = self.heads.get(&key);
+        while let Some(ancestor) = current {
+            if ancestor == transaction {
+                return true;
+            }
+            current = ancestor.parent(&key);
+        }
+        false
+    }
+}
+
+impl<D, V> ConcurrentSchedule<D, V>
+where
+    D: TransactionData,
+    V: validator::ValidatorName,
+{
+    /// Returns the cliques of validators weighting more than `safety_oracle_threshold` whose
+    /// schedules contain `transaction` and that have seen each other scheduling it. See
+    /// [`safety_oracle::clique_oracles`].
+    ///
+    /// Only the chains of the keys touched by the transaction matter, so transactions touching
+    /// disjoint keys are finalized independently.
+    ///
+    /// [`safety_oracle::clique_oracles`]: ../safety_oracle/fn.clique_oracles.html
+    pub fn safety_oracles<U: WeightUnit>(
+        transaction: &Transaction<D>,
+        latest_messages_honest: &LatestMessagesHonest<Self>,
+        equivocators: &BTreeSet<V>,
+        safety_oracle_threshold: U,
+        weights: &validator::Weights<V, U>,
+    ) -> HashSet<BTreeSet<V>> {
+        safety_oracle::clique_oracles(
+            latest_messages_honest,
+            equivocators,
+            safety_oracle_threshold,
+            weights,
+            |message| message.estimate().contains(transaction),
+        )
+    }
+}
+
+impl<D: TransactionData, V> Default for ConcurrentSchedule<D, V> {
+    fn default() -> Self {
+        ConcurrentSchedule::new()
+    }
+}
+
+impl<D: TransactionData, V> Clone for ConcurrentSchedule<D, V> {
+    fn clone(&self) -> Self {
+        ConcurrentSchedule {
+            heads: self.heads.clone(),
+            validator_name: PhantomData,
+        }
+    }
+}
+
+impl<D: TransactionData, V> PartialEq for ConcurrentSchedule<D, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.heads == other.heads
+    }
+}
+
+impl<D: TransactionData, V> Eq for ConcurrentSchedule<D, V> {}
+
+impl<D: TransactionData, V> std::hash::Hash for ConcurrentSchedule<D, V> {
+    fn hash<H: std::hash::Hasher>(&self, hasher: &mut H) {
+        self.heads.hash(hasher)
+    }
+}
+
+impl<D: TransactionData, V> Debug for ConcurrentSchedule<D, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.heads.fmt(f)
+    }
+}
+
+/// The schedule is serialized as the identifiers of the last transaction on each key.
+impl<D: TransactionData, V> serde::Serialize for ConcurrentSchedule<D, V> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let heads: BTreeMap<&D::Key, Hash> = self
+            .heads
+            .iter()
+            .map(|(key, head)| (key, head.id()))
+            .collect();
+        serde::Serialize::serialize(&heads, serializer)
+    }
+}
+
+impl<D, V> Estimator for ConcurrentSchedule<D, V>
+where
+    D: TransactionData,
+    V: validator::ValidatorName,
+{
+    type ValidatorName = V;
+    type Error = Infallible;
+
+    fn estimate<U: WeightUnit>(
+        latest_messages: &LatestMessagesHonest<Self>,
+        validators_weights: &validator::Weights<V, U>,
+    ) -> Result<Self, Self::Error> {
+        let schedules: Vec<(&Self, U)> = latest_messages
+            .iter()
+            .filter_map(
+                |message| match validators_weights.weight(message.sender()) {
+                    Ok(weight) if weight > <U as Zero<U>>::ZERO => {
+                        Some((message.estimate(), weight))
+                    }
+                    _ => None,
+                },
+            )
+            .collect();
+        let keys: BTreeSet<&D::Key> = schedules
+            .iter()
+            .flat_map(|(schedule, _)| schedule.heads.keys())
+            .collect();
+
+        let mut chains: BTreeMap<D::Key, Vec<Transaction<D>>> = keys
+            .into_iter()
+            .map(|key| {
+                let candidates: Vec<(Vec<Transaction<D>>, U)> = schedules
+                    .iter()
+                    .filter(|(schedule, _)| schedule.heads.contains_key(key))
+                    .map(|(schedule, weight)| (schedule.chain(key), *weight))
+                    .collect();
+                let chain = prefix_ghost(
+                    candidates
+                        .iter()
+                        .map(|(chain, weight)| (&chain[..], *weight))
+                        .collect(),
+                );
+                (key.clone(), chain)
+            })
+            .collect();
+
+        // A transaction chosen on one of its keys but not on another one is removed with its
+        // descendants, until every transaction is chosen on all of its keys.
+        loop {
+            let cuts: Vec<(D::Key, usize)> = {
+                let chosen: HashMap<&D::Key, HashSet<&Transaction<D>>> = chains
+                    .iter()
+                    .map(|(key, chain)| (key, chain.iter().collect()))
+                    .collect();
+                chains
+                    .iter()
+                    .filter_map(|(key, chain)| {
+                        chain
+                            .iter()
+                            .position(|transaction| {
+                                transaction.data().keys().iter().any(|other| {
+                                    match chosen.get(other) {
+                                        Some(chain) => !chain.contains(transaction),
+                                        None => true,
+                                    }
+                                })
+                            })
+                            .map(|index| (key.clone(), index))
+                    })
+                    .collect()
+            };
+            if cuts.is_empty() {
+                break;
+            }
+            for (key, index) in cuts {
+                if let Some(chain) = chains.get_mut(&key) {
+                    chain.truncate(index);
+                }
+            }
+        }
+
+        let mut schedule = ConcurrentSchedule::new();
+        for (key, mut chain) in chains {
+            if let Some(head) = chain.pop() {
+                schedule.heads.insert(key, head);
+            }
+        }
+        Ok(schedule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::justification::{Justification, LatestMessages};
+    use crate::message::Message;
+
+    #[derive(Hash, Clone, PartialEq, Eq, Debug, serde_derive::Serialize)]
+    struct TestTransaction {
+        name: u32,
+        keys: Vec<char>,
+    }
+
+    impl TransactionData for TestTransaction {
+        type Key = char;
+
+        fn keys(&self) -> BTreeSet<char> {
+            self.keys.iter().cloned().collect()
+        }
+    }
+
+    type Schedule = ConcurrentSchedule<TestTransaction, u32>;
+
+    fn transaction(name: u32, keys: &str) -> TestTransaction {
+        TestTransaction {
+            name,
+            keys: keys.chars().collect(),
+        }
+    }
+
+    fn estimate(schedules: &[(u32, &Schedule)], weights: &[f64]) -> Schedule {
+        let mut latest_messages = LatestMessages::empty();
+        for (validator, schedule) in schedules.iter() {
+            latest_messages.update(&Message::new(
+                *validator,
+                Justification::empty(),
+                (*schedule).clone(),
+            ));
+        }
+        Schedule::estimate(
+            &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+            &validator::Weights::new((0..).zip(weights.iter().cloned()).collect()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn schedule_push() {
+        let mut schedule = Schedule::new();
+        assert_eq!(schedule.push(transaction(0, "")), Err(ScheduleError::NoKey));
+        let a = schedule.push(transaction(0, "x")).unwrap();
+        let b = schedule.push(transaction(1, "y")).unwrap();
+        let c = schedule.push(transaction(2, "xy")).unwrap();
+        let d = schedule.push(transaction(3, "z")).unwrap();
+
+        assert!(a.parents().is_empty());
+        assert_eq!(c.parent(&'x'), Some(&a));
+        assert_eq!(c.parent(&'y'), Some(&b));
+        assert_eq!(schedule.chain(&'x'), vec![a.clone(), c.clone()]);
+        assert_eq!(schedule.chain(&'y'), vec![b.clone(), c.clone()]);
+        assert_eq!(schedule.chain(&'z'), vec![d.clone()]);
+        assert_eq!(schedule.head(&'x'), Some(&c));
+
+        let mut other = Schedule::new();
+        let other_a = other.push(transaction(0, "x")).unwrap();
+        let other_c = other.push(transaction(2, "xy")).unwrap();
+        assert_eq!(other_a, a);
+        assert_ne!(other_c, c);
+        for transaction in [&a, &b, &c, &d].iter() {
+            assert!(schedule.contains(transaction));
+        }
+        assert!(!schedule.contains(&other_c));
+        assert!(!other.contains(&b));
+    }
+
+    #[test]
+    fn schedule_estimate_per_key() {
+        let mut schedule_0 = Schedule::new();
+        schedule_0.push(transaction(0, "x")).unwrap();
+        schedule_0.push(transaction(1, "y")).unwrap();
+        let mut schedule_1 = Schedule::new();
+        schedule_1.push(transaction(0, "x")).unwrap();
+        schedule_1.push(transaction(2, "y")).unwrap();
+        let mut schedule_2 = Schedule::new();
+        schedule_2.push(transaction(3, "x")).unwrap();
+        schedule_2.push(transaction(2, "y")).unwrap();
+
+        // Validators 0 and 1 agree on x while validators 1 and 2 agree on y.
+        let estimate = estimate(
+            &[(0, &schedule_0), (1, &schedule_1), (2, &schedule_2)],
+            &[1.0, 1.0, 1.0],
+        );
+        assert_eq!(estimate.head(&'x'), schedule_0.head(&'x'));
+        assert_eq!(estimate.head(&'y'), schedule_2.head(&'y'));
+        assert_eq!(estimate.heads().len(), 2);
+    }
+
+    #[test]
+    fn schedule_estimate_consistency() {
+        let mut schedule_0 = Schedule::new();
+        let a = schedule_0.push(transaction(0, "x")).unwrap();
+        schedule_0.push(transaction(1, "xy")).unwrap();
+        schedule_0.push(transaction(2, "x")).unwrap();
+        let mut schedule_1 = Schedule::new();
+        let d = schedule_1.push(transaction(3, "y")).unwrap();
+
+        // The transaction touching x and y is chosen on x but not on y, so it is removed from
+        // x with the transaction following it.
+        let schedule = estimate(&[(0, &schedule_0), (1, &schedule_1)], &[1.0, 2.0]);
+        assert_eq!(schedule.chain(&'x'), vec![a]);
+        assert_eq!(schedule.chain(&'y'), vec![d]);
+
+        let schedule = estimate(&[(0, &schedule_0), (1, &schedule_1)], &[2.0, 1.0]);
+        assert_eq!(schedule, schedule_0);
+    }
+
+    #[test]
+    fn schedule_safety_oracles() {
+        let weights = validator::Weights::new(vec![(0, 1.0), (1, 1.0)].into_iter().collect());
+        let equivocators = BTreeSet::new();
+
+        let mut schedule_0 = Schedule::new();
+        let a = schedule_0.push(transaction(0, "x")).unwrap();
+        let mut schedule_1 = schedule_0.clone();
+        let b = schedule_1.push(transaction(1, "y")).unwrap();
+        let m0 = Message::new(0, Justification::empty(), schedule_0.clone());
+        let m1 = Message::new(1, Justification::empty(), schedule_1.clone());
+
+        let mut justification = Justification::empty();
+        justification.insert(m0);
+        justification.insert(m1);
+        let m2 = Message::new(0, justification.clone(), schedule_0);
+        let m3 = Message::new(1, justification, schedule_1);
+        let mut latest_messages = LatestMessages::empty();
+        latest_messages.update(&m2);
+        latest_messages.update(&m3);
+        let latest_messages_honest =
+            LatestMessagesHonest::from_latest_messages(&latest_messages, &equivocators);
+
+        // The transaction on x is final while the one on y is not known to validator 0.
+        assert_eq!(
+            Schedule::safety_oracles(&a, &latest_messages_honest, &equivocators, 1.5, &weights),
+            vec![vec![0, 1].into_iter().collect()].into_iter().collect()
+        );
+        assert!(Schedule::safety_oracles(
+            &b,
+            &latest_messages_honest,
+            &equivocators,
+            1.5,
+            &weights
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn deep_schedule() {
+        let mut schedule = Schedule::new();
+        for name in 0..100_000 {
+            schedule.push(transaction(name, "x")).unwrap();
+        }
+        assert_eq!(schedule.chain(&'x').len(), 100_000);
+    }
+}
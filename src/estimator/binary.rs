@@ -0,0 +1,326 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+use std::convert::Infallible;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+use crate::estimator::Estimator;
+use crate::justification::LatestMessagesHonest;
+use crate::util::weight::{WeightUnit, Zero};
+use crate::validator;
+
+/// Minimum weight the estimate of a [`Binary`] consensus must gather to be decided.
+///
+/// [`Binary`]: struct.Binary.html
+pub trait Quorum: Send + Sync + 'static {
+    /// Returns true if `weight` out of the `total_weight` of the validators is enough to decide.
+    fn is_reached<U: WeightUnit>(weight: U, total_weight: U) -> bool;
+}
+
+/// Any weight is enough to decide.
+#[derive(Debug)]
+pub enum NoQuorum {}
+
+impl Quorum for NoQuorum {
+    fn is_reached<U: WeightUnit>(_weight: U, _total_weight: U) -> bool {
+        true
+    }
+}
+
+/// More than half of the total weight is needed to decide.
+#[derive(Debug)]
+pub enum Majority {}
+
+impl Quorum for Majority {
+    fn is_reached<U: WeightUnit>(weight: U, total_weight: U) -> bool {
+        weight + weight > total_weight
+    }
+}
+
+/// At least two thirds of the total weight are needed to decide.
+#[derive(Debug)]
+pub enum TwoThirds {}
+
+impl Quorum for TwoThirds {
+    fn is_reached<U: WeightUnit>(weight: U, total_weight: U) -> bool {
+        weight + weight + weight >= total_weight + total_weight
+    }
+}
+
+/// Binary consensus value weighted by the validators' stake, e.g. for an upgrade flag. `V` is the
+/// type of the validators' names and `Q` the [`Quorum`] the estimate must reach.
+///
+/// The estimate is the value with the highest total weight among the latest honest estimates,
+/// `false` in case of a tie. It is undecided when no validator with a positive weight estimates a
+/// value, or when the weight of the value does not reach the quorum of the total weight of the
+/// validators, including those that did not send any message. Undecided estimates do not count
+/// for either value. The latest messages of validators that are not in the weights are ignored.
+///
+/// [`Quorum`]: trait.Quorum.html
+///
+/// # Example
+///
+/// ```
+/// use core_cbc_casper::estimator::{Binary, Estimator, TwoThirds};
+/// use core_cbc_casper::justification::{Justification, LatestMessages, LatestMessagesHonest};
+/// use core_cbc_casper::message::Message;
+/// use core_cbc_casper::validator;
+///
+/// use std::collections::BTreeSet;
+///
+/// type UpgradeFlag = Binary<u32, TwoThirds>;
+///
+/// let weights = validator::Weights::new(vec![(0, 1.0), (1, 1.0), (2, 1.0)].into_iter().collect());
+///
+/// let mut latest_messages = LatestMessages::empty();
+/// latest_messages.update(&Message::new(0, Justification::empty(), UpgradeFlag::new(true)));
+/// latest_messages.update(&Message::new(1, Justification::empty(), UpgradeFlag::new(false)));
+///
+/// let estimate = |latest_messages: &LatestMessages<UpgradeFlag>| {
+///     UpgradeFlag::estimate(
+///         &LatestMessagesHonest::from_latest_messages(latest_messages, &BTreeSet::new()),
+///         &weights,
+///     )
+///     .unwrap()
+///     .value()
+/// };
+/// assert_eq!(estimate(&latest_messages), None);
+///
+/// latest_messages.update(&Message::new(2, Justification::empty(), UpgradeFlag::new(true)));
+/// assert_eq!(estimate(&latest_messages), Some(true));
+/// ```
+pub struct Binary<V, Q = NoQuorum> {
+    value: Option<bool>,
+    parameters: PhantomData<(V, Q)>,
+}
+
+impl<V, Q> Binary<V, Q> {
+    pub fn new(value: bool) -> Self {
+        Binary {
+            value: Some(value),
+            parameters: PhantomData,
+        }
+    }
+
+    pub fn undecided() -> Self {
+        Binary {
+            value: None,
+            parameters: PhantomData,
+        }
+    }
+
+    /// Returns the decided value, or None if undecided.
+    pub fn value(&self) -> Option<bool> {
+        self.value
+    }
+}
+
+impl<V, Q> Clone for Binary<V, Q> {
+    fn clone(&self) -> Self {
+        Binary {
+            value: self.value,
+            parameters: PhantomData,
+        }
+    }
+}
+
+impl<V, Q> PartialEq for Binary<V, Q> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<V, Q> Eq for Binary<V, Q> {}
+
+impl<V, Q> Hash for Binary<V, Q> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state)
+    }
+}
+
+impl<V, Q> Debug for Binary<V, Q> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.value {
+            Some(value) => write!(f, "{:?}", value),
+            None => write!(f, "undecided"),
+        }
+    }
+}
+
+/// Only the value is serialized.
+impl<V, Q> serde::Serialize for Binary<V, Q> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(&self.value, serializer)
+    }
+}
+
+impl<V, Q> Estimator for Binary<V, Q>
+where
+    V: validator::ValidatorName,
+    Q: Quorum,
+{
+    type ValidatorName = V;
+    type Error = Infallible;
+
+    fn estimate<U: WeightUnit>(
+        latest_messages: &LatestMessagesHonest<Self>,
+        validators_weights: &validator::Weights<V, U>,
+    ) -> Result<Self, Self::Error> {
+        let mut true_weight = <U as Zero<U>>::ZERO;
+        let mut false_weight = <U as Zero<U>>::ZERO;
+        for message in latest_messages.iter() {
+            match (
+                message.estimate().value(),
+                validators_weights.weight(message.sender()),
+            ) {
+                (Some(true), Ok(weight)) if weight > <U as Zero<U>>::ZERO => true_weight += weight,
+                (Some(false), Ok(weight)) if weight > <U as Zero<U>>::ZERO => {
+                    false_weight += weight
+                }
+                _ => (),
+            }
+        }
+
+        let (value, weight) = if true_weight > false_weight {
+            (true, true_weight)
+        } else {
+            (false, false_weight)
+        };
+        if weight > <U as Zero<U>>::ZERO
+            && Q::is_reached(weight, validators_weights.sum_all_weights())
+        {
+            Ok(Binary::new(value))
+        } else {
+            Ok(Binary::undecided())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use crate::justification::{Justification, LatestMessages};
+    use crate::message::Message;
+
+    fn estimate<Q: Quorum>(estimates: &[(u32, Option<bool>)], weights: &[f64]) -> Option<bool> {
+        let mut latest_messages = LatestMessages::empty();
+        for (validator, value) in estimates.iter() {
+            let estimate = match value {
+                Some(value) => Binary::<u32, Q>::new(*value),
+                None => Binary::undecided(),
+            };
+            latest_messages.update(&Message::new(*validator, Justification::empty(), estimate));
+        }
+        Binary::estimate(
+            &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+            &validator::Weights::new((0..).zip(weights.iter().cloned()).collect()),
+        )
+        .unwrap()
+        .value()
+    }
+
+    #[test]
+    fn binary_weighted() {
+        let weights = [1.0, 1.0, 3.0];
+        let estimates = [(0, Some(true)), (1, Some(true)), (2, Some(false))];
+        assert_eq!(estimate::<NoQuorum>(&estimates, &weights), Some(false));
+        let estimates = [(0, Some(false)), (1, Some(false)), (2, Some(true))];
+        assert_eq!(estimate::<NoQuorum>(&estimates, &weights), Some(true));
+        assert_eq!(
+            estimate::<NoQuorum>(&estimates, &[1.0, 1.0, 1.0]),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn binary_tie() {
+        let weights = [1.0, 1.0, 2.0];
+        let estimates = [(0, Some(true)), (1, Some(true)), (2, Some(false))];
+        assert_eq!(estimate::<NoQuorum>(&estimates, &weights), Some(false));
+        let estimates = [(0, Some(false)), (1, Some(false)), (2, Some(true))];
+        assert_eq!(estimate::<NoQuorum>(&estimates, &weights), Some(false));
+    }
+
+    #[test]
+    fn binary_undecided() {
+        let weights = [1.0, 1.0, 1.0, 0.0];
+        assert_eq!(estimate::<NoQuorum>(&[], &weights), None);
+        // Undecided estimates, validators without weight and unknown validators do not count.
+        let estimates = [(0, None), (1, None), (3, Some(true)), (4, Some(true))];
+        assert_eq!(estimate::<NoQuorum>(&estimates, &weights), None);
+        let estimates = [
+            (0, None),
+            (1, Some(true)),
+            (3, Some(false)),
+            (4, Some(false)),
+        ];
+        assert_eq!(estimate::<NoQuorum>(&estimates, &weights), Some(true));
+    }
+
+    #[test]
+    fn binary_quorum() {
+        let weights = [1.0, 1.0, 1.0, 1.0];
+        // The quorum counts the weight of the validators without latest message.
+        let estimates = [(0, Some(true)), (1, Some(true))];
+        assert_eq!(estimate::<NoQuorum>(&estimates, &weights), Some(true));
+        assert_eq!(estimate::<Majority>(&estimates, &weights), None);
+        assert_eq!(estimate::<TwoThirds>(&estimates, &weights), None);
+
+        let estimates = [(0, Some(true)), (1, Some(true)), (2, Some(true))];
+        assert_eq!(estimate::<Majority>(&estimates, &weights), Some(true));
+        assert_eq!(estimate::<TwoThirds>(&estimates, &weights), Some(true));
+
+        let weights = [1.0, 1.0, 1.0];
+        let estimates = [(0, Some(false)), (1, Some(false)), (2, Some(true))];
+        assert_eq!(estimate::<Majority>(&estimates, &weights), Some(false));
+        assert_eq!(estimate::<TwoThirds>(&estimates, &weights), Some(false));
+        let estimates = [(0, Some(false)), (1, Some(true))];
+        assert_eq!(estimate::<Majority>(&estimates, &weights), None);
+    }
+
+    #[test]
+    fn binary_equivocators() {
+        let weights =
+            validator::Weights::new(vec![(0, 1.0), (1, 1.0), (2, 1.0)].into_iter().collect());
+        let mut latest_messages = LatestMessages::empty();
+        for (validator, value) in [(0, true), (0, false), (1, true), (2, false)].iter() {
+            latest_messages.update(&Message::new(
+                *validator,
+                Justification::empty(),
+                Binary::<u32, Majority>::new(*value),
+            ));
+        }
+        let equivocators = vec![0].into_iter().collect();
+        assert_eq!(
+            Binary::estimate(
+                &LatestMessagesHonest::from_latest_messages(&latest_messages, &equivocators),
+                &weights,
+            )
+            .unwrap()
+            .value(),
+            None
+        );
+    }
+}
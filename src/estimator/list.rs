@@ -0,0 +1,384 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::Infallible;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use crate::estimator::plurality::Serialized;
+use crate::estimator::Estimator;
+use crate::justification::LatestMessagesHonest;
+use crate::safety_oracle;
+use crate::util::id::Id;
+use crate::util::weight::{WeightUnit, Zero};
+use crate::validator;
+
+/// Ordered log of items agreed upon with the list consensus of the CBC Casper paper, e.g. for a
+/// replicated configuration log. `V` is the type of the validators' names.
+///
+/// The estimate is chosen with a GHOST-like fork choice on the prefixes of the latest honest
+/// estimates: starting from the empty list, the next item is the one following the current
+/// prefix in the estimates with the highest total weight, until no estimate extends the prefix.
+/// Ties between items are broken with the smallest hash of their serialization. Only validators
+/// with a positive weight are counted.
+///
+/// Validators append their new items to the estimate with [`push`], and [`finalized_len`] tells
+/// the length of the prefix of an estimate that is final.
+///
+/// [`push`]: #method.push
+/// [`finalized_len`]: #method.finalized_len
+///
+/// # Example
+///
+/// ```
+/// use core_cbc_casper::estimator::{Estimator, ListEstimator};
+/// use core_cbc_casper::justification::{Justification, LatestMessages, LatestMessagesHonest};
+/// use core_cbc_casper::message::Message;
+/// use core_cbc_casper::validator;
+///
+/// use std::collections::BTreeSet;
+///
+/// type Log = ListEstimator<&'static str, u32>;
+///
+/// let weights = validator::Weights::new(vec![(0, 1.0), (1, 1.0), (2, 3.0)].into_iter().collect());
+///
+/// let mut latest_messages = LatestMessages::empty();
+/// let log = |items: Vec<&'static str>| Log::new(items);
+/// latest_messages.update(&Message::new(0, Justification::empty(), log(vec!["a", "b", "c"])));
+/// latest_messages.update(&Message::new(1, Justification::empty(), log(vec!["a", "b"])));
+/// latest_messages.update(&Message::new(2, Justification::empty(), log(vec!["a", "d"])));
+///
+/// let mut estimate = Log::estimate(
+///     &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+///     &weights,
+/// )
+/// .unwrap();
+/// assert_eq!(estimate.items(), &["a", "d"]);
+///
+/// estimate.push("e");
+/// assert_eq!(estimate.items(), &["a", "d", "e"]);
+/// ```
+pub struct ListEstimator<T, V> {
+    items: Vec<T>,
+    validator_name: PhantomData<V>,
+}
+
+impl<T, V> ListEstimator<T, V> {
+    pub fn new(items: Vec<T>) -> Self {
+        ListEstimator {
+            items,
+            validator_name: PhantomData,
+        }
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    pub fn into_items(self) -> Vec<T> {
+        self.items
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Appends an item to the list, e.g. to propose it on top of the estimate.
+    pub fn push(&mut self, item: T) {
+        self.items.push(item)
+    }
+}
+
+impl<T, V> Default for ListEstimator<T, V> {
+    fn default() -> Self {
+        ListEstimator::new(vec![])
+    }
+}
+
+impl<T: Clone, V> Clone for ListEstimator<T, V> {
+    fn clone(&self) -> Self {
+        ListEstimator::new(self.items.clone())
+    }
+}
+
+impl<T: PartialEq, V> PartialEq for ListEstimator<T, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.items == other.items
+    }
+}
+
+impl<T: Eq, V> Eq for ListEstimator<T, V> {}
+
+impl<T: Hash, V> Hash for ListEstimator<T, V> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.items.hash(state)
+    }
+}
+
+impl<T: Debug, V> Debug for ListEstimator<T, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.items.fmt(f)
+    }
+}
+
+/// Only the items are serialized.
+impl<T: serde::Serialize, V> serde::Serialize for ListEstimator<T, V> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.items.serialize(serializer)
+    }
+}
+
+impl<T, V> ListEstimator<T, V>
+where
+    T: Clone + Eq + Hash + Send + Sync + Debug + serde::Serialize,
+    V: validator::ValidatorName,
+{
+    /// Returns the cliques of validators weighting more than `safety_oracle_threshold` whose
+    /// estimates start with the first `len` items of this list and that have seen each other
+    /// estimating so. See [`safety_oracle::clique_oracles`].
+    ///
+    /// [`safety_oracle::clique_oracles`]: ../safety_oracle/fn.clique_oracles.html
+    pub fn safety_oracles<U: WeightUnit>(
+        &self,
+        len: usize,
+        latest_messages_honest: &LatestMessagesHonest<Self>,
+        equivocators: &BTreeSet<V>,
+        safety_oracle_threshold: U,
+        weights: &validator::Weights<V, U>,
+    ) -> HashSet<BTreeSet<V>> {
+        let prefix = &self.items[..len.min(self.items.len())];
+        safety_oracle::clique_oracles(
+            latest_messages_honest,
+            equivocators,
+            safety_oracle_threshold,
+            weights,
+            |message| message.estimate().items.starts_with(prefix),
+        )
+    }
+
+    /// Returns the length of the longest prefix of this list that is final, i.e. that has a
+    /// safety oracle above `safety_oracle_threshold`.
+    pub fn finalized_len<U: WeightUnit>(
+        &self,
+        latest_messages_honest: &LatestMessagesHonest<Self>,
+        equivocators: &BTreeSet<V>,
+        safety_oracle_threshold: U,
+        weights: &validator::Weights<V, U>,
+    ) -> usize {
+        let is_final = |len| {
+            !self
+                .safety_oracles(
+                    len,
+                    latest_messages_honest,
+                    equivocators,
+                    safety_oracle_threshold,
+                    weights,
+                )
+                .is_empty()
+        };
+        // A clique agreeing on a prefix agrees on its shorter prefixes, so the final lengths
+        // can be searched by bisection.
+        let (mut low, mut high) = (0, self.items.len());
+        while low < high {
+            let middle = high - (high - low) / 2;
+            if is_final(middle) {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+        low
+    }
+}
+
+impl<T, V> Estimator for ListEstimator<T, V>
+where
+    T: Clone + Eq + Hash + Send + Sync + Debug + serde::Serialize,
+    V: validator::ValidatorName,
+{
+    type ValidatorName = V;
+    type Error = Infallible;
+
+    fn estimate<U: WeightUnit>(
+        latest_messages: &LatestMessagesHonest<Self>,
+        validators_weights: &validator::Weights<V, U>,
+    ) -> Result<Self, Self::Error> {
+        let candidates = latest_messages
+            .iter()
+            .filter_map(
+                |message| match validators_weights.weight(message.sender()) {
+                    Ok(weight) if weight > <U as Zero<U>>::ZERO => {
+                        Some((message.estimate().items(), weight))
+                    }
+                    _ => None,
+                },
+            )
+            .collect();
+        Ok(ListEstimator::new(prefix_ghost(candidates)))
+    }
+}
+
+/// Returns the longest prefix such that each item is, among the items following the previous
+/// ones in the weighted `candidates`, the one with the highest total weight. Ties between items
+/// are broken with the smallest hash of their serialization.
+pub(crate) fn prefix_ghost<T, U>(mut candidates: Vec<(&[T], U)>) -> Vec<T>
+where
+    T: Clone + Eq + Hash + serde::Serialize,
+    U: WeightUnit,
+{
+    let mut items = vec![];
+    loop {
+        let index = items.len();
+        candidates.retain(|(candidate, _)| candidate.len() > index);
+
+        let mut next_items_weights: HashMap<&T, U> = HashMap::new();
+        for (candidate, weight) in candidates.iter() {
+            *next_items_weights
+                .entry(&candidate[index])
+                .or_insert(<U as Zero<U>>::ZERO) += *weight;
+        }
+        let next_item = next_items_weights
+            .into_iter()
+            .max_by(|(left, left_weight), (right, right_weight)| {
+                left_weight
+                    .partial_cmp(right_weight)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .then_with(|| Serialized(*right).id().cmp(&Serialized(*left).id()))
+            })
+            .map(|(item, _)| item.clone());
+
+        match next_item {
+            Some(next_item) => {
+                candidates.retain(|(candidate, _)| candidate[index] == next_item);
+                items.push(next_item);
+            }
+            None => return items,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::justification::{Justification, LatestMessages};
+    use crate::message::Message;
+
+    type Log = ListEstimator<u32, u32>;
+
+    fn estimate(estimates: &[(u32, Vec<u32>)], weights: &[f64]) -> Vec<u32> {
+        let mut latest_messages = LatestMessages::empty();
+        for (validator, items) in estimates.iter() {
+            latest_messages.update(&Message::new(
+                *validator,
+                Justification::empty(),
+                Log::new(items.clone()),
+            ));
+        }
+        Log::estimate(
+            &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+            &validator::Weights::new((0..).zip(weights.iter().cloned()).collect()),
+        )
+        .unwrap()
+        .into_items()
+    }
+
+    #[test]
+    fn list_estimate_prefix_ghost() {
+        assert_eq!(estimate(&[], &[1.0]), Vec::<u32>::new());
+        let weights = [1.0, 1.0, 1.5, 1.0];
+        let estimates = [
+            (0, vec![1, 2, 3]),
+            (1, vec![1, 2]),
+            (2, vec![1, 4, 5, 6]),
+            (3, vec![7, 8, 9, 10, 11]),
+        ];
+        // 1 outweighs 7, then 2 outweighs 4 although validator 2 is the heaviest.
+        assert_eq!(estimate(&estimates, &weights), vec![1, 2, 3]);
+        let weights = [1.0, 1.0, 2.5, 1.0];
+        assert_eq!(estimate(&estimates, &weights), vec![1, 4, 5, 6]);
+        let weights = [1.0, 1.0, 1.0, 3.5];
+        assert_eq!(estimate(&estimates, &weights), vec![7, 8, 9, 10, 11]);
+    }
+
+    #[test]
+    fn list_estimate_ignored_validators() {
+        let estimates = [(0, vec![1]), (1, vec![2]), (2, vec![2]), (3, vec![2])];
+        // Validator 1 has no weight and validators 2 and 3 are unknown.
+        assert_eq!(estimate(&estimates, &[1.0, 0.0]), vec![1]);
+    }
+
+    #[test]
+    fn list_estimate_tie() {
+        let estimates = [(0, vec![1, 2]), (1, vec![3])];
+        let smallest_hash = if Serialized(&1).id() < Serialized(&3).id() {
+            vec![1, 2]
+        } else {
+            vec![3]
+        };
+        assert_eq!(estimate(&estimates, &[1.0, 1.0]), smallest_hash);
+        let estimates = [(1, vec![3]), (0, vec![1, 2])];
+        assert_eq!(estimate(&estimates, &[1.0, 1.0]), smallest_hash);
+    }
+
+    #[test]
+    fn list_finalized_len() {
+        let weights =
+            validator::Weights::new(vec![(0, 1.0), (1, 1.0), (2, 1.0)].into_iter().collect());
+        let equivocators = BTreeSet::new();
+        let finalized_len = |estimate: &Log, messages: &[&Message<Log>]| {
+            let mut latest_messages = LatestMessages::empty();
+            for message in messages {
+                latest_messages.update(message);
+            }
+            estimate.finalized_len(
+                &LatestMessagesHonest::from_latest_messages(&latest_messages, &equivocators),
+                &equivocators,
+                1.5,
+                &weights,
+            )
+        };
+
+        let m0 = Message::new(0, Justification::empty(), Log::new(vec![1, 2, 3]));
+        let m1 = Message::new(1, Justification::empty(), Log::new(vec![1, 2]));
+        let m2 = Message::new(2, Justification::empty(), Log::new(vec![1, 4]));
+        let estimate = Log::new(vec![1, 2, 3]);
+        // Nobody has seen the others estimates yet.
+        assert_eq!(finalized_len(&estimate, &[&m0, &m1, &m2]), 0);
+
+        let mut justification = Justification::empty();
+        justification.insert(m0.clone());
+        justification.insert(m1.clone());
+        justification.insert(m2.clone());
+        let m3 = Message::new(0, justification.clone(), Log::new(vec![1, 2, 3, 5]));
+        let m4 = Message::new(1, justification.clone(), Log::new(vec![1, 2, 3]));
+        let m5 = Message::new(2, justification, Log::new(vec![1, 4, 6]));
+        // Validators 0 and 1 have seen each other estimating [1, 2] but not [1, 2, 3].
+        assert_eq!(finalized_len(&estimate, &[&m3, &m4, &m5]), 2);
+        assert_eq!(finalized_len(&Log::new(vec![1, 4, 6]), &[&m3, &m4, &m5]), 1);
+        assert_eq!(finalized_len(&Log::new(vec![7]), &[&m3, &m4, &m5]), 0);
+    }
+}
@@ -0,0 +1,250 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use crate::estimator::Estimator;
+use crate::justification::LatestMessagesHonest;
+use crate::util::weight::{WeightUnit, Zero};
+use crate::validator;
+
+/// Integer-like consensus value agreed upon with the weighted median of the latest honest
+/// estimates, e.g. for a gas limit or a fee. `V` is the type of the validators' names.
+///
+/// The estimate is the lower weighted median: the smallest value such that the validators
+/// estimating this value or a smaller one hold at least half of the total weight. When exactly
+/// half of the weight is on values up to `a` and the other half on values greater than `a`, `a` is
+/// chosen.
+///
+/// Only validators with a positive weight are counted. As for the GHOST fork choice, the latest
+/// messages of validators that are not in the weights are ignored.
+///
+/// # Example
+///
+/// ```
+/// use core_cbc_casper::estimator::{Estimator, Median};
+/// use core_cbc_casper::justification::{Justification, LatestMessages, LatestMessagesHonest};
+/// use core_cbc_casper::message::Message;
+/// use core_cbc_casper::validator;
+///
+/// use std::collections::BTreeSet;
+///
+/// type GasLimit = Median<u64, u32>;
+///
+/// let weights = validator::Weights::new(vec![(0, 1.0), (1, 1.0), (2, 3.0)].into_iter().collect());
+///
+/// let mut latest_messages = LatestMessages::empty();
+/// latest_messages.update(&Message::new(0, Justification::empty(), GasLimit::new(8_000_000)));
+/// latest_messages.update(&Message::new(1, Justification::empty(), GasLimit::new(10_000_000)));
+/// latest_messages.update(&Message::new(2, Justification::empty(), GasLimit::new(12_000_000)));
+///
+/// let estimate = GasLimit::estimate(
+///     &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+///     &weights,
+/// )
+/// .unwrap();
+/// assert_eq!(*estimate.value(), 12_000_000);
+/// ```
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Median<T, V> {
+    value: T,
+    validator_name: PhantomData<V>,
+}
+
+impl<T, V> Median<T, V> {
+    pub fn new(value: T) -> Self {
+        Median {
+            value,
+            validator_name: PhantomData,
+        }
+    }
+
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    pub fn into_value(self) -> T {
+        self.value
+    }
+}
+
+impl<T: Debug, V> Debug for Median<T, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self.value)
+    }
+}
+
+/// Only the value is serialized.
+impl<T: serde::Serialize, V> serde::Serialize for Median<T, V> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(serializer)
+    }
+}
+
+/// The latest honest messages do not contain any message from a validator with a positive
+/// weight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MedianError;
+
+impl std::fmt::Display for MedianError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "No weighted estimate to take the median of")
+    }
+}
+
+impl std::error::Error for MedianError {}
+
+impl<T, V> Estimator for Median<T, V>
+where
+    T: Ord + Clone + Hash + Send + Sync + Debug + serde::Serialize,
+    V: validator::ValidatorName,
+{
+    type ValidatorName = V;
+    type Error = MedianError;
+
+    fn estimate<U: WeightUnit>(
+        latest_messages: &LatestMessagesHonest<Self>,
+        validators_weights: &validator::Weights<V, U>,
+    ) -> Result<Self, Self::Error> {
+        let mut values_weights: BTreeMap<&T, U> = BTreeMap::new();
+        for message in latest_messages.iter() {
+            match validators_weights.weight(message.sender()) {
+                Ok(weight) if weight > <U as Zero<U>>::ZERO => {
+                    let value_weight = values_weights
+                        .entry(message.estimate().value())
+                        .or_insert(<U as Zero<U>>::ZERO);
+                    *value_weight += weight;
+                }
+                _ => (),
+            }
+        }
+
+        // Summing in the same order as the running weight below, so that exact ties are found.
+        let total_weight = values_weights
+            .values()
+            .fold(<U as Zero<U>>::ZERO, |acc, weight| acc + *weight);
+        let mut running_weight = <U as Zero<U>>::ZERO;
+        for (value, weight) in values_weights {
+            running_weight += weight;
+            if running_weight + running_weight >= total_weight {
+                return Ok(Median::new(value.clone()));
+            }
+        }
+        Err(MedianError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use crate::justification::{Justification, LatestMessages};
+    use crate::message::Message;
+
+    fn estimate<V: validator::ValidatorName>(
+        estimates: &[(V, u32)],
+        weights: &[(V, f64)],
+    ) -> Result<u32, MedianError> {
+        let mut latest_messages = LatestMessages::empty();
+        for (validator, value) in estimates.iter() {
+            latest_messages.update(&Message::new(
+                validator.clone(),
+                Justification::empty(),
+                Median::new(*value),
+            ));
+        }
+        Median::estimate(
+            &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+            &validator::Weights::new(weights.iter().cloned().collect()),
+        )
+        .map(Median::into_value)
+    }
+
+    #[test]
+    fn median() {
+        let weights = [(0, 1.0), (1, 1.0), (2, 1.0)];
+        assert_eq!(estimate(&[(0, 7), (1, 3), (2, 5)], &weights), Ok(5));
+        assert_eq!(estimate(&[(0, 7), (1, 7), (2, 5)], &weights), Ok(7));
+        assert_eq!(estimate(&[(0, 4)], &weights), Ok(4));
+    }
+
+    #[test]
+    fn median_weighted() {
+        let weights = [(0, 1.0), (1, 1.0), (2, 3.0)];
+        assert_eq!(estimate(&[(0, 1), (1, 2), (2, 3)], &weights), Ok(3));
+        assert_eq!(estimate(&[(0, 3), (1, 2), (2, 1)], &weights), Ok(1));
+        assert_eq!(estimate(&[(0, 1), (1, 3), (2, 2)], &weights), Ok(2));
+    }
+
+    #[test]
+    fn median_weighted_ties() {
+        // Half of the weight on each side: the lower value is chosen.
+        assert_eq!(estimate(&[(0, 10), (1, 20)], &[(0, 1.0), (1, 1.0)]), Ok(10));
+        assert_eq!(
+            estimate(
+                &[(0, 10), (1, 20), (2, 30)],
+                &[(0, 1.0), (1, 1.0), (2, 2.0)]
+            ),
+            Ok(20)
+        );
+        assert_eq!(
+            estimate(
+                &[(0, 30), (1, 10), (2, 20)],
+                &[(0, 0.25), (1, 0.5), (2, 0.25)]
+            ),
+            Ok(10)
+        );
+    }
+
+    #[test]
+    fn median_unknown_validators() {
+        let weights = [(0, 1.0), (1, 1.0), (2, 0.0)];
+        // Validator 3 is not in the weights and validator 2 has no weight.
+        assert_eq!(estimate(&[(0, 1), (1, 2), (2, 0), (3, 0)], &weights), Ok(1));
+        assert_eq!(estimate(&[(2, 0), (3, 0)], &weights), Err(MedianError));
+        assert_eq!(estimate::<u32>(&[], &weights), Err(MedianError));
+    }
+
+    #[test]
+    fn median_validator_name() {
+        #[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, serde_derive::Serialize)]
+        struct Name(&'static str);
+
+        impl validator::ValidatorName for Name {}
+
+        let weights = [
+            (Name("alice"), 2.0),
+            (Name("bob"), 1.0),
+            (Name("carol"), 1.0),
+        ];
+        assert_eq!(
+            estimate(
+                &[(Name("alice"), 5), (Name("bob"), 1), (Name("carol"), 9)],
+                &weights
+            ),
+            Ok(5)
+        );
+    }
+}
@@ -26,6 +26,21 @@ use crate::justification::LatestMessagesHonest;
 use crate::util::weight::WeightUnit;
 use crate::validator;
 
+mod binary;
+mod list;
+mod median;
+mod plurality;
+mod product;
+
+pub use self::binary::{Binary, Majority, NoQuorum, Quorum, TwoThirds};
+pub use self::list::ListEstimator;
+pub use self::median::{Median, MedianError};
+pub use self::plurality::{weighted_plurality, PluralityError, TieBreaking};
+pub use self::product::{ProductCache, ProductError};
+pub use core_cbc_casper_derive::PluralityEstimator;
+
+pub(crate) use self::list::prefix_ghost;
+
 /// Describes an estimate, or a value of the consensus at a certain time. Implementing this trait
 /// allows to produce an estimate given the set of [`latest messages`] and the set of [`validators`] and
 /// their [`weights`].
@@ -45,7 +60,7 @@ use crate::validator;
 /// use core_cbc_casper::estimator::Estimator;
 /// use core_cbc_casper::util::weight::WeightUnit;
 ///
-/// use std::collections::HashSet;
+/// use std::collections::BTreeSet;
 ///
 /// // An error type is needed for the different ways the estimate functions could fail. This
 /// // example is too minimalistic for this to be a concern.
@@ -104,7 +119,7 @@ use crate::validator;
 ///     Value::estimate(
 ///         &LatestMessagesHonest::from_latest_messages(
 ///             &latest_messages,
-///             &HashSet::new(),
+///             &BTreeSet::new(),
 ///         ),
 ///         &weights,
 ///     ).unwrap().boolean
@@ -116,7 +131,7 @@ use crate::validator;
 ///     !Value::estimate(
 ///         &LatestMessagesHonest::from_latest_messages(
 ///             &latest_messages,
-///             &HashSet::new(),
+///             &BTreeSet::new(),
 ///         ),
 ///         &weights,
 ///     ).unwrap().boolean
@@ -132,3 +147,104 @@ pub trait Estimator: Hash + Eq + Clone + Send + Sync + Debug + serde::Serialize
         validators_weights: &validator::Weights<Self::ValidatorName, U>,
     ) -> Result<Self, Self::Error>;
 }
+
+/// Estimator that consults application state, e.g. to reject blocks with invalid transactions or
+/// to read the parameters of the current epoch, given by the caller as a context `C`.
+///
+/// Every [`Estimator`] is a contextual estimator with the unit context `()`, for which the
+/// context is ignored. The estimate functions taking a context, such as
+/// [`Message::from_validator_state_with_context`], therefore work with every estimator.
+///
+/// [`Estimator`]: trait.Estimator.html
+/// [`Message::from_validator_state_with_context`]: ../message/struct.Message.html#method.from_validator_state_with_context
+///
+/// # Example
+///
+/// ```
+/// use core_cbc_casper::estimator::{ContextualEstimator, Estimator, Median, MedianError};
+/// use core_cbc_casper::justification::{Justification, LatestMessages, LatestMessagesHonest};
+/// use core_cbc_casper::message::Message;
+/// use core_cbc_casper::util::weight::WeightUnit;
+/// use core_cbc_casper::validator;
+///
+/// use std::collections::BTreeSet;
+///
+/// // A gas limit whose estimate is capped by the current epoch's maximum.
+/// #[derive(Debug, Hash, Clone, PartialEq, Eq, serde_derive::Serialize)]
+/// struct GasLimit(u64);
+///
+/// struct Epoch {
+///     max_gas_limit: u64,
+/// }
+///
+/// impl Estimator for GasLimit {
+///     type ValidatorName = u32;
+///     type Error = MedianError;
+///
+///     fn estimate<U: WeightUnit>(
+///         latest_messages: &LatestMessagesHonest<Self>,
+///         validators_weights: &validator::Weights<u32, U>,
+///     ) -> Result<Self, Self::Error> {
+///         let max_gas_limit = std::u64::MAX;
+///         Self::estimate_with_context(latest_messages, validators_weights, &Epoch { max_gas_limit })
+///     }
+/// }
+///
+/// impl ContextualEstimator<Epoch> for GasLimit {
+///     fn estimate_with_context<U: WeightUnit>(
+///         latest_messages: &LatestMessagesHonest<Self>,
+///         validators_weights: &validator::Weights<u32, U>,
+///         epoch: &Epoch,
+///     ) -> Result<Self, Self::Error> {
+///         let mut median = LatestMessages::empty();
+///         for message in latest_messages.iter() {
+///             median.update(&Message::new(
+///                 *message.sender(),
+///                 Justification::empty(),
+///                 Median::<u64, u32>::new(message.estimate().0),
+///             ));
+///         }
+///         let median = Median::estimate(
+///             &LatestMessagesHonest::from_latest_messages(&median, &BTreeSet::new()),
+///             validators_weights,
+///         )?;
+///         Ok(GasLimit(epoch.max_gas_limit.min(median.into_value())))
+///     }
+/// }
+///
+/// let weights = validator::Weights::new(vec![(0, 1.0), (1, 1.0)].into_iter().collect());
+/// let mut latest_messages = LatestMessages::empty();
+/// latest_messages.update(&Message::new(0, Justification::empty(), GasLimit(8_000_000)));
+/// latest_messages.update(&Message::new(1, Justification::empty(), GasLimit(10_000_000)));
+/// let latest_messages_honest =
+///     LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new());
+///
+/// assert_eq!(
+///     latest_messages_honest.make_estimate(&weights).unwrap(),
+///     GasLimit(8_000_000),
+/// );
+/// assert_eq!(
+///     latest_messages_honest
+///         .make_estimate_with_context(&weights, &Epoch { max_gas_limit: 5_000_000 })
+///         .unwrap(),
+///     GasLimit(5_000_000),
+/// );
+/// ```
+pub trait ContextualEstimator<C>: Estimator {
+    /// Chooses an estimate from a set of latest messages given the context.
+    fn estimate_with_context<U: WeightUnit>(
+        latest_messages: &LatestMessagesHonest<Self>,
+        validators_weights: &validator::Weights<Self::ValidatorName, U>,
+        context: &C,
+    ) -> Result<Self, Self::Error>;
+}
+
+impl<E: Estimator> ContextualEstimator<()> for E {
+    fn estimate_with_context<U: WeightUnit>(
+        latest_messages: &LatestMessagesHonest<Self>,
+        validators_weights: &validator::Weights<Self::ValidatorName, U>,
+        _context: &(),
+    ) -> Result<Self, Self::Error> {
+        E::estimate(latest_messages, validators_weights)
+    }
+}
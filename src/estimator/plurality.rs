@@ -0,0 +1,188 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::estimator::Estimator;
+use crate::justification::LatestMessagesHonest;
+use crate::util::hash::Hash;
+use crate::util::id::Id;
+use crate::util::weight::{WeightUnit, Zero};
+use crate::validator;
+
+/// Rule choosing between the values with the same highest weight in [`weighted_plurality`].
+///
+/// [`weighted_plurality`]: fn.weighted_plurality.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TieBreaking {
+    /// The value coming first in the given values wins.
+    First,
+    /// The value with the smallest hash of its serialization wins.
+    SmallestHash,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PluralityError;
+
+impl std::fmt::Display for PluralityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "No value to choose from")
+    }
+}
+
+impl std::error::Error for PluralityError {}
+
+/// Wrapper giving any estimate the content identifier of its serialization.
+pub(crate) struct Serialized<'z, E>(pub(crate) &'z E);
+
+impl<'z, E: serde::Serialize> serde::Serialize for Serialized<'z, E> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'z, E: serde::Serialize> Id for Serialized<'z, E> {
+    type ID = Hash;
+}
+
+/// Chooses among `values` the one with the highest total weight of validators estimating it in
+/// their latest honest messages, breaking ties with `tie_breaking`. Validators that are not in
+/// the weights are ignored. When no validator estimates any of the values, all the values are
+/// tied.
+///
+/// This is the estimate of the enums deriving [`PluralityEstimator`], where `values` are the
+/// variants ordered by preference.
+///
+/// [`PluralityEstimator`]: derive.PluralityEstimator.html
+pub fn weighted_plurality<E: Estimator, U: WeightUnit>(
+    latest_messages: &LatestMessagesHonest<E>,
+    validators_weights: &validator::Weights<E::ValidatorName, U>,
+    values: &[E],
+    tie_breaking: TieBreaking,
+) -> Result<E, PluralityError> {
+    let mut values_weights = vec![<U as Zero<U>>::ZERO; values.len()];
+    for message in latest_messages.iter() {
+        if let Ok(weight) = validators_weights.weight(message.sender()) {
+            if let Some(index) = values.iter().position(|value| value == message.estimate()) {
+                values_weights[index] += weight;
+            }
+        }
+    }
+
+    let mut best: Option<(&E, U)> = None;
+    for (value, weight) in values.iter().zip(values_weights) {
+        best = match best {
+            Some((best_value, best_weight))
+                if weight < best_weight
+                    || weight == best_weight
+                        && (tie_breaking == TieBreaking::First
+                            || Serialized(best_value).id() < Serialized(value).id()) =>
+            {
+                Some((best_value, best_weight))
+            }
+            _ => Some((value, weight)),
+        };
+    }
+    best.map(|(value, _)| value.clone()).ok_or(PluralityError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use crate::justification::{Justification, LatestMessages};
+    use crate::message::Message;
+    use crate::IntegerWrapper;
+
+    fn estimate(
+        estimates: &[(u32, u32)],
+        weights: &[f64],
+        values: &[u32],
+        tie_breaking: TieBreaking,
+    ) -> Result<u32, PluralityError> {
+        let mut latest_messages = LatestMessages::empty();
+        for (validator, value) in estimates.iter() {
+            latest_messages.update(&Message::new(
+                *validator,
+                Justification::empty(),
+                IntegerWrapper(*value),
+            ));
+        }
+        let values: Vec<_> = values.iter().cloned().map(IntegerWrapper).collect();
+        weighted_plurality(
+            &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+            &validator::Weights::new((0..).zip(weights.iter().cloned()).collect()),
+            &values,
+            tie_breaking,
+        )
+        .map(|value| value.0)
+    }
+
+    #[test]
+    fn weighted_plurality_highest_weight() {
+        let weights = [1.0, 1.0, 1.0, 2.5];
+        let estimates = [(0, 0), (1, 0), (2, 1), (3, 2)];
+        assert_eq!(
+            estimate(&estimates, &weights, &[0, 1, 2], TieBreaking::First),
+            Ok(2)
+        );
+        // Values that are not given and unknown validators are ignored.
+        let estimates = [(0, 0), (1, 0), (2, 1), (3, 2), (4, 1), (5, 1)];
+        assert_eq!(
+            estimate(&estimates, &weights, &[0, 1], TieBreaking::First),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn weighted_plurality_tie_breaking() {
+        let weights = [1.0, 1.0, 2.0];
+        let estimates = [(0, 0), (1, 0), (2, 1)];
+        assert_eq!(
+            estimate(&estimates, &weights, &[0, 1], TieBreaking::First),
+            Ok(0)
+        );
+        assert_eq!(
+            estimate(&estimates, &weights, &[1, 0], TieBreaking::First),
+            Ok(1)
+        );
+        let by_hash = estimate(&estimates, &weights, &[0, 1], TieBreaking::SmallestHash);
+        assert_eq!(
+            estimate(&estimates, &weights, &[1, 0], TieBreaking::SmallestHash),
+            by_hash
+        );
+        let smallest_hash =
+            if Serialized(&IntegerWrapper(0)).id() < Serialized(&IntegerWrapper(1)).id() {
+                0
+            } else {
+                1
+            };
+        assert_eq!(by_hash, Ok(smallest_hash));
+    }
+
+    #[test]
+    fn weighted_plurality_no_estimate() {
+        assert_eq!(estimate(&[], &[1.0], &[2, 1], TieBreaking::First), Ok(2));
+        assert_eq!(
+            estimate(&[(0, 1)], &[1.0], &[], TieBreaking::First),
+            Err(PluralityError)
+        );
+    }
+}
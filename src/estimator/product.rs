@@ -0,0 +1,435 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use std::sync::RwLock;
+
+use crate::estimator::{ContextualEstimator, Estimator};
+use crate::justification::{Justification, LatestMessagesHonest};
+use crate::message::Message;
+use crate::util::hash::Hash;
+use crate::util::id::Id;
+use crate::util::weight::WeightUnit;
+use crate::validator;
+
+/// Error of one of the components of a product estimate, holding the error of the component's
+/// estimator.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProductError<A, B, C = Infallible, D = Infallible, E = Infallible> {
+    First(A),
+    Second(B),
+    Third(C),
+    Fourth(D),
+    Fifth(E),
+}
+
+impl<A, B, C, D, E> ProductError<A, B, C, D, E> {
+    /// Returns the index of the component whose estimator failed.
+    pub fn component(&self) -> usize {
+        match self {
+            ProductError::First(_) => 0,
+            ProductError::Second(_) => 1,
+            ProductError::Third(_) => 2,
+            ProductError::Fourth(_) => 3,
+            ProductError::Fifth(_) => 4,
+        }
+    }
+}
+
+impl<A, B, C, D, E> std::fmt::Display for ProductError<A, B, C, D, E>
+where
+    A: std::fmt::Display,
+    B: std::fmt::Display,
+    C: std::fmt::Display,
+    D: std::fmt::Display,
+    E: std::fmt::Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let error = match self {
+            ProductError::First(error) => error.to_string(),
+            ProductError::Second(error) => error.to_string(),
+            ProductError::Third(error) => error.to_string(),
+            ProductError::Fourth(error) => error.to_string(),
+            ProductError::Fifth(error) => error.to_string(),
+        };
+        writeln!(
+            f,
+            "Estimate of component {} failed: {}",
+            self.component(),
+            error.trim_end()
+        )
+    }
+}
+
+impl<A, B, C, D, E> std::error::Error for ProductError<A, B, C, D, E>
+where
+    A: std::error::Error,
+    B: std::error::Error,
+    C: std::error::Error,
+    D: std::error::Error,
+    E: std::error::Error,
+{
+}
+
+/// Projections of messages on the components of the product estimator `P`, kept by the caller
+/// across estimates so that each message is only projected once. Given as the context of
+/// [`ContextualEstimator::estimate_with_context`], only the messages received since the last
+/// estimate are projected. The projections are kept until the cache is cleared or dropped.
+///
+/// [`ContextualEstimator::estimate_with_context`]: trait.ContextualEstimator.html#tymethod.estimate_with_context
+pub struct ProductCache<P> {
+    projections: RwLock<HashMap<(usize, Hash), Box<dyn Any + Send + Sync>>>,
+    product: PhantomData<P>,
+}
+
+impl<P> ProductCache<P> {
+    pub fn new() -> Self {
+        ProductCache {
+            projections: RwLock::new(HashMap::new()),
+            product: PhantomData,
+        }
+    }
+
+    /// Forgets all the projections.
+    pub fn clear(&mut self) {
+        if let Ok(projections) = self.projections.get_mut() {
+            projections.clear();
+        }
+    }
+}
+
+impl<P> Default for ProductCache<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Projects the messages on one of the components of their estimates, with their justifications.
+/// The messages already projected on that component are read from the cache, and the new
+/// projections are added to it.
+fn project_messages<'z, E, F, P>(
+    messages: impl Iterator<Item = &'z Message<E>>,
+    component: usize,
+    project: &P,
+    cache: &ProductCache<E>,
+) -> Vec<Message<F>>
+where
+    E: Estimator + 'z,
+    F: Estimator<ValidatorName = E::ValidatorName> + 'static,
+    P: Fn(&E) -> F,
+{
+    let messages: Vec<&Message<E>> = messages.collect();
+    let mut projected: HashMap<Hash, Message<F>> = HashMap::new();
+    let mut new_projections = Vec::new();
+    {
+        let cached = cache.projections.read().ok();
+        let cached_projection = |message: &Message<E>| {
+            cached
+                .as_ref()
+                .and_then(|cached| cached.get(&(component, message.id())))
+                .and_then(|projection| projection.downcast_ref::<Message<F>>())
+                .cloned()
+        };
+        // Post-order traversal of the justifications: a message is projected once all the
+        // messages in its justification have been.
+        let mut stack: Vec<(&Message<E>, bool)> =
+            messages.iter().map(|&message| (message, false)).collect();
+        while let Some((message, justification_projected)) = stack.pop() {
+            if projected.contains_key(&message.id()) {
+                continue;
+            }
+            if let Some(projection) = cached_projection(message) {
+                projected.insert(message.id(), projection);
+            } else if justification_projected {
+                let mut justification = Justification::empty();
+                for justified in message.justification().iter() {
+                    justification.insert(projected[&justified.id()].clone());
+                }
+                let projection = Message::new(
+                    message.sender().clone(),
+                    justification,
+                    project(message.estimate()),
+                );
+                new_projections.push((message.id(), projection.clone()));
+                projected.insert(message.id(), projection);
+            } else {
+                stack.push((message, true));
+                stack.extend(
+                    message
+                        .justification()
+                        .iter()
+                        .filter(|justified| !projected.contains_key(&justified.id()))
+                        .map(|justified| (justified, false)),
+                );
+            }
+        }
+    }
+    if let Ok(mut cached) = cache.projections.write() {
+        for (id, projection) in new_projections {
+            cached.insert((component, id), Box::new(projection));
+        }
+    }
+    messages
+        .into_iter()
+        .map(|message| projected[&message.id()].clone())
+        .collect()
+}
+
+/// Runs the estimator of one component on the latest honest messages projected on it.
+fn estimate_component<E, F, U, P>(
+    latest_messages: &LatestMessagesHonest<E>,
+    validators_weights: &validator::Weights<E::ValidatorName, U>,
+    component: usize,
+    project: P,
+    cache: &ProductCache<E>,
+) -> Result<F, F::Error>
+where
+    E: Estimator,
+    F: Estimator<ValidatorName = E::ValidatorName> + 'static,
+    U: WeightUnit,
+    P: Fn(&E) -> F,
+{
+    let latest_messages = LatestMessagesHonest::from_honest_messages(project_messages(
+        latest_messages.iter(),
+        component,
+        &project,
+        cache,
+    ));
+    F::estimate(&latest_messages, validators_weights)
+}
+
+/// Implements the [`Estimator`] trait on the tuples of estimators of the same validators, to run
+/// several consensus instances, e.g. a blockchain and a binary flag, in a single message stream
+/// sharing one justification per message.
+///
+/// Each component is estimated by its own estimator on the latest honest messages projected on
+/// the component, with their justifications. As the latest honest messages are selected before
+/// the projection, a validator equivocating on any component is an equivocator for all of them.
+///
+/// Tuples are also [`ContextualEstimator`]s given a [`ProductCache`], to keep the projections
+/// from one estimate to the next.
+///
+/// [`Estimator`]: trait.Estimator.html
+/// [`ContextualEstimator`]: trait.ContextualEstimator.html
+/// [`ProductCache`]: struct.ProductCache.html
+macro_rules! product_estimator {
+    ($first:ident $(, $component:ident)*; $($index:tt $variant:ident),*) => {
+        impl<$first, $($component),*> Estimator for ($first, $($component),*)
+        where
+            $first: Estimator + 'static,
+            $($component: Estimator<ValidatorName = <$first as Estimator>::ValidatorName> + 'static,)*
+        {
+            type ValidatorName = <$first as Estimator>::ValidatorName;
+            type Error = ProductError<<$first as Estimator>::Error, $(<$component as Estimator>::Error),*>;
+
+            fn estimate<U: WeightUnit>(
+                latest_messages: &LatestMessagesHonest<Self>,
+                validators_weights: &validator::Weights<Self::ValidatorName, U>,
+            ) -> Result<Self, Self::Error> {
+                Self::estimate_with_context(latest_messages, validators_weights, &ProductCache::new())
+            }
+        }
+
+        impl<$first, $($component),*> ContextualEstimator<ProductCache<($first, $($component),*)>>
+            for ($first, $($component),*)
+        where
+            $first: Estimator + 'static,
+            $($component: Estimator<ValidatorName = <$first as Estimator>::ValidatorName> + 'static,)*
+        {
+            fn estimate_with_context<U: WeightUnit>(
+                latest_messages: &LatestMessagesHonest<Self>,
+                validators_weights: &validator::Weights<Self::ValidatorName, U>,
+                cache: &ProductCache<Self>,
+            ) -> Result<Self, Self::Error> {
+                Ok(($(
+                    estimate_component(
+                        latest_messages,
+                        validators_weights,
+                        $index,
+                        |estimate: &Self| estimate.$index.clone(),
+                        cache,
+                    )
+                    .map_err(ProductError::$variant)?,
+                )*))
+            }
+        }
+    };
+}
+
+product_estimator!(A, B; 0 First, 1 Second);
+product_estimator!(A, B, C; 0 First, 1 Second, 2 Third);
+product_estimator!(A, B, C, D; 0 First, 1 Second, 2 Third, 3 Fourth);
+product_estimator!(A, B, C, D, E; 0 First, 1 Second, 2 Third, 3 Fourth, 4 Fifth);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use crate::estimator::{Binary, Median, MedianError};
+    use crate::justification::LatestMessages;
+    use crate::VoteCount;
+
+    type Flag = Binary<u32>;
+    type Parameter = Median<u32, u32>;
+
+    fn latest_honest<E: Estimator>(messages: &[&Message<E>]) -> LatestMessagesHonest<E> {
+        let mut latest_messages = LatestMessages::empty();
+        for message in messages {
+            latest_messages.update(message);
+        }
+        LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new())
+    }
+
+    #[test]
+    fn product_components() {
+        let weights =
+            validator::Weights::new(vec![(0, 1.0), (1, 1.0), (2, 3.0)].into_iter().collect());
+        let estimates = [
+            (Flag::new(true), Parameter::new(10)),
+            (Flag::new(true), Parameter::new(20)),
+            (Flag::new(false), Parameter::new(30)),
+        ];
+        let messages: Vec<_> = (0..)
+            .zip(estimates.iter())
+            .map(|(validator, estimate)| {
+                Message::new(validator, Justification::empty(), estimate.clone())
+            })
+            .collect();
+        let messages: Vec<_> = messages.iter().collect();
+
+        assert_eq!(
+            <(Flag, Parameter)>::estimate(&latest_honest(&messages), &weights),
+            Ok((Flag::new(false), Parameter::new(30)))
+        );
+
+        let weights =
+            validator::Weights::new(vec![(0, 1.0), (1, 1.0), (2, 1.0)].into_iter().collect());
+        assert_eq!(
+            <(Flag, Parameter)>::estimate(&latest_honest(&messages), &weights),
+            Ok((Flag::new(true), Parameter::new(20)))
+        );
+    }
+
+    #[test]
+    fn product_projected_justifications() {
+        let weights =
+            validator::Weights::new(vec![(0, 1.0), (1, 1.0), (2, 1.0)].into_iter().collect());
+        // VoteCount counts the votes found in the justifications, which must be projected.
+        let vote = |validator, vote| {
+            let message = VoteCount::create_vote_message(validator, vote);
+            Message::new(
+                validator,
+                Justification::empty(),
+                (*message.estimate(), Flag::new(vote)),
+            )
+        };
+        let m0 = vote(0, true);
+        let m1 = vote(1, false);
+        let m2 = vote(2, true);
+        let mut justification = Justification::empty();
+        justification.insert(m0.clone());
+        justification.insert(m1.clone());
+        justification.insert(m2.clone());
+        let m3 = Message::new(
+            0,
+            justification,
+            (VoteCount { yes: 2, no: 1 }, Flag::new(true)),
+        );
+
+        assert_eq!(
+            <(VoteCount, Flag)>::estimate(&latest_honest(&[&m1, &m2, &m3]), &weights).unwrap(),
+            (VoteCount { yes: 2, no: 1 }, Flag::new(true)),
+        );
+    }
+
+    #[test]
+    fn product_equivocator() {
+        let weights = validator::Weights::new(vec![(0, 3.0), (1, 1.0)].into_iter().collect());
+        // Validator 0 only equivocates on the parameter, but is ignored for the flag as well.
+        let m0 = Message::new(
+            0,
+            Justification::empty(),
+            (Flag::new(true), Parameter::new(1)),
+        );
+        let m1 = Message::new(
+            0,
+            Justification::empty(),
+            (Flag::new(true), Parameter::new(2)),
+        );
+        let m2 = Message::new(
+            1,
+            Justification::empty(),
+            (Flag::new(false), Parameter::new(3)),
+        );
+
+        assert_eq!(
+            <(Flag, Parameter)>::estimate(&latest_honest(&[&m0, &m1, &m2]), &weights),
+            Ok((Flag::new(false), Parameter::new(3)))
+        );
+    }
+
+    #[test]
+    fn product_deep_history() {
+        let weights = validator::Weights::new(vec![(0, 1.0), (1, 1.0)].into_iter().collect());
+        let mut cache = ProductCache::new();
+        let mut top = Message::new(
+            0,
+            Justification::empty(),
+            (Flag::new(true), Parameter::new(0)),
+        );
+        // Estimating after each message only projects the new one, the history being cached.
+        for i in 1..=100_000 {
+            let estimate = <(Flag, Parameter)>::estimate_with_context(
+                &latest_honest(&[&top]),
+                &weights,
+                &cache,
+            );
+            assert_eq!(estimate, Ok((Flag::new(true), Parameter::new(i - 1))));
+            let mut justification = Justification::empty();
+            justification.insert(top);
+            top = Message::new(i % 2, justification, (Flag::new(true), Parameter::new(i)));
+        }
+
+        // Both components of every message but the last one are cached.
+        assert_eq!(cache.projections.read().unwrap().len(), 2 * 100_000);
+        cache.clear();
+        assert!(cache.projections.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn product_error() {
+        let weights = validator::Weights::new(vec![(0, 1.0)].into_iter().collect());
+        let error = <(Flag, Parameter)>::estimate(&latest_honest(&[]), &weights).unwrap_err();
+        assert_eq!(error, ProductError::Second(MedianError));
+        assert_eq!(error.component(), 1);
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Estimate of component 1 failed: {}\n",
+                MedianError.to_string().trim_end()
+            )
+        );
+    }
+}
@@ -18,18 +18,22 @@
 // You should have received a copy of the GNU Affero General Public License along with the Core CBC
 // Rust Library. If not, see <https://www.gnu.org/licenses/>.
 
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{btree_map, btree_set, BTreeMap, BTreeSet, HashSet};
 use std::fmt::{Debug, Formatter};
 
 use rayon::iter::IntoParallelRefIterator;
 
-use crate::estimator::Estimator;
-use crate::message::Message;
+use crate::estimator::{ContextualEstimator, Estimator};
+use crate::message::{self, Message};
+use crate::util::hash::Hash;
+use crate::util::id::Id;
 use crate::util::weight::{WeightUnit, Zero};
 use crate::validator;
 
 /// This struct holds the set of the [`Message`] that justify
-/// the current message. It works like a `Vec`.
+/// the current message. It works like a `Vec` that keeps the insertion order for iteration, but
+/// it has set semantics for equality and hashing: two justifications holding the same messages
+/// are equal whatever the order in which the messages were inserted.
 ///
 /// # Example
 ///
@@ -54,9 +58,21 @@ use crate::validator;
 ///
 /// [`Message`]: ../message/struct.Message.html
 /// [`VoteCount`]: ../struct.VoteCount.html
-#[derive(Eq, PartialEq, Clone, Hash)]
+#[derive(Eq, Clone)]
 pub struct Justification<E: Estimator>(Vec<Message<E>>);
 
+impl<E: Estimator> PartialEq for Justification<E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.sorted_ids() == other.sorted_ids()
+    }
+}
+
+impl<E: Estimator> std::hash::Hash for Justification<E> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.sorted_ids().hash(state)
+    }
+}
+
 impl<E: Estimator> Justification<E> {
     /// Creates an empty justification.
     pub fn empty() -> Self {
@@ -99,6 +115,41 @@ impl<E: Estimator> Justification<E> {
         self.0.is_empty()
     }
 
+    /// Consumes the justification and returns its messages.
+    pub(crate) fn into_messages(self) -> Vec<Message<E>> {
+        self.0
+    }
+
+    /// Returns the sorted and deduplicated ids of the messages of the justification. This is the
+    /// canonical representation of the justification used to compute message ids.
+    pub fn sorted_ids(&self) -> Vec<Hash> {
+        let mut ids: Vec<_> = self.0.iter().map(Message::id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    /// Returns the transitive reduction of the justification: the messages that are not in the
+    /// dependencies of another message of the justification. Both justifications have the same
+    /// transitive closure, thus yield the same [`LatestMessages`]. Messages from `sender` are always
+    /// kept, so that a message from `sender` justified by the result still references its previous
+    /// message.
+    ///
+    /// [`LatestMessages`]: struct.LatestMessages.html
+    pub fn reduce(&self, sender: &E::ValidatorName) -> Self {
+        let mut justification = Justification::empty();
+        for message in self.iter() {
+            if message.sender() == sender
+                || !self
+                    .iter()
+                    .any(|other| other != message && other.depends(message))
+            {
+                justification.insert(message.clone());
+            }
+        }
+        justification
+    }
+
     /// This function checks if the [`message`] is already contained into the underlying `Vec`. If it
     /// is, it does nothing and returns false. Else, it will push the message in the `Vec` and
     /// returns true.
@@ -120,13 +171,29 @@ impl<E: Estimator> Justification<E> {
     /// [`validators' weights`]: ../validator/struct.Weights.html
     pub fn make_estimate<U: WeightUnit>(
         &self,
-        equivocators: &HashSet<E::ValidatorName>,
+        equivocators: &BTreeSet<E::ValidatorName>,
         validators_weights: &validator::Weights<E::ValidatorName, U>,
     ) -> Result<E, E::Error> {
+        self.make_estimate_with_context(equivocators, validators_weights, &())
+    }
+
+    /// Works like [`make_estimate`] with a [`contextual estimator`] given the `context`.
+    ///
+    /// [`make_estimate`]: #method.make_estimate
+    /// [`contextual estimator`]: ../estimator/trait.ContextualEstimator.html
+    pub fn make_estimate_with_context<U: WeightUnit, C>(
+        &self,
+        equivocators: &BTreeSet<E::ValidatorName>,
+        validators_weights: &validator::Weights<E::ValidatorName, U>,
+        context: &C,
+    ) -> Result<E, E::Error>
+    where
+        E: ContextualEstimator<C>,
+    {
         let latest_messages = LatestMessages::from(self);
         let latest_messages_honest =
             LatestMessagesHonest::from_latest_messages(&latest_messages, equivocators);
-        Estimator::estimate(&latest_messages_honest, validators_weights)
+        E::estimate_with_context(&latest_messages_honest, validators_weights, context)
     }
 
     /// Inserts [`messages`] to the justification, accepting up to the threshold faults by weight.
@@ -203,7 +270,7 @@ impl<E: Estimator> Justification<E> {
         &mut self,
         message: &Message<E>,
         state: &'a mut validator::State<E, U>,
-    ) -> Result<bool, validator::Error<'a, HashMap<E::ValidatorName, U>>> {
+    ) -> Result<bool, validator::Error<'a, BTreeMap<E::ValidatorName, U>>> {
         let is_equivocation = state.latest_messages.equivocate(message);
         if is_equivocation {
             let sender = message.sender();
@@ -236,6 +303,7 @@ impl<E: Estimator> Debug for Justification<E> {
 
 /// LatestMessages is a map between [`validators`] and their latests [`messages`]. Latest messages from a
 /// validator are all their messages that are not in the dependency of another of their messages.
+/// Validators are iterated in ascending order.
 ///
 /// # Example
 ///
@@ -282,12 +350,12 @@ impl<E: Estimator> Debug for Justification<E> {
 /// [`validators`]: ../validator/trait.ValidatorName.html
 /// [`messages`]: ../message/struct.Message.html
 #[derive(Eq, PartialEq, Clone, Debug)]
-pub struct LatestMessages<E: Estimator>(HashMap<E::ValidatorName, HashSet<Message<E>>>);
+pub struct LatestMessages<E: Estimator>(BTreeMap<E::ValidatorName, HashSet<Message<E>>>);
 
 impl<E: Estimator> LatestMessages<E> {
     /// Create an empty set of latest messages.
     pub fn empty() -> Self {
-        LatestMessages(HashMap::new())
+        LatestMessages(BTreeMap::new())
     }
 
     /// Insert a new set of messages for a validator.
@@ -318,7 +386,7 @@ impl<E: Estimator> LatestMessages<E> {
     }
 
     /// Get an iterator over the map.
-    pub fn iter(&self) -> std::collections::hash_map::Iter<E::ValidatorName, HashSet<Message<E>>> {
+    pub fn iter(&self) -> btree_map::Iter<E::ValidatorName, HashSet<Message<E>>> {
         self.0.iter()
     }
 
@@ -332,14 +400,12 @@ impl<E: Estimator> LatestMessages<E> {
     }
 
     /// Get the map keys, i.e. the validators.
-    pub fn keys(&self) -> std::collections::hash_map::Keys<E::ValidatorName, HashSet<Message<E>>> {
+    pub fn keys(&self) -> btree_map::Keys<E::ValidatorName, HashSet<Message<E>>> {
         self.0.keys()
     }
 
     /// Get the map values, i.e. the messages.
-    pub fn values(
-        &self,
-    ) -> std::collections::hash_map::Values<E::ValidatorName, HashSet<Message<E>>> {
+    pub fn values(&self) -> btree_map::Values<E::ValidatorName, HashSet<Message<E>>> {
         self.0.values()
     }
 
@@ -400,26 +466,24 @@ impl<E: Estimator> LatestMessages<E> {
 }
 
 impl<E: Estimator> From<&Justification<E>> for LatestMessages<E> {
-    /// Extracts the latest messages of each validator from a [`justification`].
+    /// Extracts the latest messages of each validator from the transitive closure of a
+    /// [`justification`]. The justification does not need to contain the latest messages
+    /// themselves, e.g. it can be a [`reduced`] justification.
     ///
     /// [`justification`]: ../justification/struct.Justification.html
+    /// [`reduced`]: ../justification/struct.Justification.html#method.reduce
     fn from(justification: &Justification<E>) -> Self {
         let mut latest_messages: LatestMessages<E> = LatestMessages::empty();
-        let mut queue: VecDeque<Message<E>> = justification.iter().cloned().collect();
-        while let Some(message) = queue.pop_front() {
-            if latest_messages.update(&message) {
-                message
-                    .justification()
-                    .iter()
-                    .for_each(|message| queue.push_back(message.clone()));
-            }
+        for (validator, messages) in message::frontier(justification) {
+            latest_messages.insert(validator, messages.into_iter().collect());
         }
         latest_messages
     }
 }
 
 /// Set of latest honest [`messages`] for each [`validator`]. Works like [`LatestMessages`] but ignores
-/// equivocations.
+/// equivocations. Messages are iterated in ascending order of their ids, so that estimators
+/// folding over them behave the same on every node.
 ///
 /// # Example
 ///
@@ -430,7 +494,7 @@ impl<E: Estimator> From<&Justification<E>> for LatestMessages<E> {
 /// use core_cbc_casper::message::Message;
 /// use core_cbc_casper::VoteCount;
 ///
-/// use std::collections::HashSet;
+/// use std::collections::{BTreeSet, HashSet};
 /// use std::iter::FromIterator;
 ///
 /// let mut justification = Justification::empty();
@@ -440,7 +504,7 @@ impl<E: Estimator> From<&Justification<E>> for LatestMessages<E> {
 /// assert_eq!(
 ///     HashSet::<&Message<_>>::from_iter(LatestMessagesHonest::from_latest_messages(
 ///         &LatestMessages::from(&justification),
-///         &HashSet::new(),
+///         &BTreeSet::new(),
 ///     ).iter()),
 ///     HashSet::from_iter(vec![&message_0]),
 /// );
@@ -451,7 +515,7 @@ impl<E: Estimator> From<&Justification<E>> for LatestMessages<E> {
 /// assert_eq!(
 ///     HashSet::<&Message<_>>::from_iter(LatestMessagesHonest::from_latest_messages(
 ///         &LatestMessages::from(&justification),
-///         &HashSet::new(),
+///         &BTreeSet::new(),
 ///     ).iter()),
 ///     HashSet::from_iter(vec![&message_1]),
 /// );
@@ -464,7 +528,7 @@ impl<E: Estimator> From<&Justification<E>> for LatestMessages<E> {
 /// assert_eq!(
 ///     HashSet::<&Message<_>>::from_iter(LatestMessagesHonest::from_latest_messages(
 ///         &LatestMessages::from(&justification),
-///         &HashSet::new(),
+///         &BTreeSet::new(),
 ///     ).iter()),
 ///     HashSet::from_iter(vec![]),
 /// );
@@ -475,12 +539,12 @@ impl<E: Estimator> From<&Justification<E>> for LatestMessages<E> {
 /// [`LatestMessages`]: struct.LatestMessages.html
 /// [`VoteCount`]: ../struct.VoteCount.html
 #[derive(Clone)]
-pub struct LatestMessagesHonest<E: Estimator>(HashSet<Message<E>>);
+pub struct LatestMessagesHonest<E: Estimator>(BTreeSet<Message<E>>);
 
 impl<E: Estimator> LatestMessagesHonest<E> {
     /// Create an empty latest honest messages set.
     fn empty() -> Self {
-        LatestMessagesHonest(HashSet::new())
+        LatestMessagesHonest(BTreeSet::new())
     }
 
     /// Inserts a message in the set.
@@ -488,6 +552,12 @@ impl<E: Estimator> LatestMessagesHonest<E> {
         self.0.insert(message)
     }
 
+    /// Creates the latest honest messages from messages already known to be the latest honest
+    /// ones, e.g. the projections of other latest honest messages.
+    pub(crate) fn from_honest_messages<I: IntoIterator<Item = Message<E>>>(messages: I) -> Self {
+        LatestMessagesHonest(messages.into_iter().collect())
+    }
+
     /// Removes the messages from a validator.
     pub fn remove(&mut self, validator: &E::ValidatorName) {
         self.0.retain(|message| message.sender() != validator);
@@ -496,7 +566,7 @@ impl<E: Estimator> LatestMessagesHonest<E> {
     /// Filters the latest messages to retrieve the latest honest messages and remove equivocators.
     pub fn from_latest_messages(
         latest_messages: &LatestMessages<E>,
-        equivocators: &HashSet<E::ValidatorName>,
+        equivocators: &BTreeSet<E::ValidatorName>,
     ) -> Self {
         latest_messages
             .iter()
@@ -513,7 +583,7 @@ impl<E: Estimator> LatestMessagesHonest<E> {
             })
     }
 
-    pub fn iter(&self) -> std::collections::hash_set::Iter<Message<E>> {
+    pub fn iter(&self) -> btree_set::Iter<Message<E>> {
         self.0.iter()
     }
 
@@ -531,19 +601,67 @@ impl<E: Estimator> LatestMessagesHonest<E> {
     ) -> Result<E, E::Error> {
         E::estimate(&self, validators_weights)
     }
+
+    /// Works like [`make_estimate`] with a [`contextual estimator`] given the `context`.
+    ///
+    /// [`make_estimate`]: #method.make_estimate
+    /// [`contextual estimator`]: ../estimator/trait.ContextualEstimator.html
+    pub fn make_estimate_with_context<U: WeightUnit, C>(
+        &self,
+        validators_weights: &validator::Weights<E::ValidatorName, U>,
+        context: &C,
+    ) -> Result<E, E::Error>
+    where
+        E: ContextualEstimator<C>,
+    {
+        E::estimate_with_context(self, validators_weights, context)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use crate::{IntegerWrapper, VoteCount};
 
-    use std::collections::HashSet;
+    use std::collections::{BTreeSet, HashSet};
     use std::iter::FromIterator;
 
     use crate::justification::{Justification, LatestMessages};
     use crate::message::Message;
     use crate::validator;
 
+    #[test]
+    fn justification_set_semantics() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let v0 = VoteCount::create_vote_message(0, false);
+        let v1 = VoteCount::create_vote_message(1, true);
+
+        let mut justification = Justification::empty();
+        justification.insert(v0.clone());
+        justification.insert(v1.clone());
+        let mut reversed_justification = Justification::empty();
+        reversed_justification.insert(v1.clone());
+        reversed_justification.insert(v0.clone());
+        assert!(!reversed_justification.insert(v1));
+
+        let hash = |justification: &Justification<VoteCount>| {
+            let mut hasher = DefaultHasher::new();
+            justification.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(justification, reversed_justification);
+        assert_eq!(hash(&justification), hash(&reversed_justification));
+        assert_eq!(
+            justification.sorted_ids(),
+            reversed_justification.sorted_ids()
+        );
+
+        let mut partial_justification = Justification::empty();
+        partial_justification.insert(v0);
+        assert_ne!(justification, partial_justification);
+    }
+
     #[test]
     fn faulty_insert_sorted() {
         let v0 = VoteCount::create_vote_message(0, false);
@@ -563,7 +681,7 @@ mod test {
             0.0,
             latest_messages,
             3.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
         let mut justification = Justification::empty();
         let sorted_messages = validator_state
@@ -611,7 +729,7 @@ mod test {
             0.0,
             latest_messages,
             3.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
         let mut justification = Justification::empty();
         justification.faulty_inserts(
@@ -644,7 +762,7 @@ mod test {
             0.0,
             LatestMessages::empty(),
             0.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         let v0 = VoteCount::create_vote_message(0, false);
@@ -822,7 +940,7 @@ mod test {
             0.0,
             LatestMessages::empty(),
             1.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         let v0 = Message::new(0, Justification::empty(), IntegerWrapper::new(0));
@@ -852,7 +970,7 @@ mod test {
             0.0,
             LatestMessages::empty(),
             1.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         let v0 = Message::new(0, Justification::empty(), IntegerWrapper::new(0));
@@ -873,7 +991,10 @@ mod test {
             *validator_state.latests_messages().get(&0).unwrap(),
             HashSet::from_iter(vec![v0, v0_prime]),
         );
-        assert_eq!(*validator_state.equivocators(), HashSet::from_iter(vec![0]));
+        assert_eq!(
+            *validator_state.equivocators(),
+            BTreeSet::from_iter(vec![0])
+        );
         float_eq!(
             validator_state.validators_weights().weight(&0).unwrap(),
             0.0
@@ -888,7 +1009,7 @@ mod test {
             0.0,
             LatestMessages::empty(),
             0.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         let v0 = VoteCount::create_vote_message(0, true);
@@ -908,7 +1029,7 @@ mod test {
             0.0,
             LatestMessages::empty(),
             0.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         let v0 = VoteCount::create_vote_message(0, true);
@@ -931,7 +1052,7 @@ mod test {
             0.0,
             LatestMessages::empty(),
             0.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         let v0 = VoteCount::create_vote_message(0, true);
@@ -958,7 +1079,7 @@ mod test {
             0.0,
             LatestMessages::empty(),
             0.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         let v0 = VoteCount::create_vote_message(0, true);
@@ -981,7 +1102,7 @@ mod test {
             0.0,
             LatestMessages::empty(),
             1.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         let v0 = VoteCount::create_vote_message(0, true);
@@ -1009,7 +1130,7 @@ mod test {
             0.0,
             LatestMessages::empty(),
             1.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         let v0 = VoteCount::create_vote_message(0, true);
@@ -1040,7 +1161,7 @@ mod test {
             0.0,
             LatestMessages::empty(),
             0.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         let v0 = VoteCount::create_vote_message(0, true);
@@ -1144,4 +1265,71 @@ mod test {
             Some(&vec![v0, v0_prime].into_iter().collect())
         );
     }
+
+    #[test]
+    fn justification_reduce() {
+        let v0 = VoteCount::create_vote_message(0, false);
+        let v1 = VoteCount::create_vote_message(1, true);
+        let mut justification = Justification::empty();
+        justification.insert(v0.clone());
+        let m1 = Message::new(1, justification, VoteCount { yes: 0, no: 1 });
+        let mut justification = Justification::empty();
+        justification.insert(m1.clone());
+        let m2 = Message::new(2, justification, VoteCount { yes: 0, no: 1 });
+
+        let mut justification = Justification::empty();
+        justification.insert(v0.clone());
+        justification.insert(v1.clone());
+        justification.insert(m1.clone());
+        justification.insert(m2.clone());
+
+        // v0 and m1 are in the dependencies of m2, v1 is not.
+        let mut expected = Justification::empty();
+        expected.insert(v1.clone());
+        expected.insert(m2.clone());
+        assert_eq!(justification.reduce(&3), expected);
+
+        // The messages of the sender are kept.
+        expected.insert(v0);
+        assert_eq!(justification.reduce(&0), expected);
+    }
+
+    #[test]
+    fn latest_messages_from_reduced_justification() {
+        let v0 = VoteCount::create_vote_message(0, false);
+        let v1 = VoteCount::create_vote_message(1, true);
+        let v1_prime = VoteCount::create_vote_message(1, false);
+        let mut justification = Justification::empty();
+        justification.insert(v0.clone());
+        justification.insert(v1.clone());
+        let m0 = Message::new(0, justification, VoteCount { yes: 1, no: 1 });
+        let mut justification = Justification::empty();
+        justification.insert(m0.clone());
+        justification.insert(v1_prime.clone());
+        let m2 = Message::new(2, justification, VoteCount { yes: 1, no: 2 });
+
+        let mut justification = Justification::empty();
+        justification.insert(m0.clone());
+        justification.insert(v1.clone());
+        justification.insert(v1_prime.clone());
+        justification.insert(m2.clone());
+        let reduced = justification.reduce(&3);
+        assert_eq!(reduced.len(), 1);
+
+        let latest_messages = LatestMessages::from(&reduced);
+        assert_eq!(latest_messages, LatestMessages::from(&justification));
+        assert_eq!(
+            latest_messages.get(&0),
+            Some(&vec![m0].into_iter().collect())
+        );
+        // The equivocation of validator 1 is still visible through m2.
+        assert_eq!(
+            latest_messages.get(&1),
+            Some(&vec![v1, v1_prime].into_iter().collect())
+        );
+        assert_eq!(
+            latest_messages.get(&2),
+            Some(&vec![m2].into_iter().collect())
+        );
+    }
 }
@@ -54,6 +54,18 @@
 //! examples in the documentation and in the examples folder (under development).
 //! It is also instructive to read and run the tests.
 //!
+//! ### Modules
+//!
+//! * `message`, `justification` and `validator`: the messages, their justifications and the
+//!   validators' state and weights.
+//! * `estimator`: the estimator traits and generic estimators, such as the median, binary, list
+//!   and tuple estimators.
+//! * `safety_oracle`: the clique safety oracle.
+//! * `blockchain`: the blockchain, its fork choice rules and tie breakers, and fork monitoring.
+//! * `sharding`: the sharded blockchain estimator.
+//! * `concurrent`: the concurrent schedule consensus.
+//! * `session`: the delta encoded transmission of messages to peers.
+//!
 //! ### Cargo
 //!
 //! You can use this library in your dependencies with
@@ -162,6 +174,8 @@ mod tests_common;
 
 /// Implementation of basic types for a casper based blockchain consensus mechanism.
 pub mod blockchain;
+/// Concurrent schedule consensus on transactions touching independent keys of a state.
+pub mod concurrent;
 pub mod estimator;
 /// Justifications are supposed to “justify” the proposed values. Justifications of messages
 /// are sets of messages that validators has seen and acknowledged while generating messages.
@@ -169,6 +183,12 @@ pub mod justification;
 /// Messages are generated and passed around by validators in the effort of trying to reach
 /// consensus.
 pub mod message;
+/// Safety oracles detecting when validators cannot change their estimates without equivocating.
+pub mod safety_oracle;
+/// Delta encoded transmission of messages between peers.
+pub mod session;
+/// Sharded blockchain consensus built on top of the blockchain module.
+pub mod sharding;
 /// Utility module for various types and components.
 pub mod util;
 /// The consensus forming peers nodes in the network are called validators.
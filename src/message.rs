@@ -18,17 +18,18 @@
 // You should have received a copy of the GNU Affero General Public License along with the Core CBC
 // Rust Library. If not, see <https://www.gnu.org/licenses/>.
 
-use std::collections::HashSet;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt::Debug;
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 
-use rayon::prelude::*;
 use serde::Serialize;
 
-use crate::estimator::Estimator;
+use crate::estimator::{ContextualEstimator, Estimator};
 use crate::justification::{Justification, LatestMessagesHonest};
 use crate::util::hash::Hash;
 use crate::util::id::Id;
+use crate::util::skip_list::{follow_skip, skip_height};
 use crate::util::weight::WeightUnit;
 use crate::validator;
 
@@ -36,6 +37,7 @@ use crate::validator;
 pub enum Error<E: std::error::Error> {
     Estimator(E),
     NoNewMessage,
+    NotSelfJustified,
 }
 
 impl<E: std::error::Error> std::fmt::Display for Error<E> {
@@ -43,6 +45,10 @@ impl<E: std::error::Error> std::fmt::Display for Error<E> {
         match self {
             Error::Estimator(err) => std::fmt::Display::fmt(&err, f),
             Error::NoNewMessage => writeln!(f, "No message could be added to the state"),
+            Error::NotSelfJustified => writeln!(
+                f,
+                "The sender's previous message is not part of the state's latest honest messages"
+            ),
         }
     }
 }
@@ -50,11 +56,127 @@ impl<E: std::error::Error> std::fmt::Display for Error<E> {
 impl<E: std::error::Error> std::error::Error for Error<E> {}
 
 // Mathematical definition of a casper message with (value, validator, justification).
+// The sequence number, the frontier and the skip pointer are derived from the justification and
+// are not serialized.
 #[derive(Clone, Eq, PartialEq)]
 struct ProtoMessage<E: Estimator> {
     estimate: E,
     sender: E::ValidatorName,
     justification: Justification<E>,
+    seq: u64,
+    frontier: Frontier<E>,
+    /// Message of the sender at sequence number `skip_height(seq)`, following the predecessors.
+    /// None for first messages and when the predecessors do not reach that sequence number.
+    skip: Option<Message<E>>,
+    /// Highest sequence number of a message that is not self-justified among this message and
+    /// its predecessors.
+    unjustified_seq: Option<u64>,
+}
+
+/// For each validator, the messages from that validator in the dependencies of a message that are
+/// not themselves dependencies of another of its messages. There is more than one such message
+/// only for validators that equivocated.
+pub(crate) type Frontier<E> = BTreeMap<<E as Estimator>::ValidatorName, Vec<Message<E>>>;
+
+/// Computes the [`Frontier`] of the transitive closure of a justification from the frontiers of its
+/// messages, so that the justification does not need to list every latest message itself.
+pub(crate) fn frontier<E: Estimator>(justification: &Justification<E>) -> Frontier<E> {
+    let mut frontier: Frontier<E> = BTreeMap::new();
+    for message in justification.iter() {
+        let candidates = message
+            .0
+            .frontier
+            .iter()
+            .flat_map(|(validator, messages)| messages.iter().map(move |m| (validator, m)))
+            .chain(std::iter::once((message.sender(), message)));
+        for (validator, candidate) in candidates {
+            let messages = frontier.entry(validator.clone()).or_default();
+            if !messages.contains(candidate) {
+                messages.push(candidate.clone());
+            }
+        }
+    }
+    for messages in frontier.values_mut() {
+        if messages.len() > 1 {
+            let maximal: Vec<_> = messages
+                .iter()
+                .filter(|message| {
+                    !messages
+                        .iter()
+                        .any(|other| other.seq() > message.seq() && other.depends(message))
+                })
+                .cloned()
+                .collect();
+            *messages = maximal;
+        }
+    }
+    frontier
+}
+
+impl<E: Estimator> ProtoMessage<E> {
+    fn new(sender: E::ValidatorName, justification: Justification<E>, estimate: E) -> Self {
+        let frontier = frontier(&justification);
+
+        let seq = frontier
+            .get(&sender)
+            .and_then(|messages| messages.iter().map(Message::seq).max())
+            .map(|seq| seq + 1)
+            .unwrap_or(0);
+
+        let predecessor = match frontier.get(&sender) {
+            Some(messages) if messages.len() == 1 => messages.first(),
+            _ => None,
+        };
+        let skip = predecessor
+            .and_then(|predecessor| predecessor.ancestor_at_seq(skip_height(seq)))
+            .cloned();
+        let is_self_justified = match frontier.get(&sender) {
+            None => true,
+            Some(_) => predecessor
+                .map(|predecessor| justification.contains(predecessor))
+                .unwrap_or(false),
+        };
+        let unjustified_seq = if is_self_justified {
+            predecessor.and_then(|predecessor| predecessor.0.unjustified_seq)
+        } else {
+            Some(seq)
+        };
+
+        ProtoMessage {
+            sender,
+            justification,
+            estimate,
+            seq,
+            frontier,
+            skip,
+            unjustified_seq,
+        }
+    }
+}
+
+/// Dropping the last reference to a message at the top of a long chain of messages would drop its
+/// dependencies recursively and overflow the stack. The dependencies that are not referenced
+/// elsewhere are instead released one after the other.
+impl<E: Estimator> Drop for ProtoMessage<E> {
+    fn drop(&mut self) {
+        fn release<E: Estimator>(protomessage: &mut ProtoMessage<E>, stack: &mut Vec<Message<E>>) {
+            let justification =
+                std::mem::replace(&mut protomessage.justification, Justification::empty());
+            stack.extend(justification.into_messages());
+            for messages in protomessage.frontier.values_mut() {
+                stack.append(messages);
+            }
+            stack.extend(protomessage.skip.take());
+        }
+
+        let mut stack = Vec::new();
+        release(self, &mut stack);
+        while let Some(message) = stack.pop() {
+            if let Ok(mut protomessage) = Arc::try_unwrap(message.0) {
+                release(&mut protomessage, &mut stack);
+            }
+        }
+    }
 }
 
 impl<E: Estimator> Id for ProtoMessage<E> {
@@ -66,7 +188,7 @@ impl<E: Estimator> Serialize for ProtoMessage<E> {
         use serde::ser::SerializeStruct;
 
         let mut message = serializer.serialize_struct("Message", 3)?;
-        let justification: Vec<_> = self.justification.iter().map(Message::id).collect();
+        let justification = self.justification.sorted_ids();
         message.serialize_field("sender", &self.sender)?;
         message.serialize_field("estimate", &self.estimate)?;
         message.serialize_field("justification", &justification)?;
@@ -116,12 +238,59 @@ impl<E: Estimator> Message<E> {
         &self.0.justification
     }
 
+    /// Returns the previous message of the sender, i.e. the latest message from the sender in the
+    /// dependencies of this message. Returns None for the sender's first message, and for
+    /// messages of a sender that already equivocated in these dependencies.
+    pub fn predecessor(&self) -> Option<&Self> {
+        match self.0.frontier.get(self.sender()) {
+            Some(messages) if messages.len() == 1 => messages.first(),
+            _ => None,
+        }
+    }
+
+    /// Checks the self-justification rule: a message must reference the previous message of its
+    /// sender in its [`justification`], unless it is the first message of its sender. Messages
+    /// breaking this rule are treated as equivocations by the [`validator::State`].
+    ///
+    /// [`justification`]: ../justification/struct.Justification.html
+    /// [`validator::State`]: ../validator/struct.State.html
+    pub fn is_self_justified(&self) -> bool {
+        match self.0.frontier.get(self.sender()) {
+            None => true,
+            Some(_) => self
+                .predecessor()
+                .map(|predecessor| self.justification().contains(predecessor))
+                .unwrap_or(false),
+        }
+    }
+
+    /// Sequence number of the message among the messages of its sender: 0 if no message from the
+    /// sender is in its dependencies, else one more than the highest sequence number of these.
+    pub fn seq(&self) -> u64 {
+        self.0.seq
+    }
+
+    /// Summary of the dependencies of the message: the highest sequence number seen from each
+    /// validator, including the message itself.
+    pub fn vector_clock(&self) -> BTreeMap<&E::ValidatorName, u64> {
+        let mut clock: BTreeMap<_, _> = self
+            .0
+            .frontier
+            .iter()
+            .filter_map(|(validator, messages)| {
+                messages
+                    .iter()
+                    .map(Message::seq)
+                    .max()
+                    .map(|seq| (validator, seq))
+            })
+            .collect();
+        clock.insert(self.sender(), self.seq());
+        clock
+    }
+
     pub fn new(sender: E::ValidatorName, justification: Justification<E>, estimate: E) -> Self {
-        let proto = ProtoMessage {
-            sender,
-            justification,
-            estimate,
-        };
+        let proto = ProtoMessage::new(sender, justification, estimate);
         // Message is not mutable, id is computed only once at creation
         let id = proto.id();
         Message(Arc::new(proto), id)
@@ -130,12 +299,58 @@ impl<E: Estimator> Message<E> {
     /// Creates a message from newly received messages contained in
     /// [`validator_state`], which is used to compute the [`latest honest messages`].
     ///
+    /// Returns [`Error::NotSelfJustified`] if the created message would not reference the
+    /// previous message of the sender, e.g. when the sender is an equivocator in the state.
+    ///
+    /// [`Error::NotSelfJustified`]: enum.Error.html#variant.NotSelfJustified
     /// [`validator_state`]: ../validator/struct.State.html
     /// [`latest honest messages`]: ../justification/struct.LatestMessagesHonest.html
     pub fn from_validator_state<U: WeightUnit>(
         sender: E::ValidatorName,
         validator_state: &validator::State<E, U>,
     ) -> Result<Self, Error<E::Error>> {
+        Self::build_from_validator_state(sender, validator_state, false, &())
+    }
+
+    /// Works like [`from_validator_state`] with a [`contextual estimator`] given the `context`,
+    /// e.g. the application state needed to validate the estimate.
+    ///
+    /// [`from_validator_state`]: #method.from_validator_state
+    /// [`contextual estimator`]: ../estimator/trait.ContextualEstimator.html
+    pub fn from_validator_state_with_context<U: WeightUnit, C>(
+        sender: E::ValidatorName,
+        validator_state: &validator::State<E, U>,
+        context: &C,
+    ) -> Result<Self, Error<E::Error>>
+    where
+        E: ContextualEstimator<C>,
+    {
+        Self::build_from_validator_state(sender, validator_state, false, context)
+    }
+
+    /// Works like [`from_validator_state`] but justifies the message with the [`reduced`] latest
+    /// honest messages, leaving out the messages that are already in the dependencies of another
+    /// one. The resulting message has the same dependencies and estimate, with a smaller
+    /// justification to send.
+    ///
+    /// [`from_validator_state`]: #method.from_validator_state
+    /// [`reduced`]: ../justification/struct.Justification.html#method.reduce
+    pub fn from_validator_state_reduced<U: WeightUnit>(
+        sender: E::ValidatorName,
+        validator_state: &validator::State<E, U>,
+    ) -> Result<Self, Error<E::Error>> {
+        Self::build_from_validator_state(sender, validator_state, true, &())
+    }
+
+    fn build_from_validator_state<U: WeightUnit, C>(
+        sender: E::ValidatorName,
+        validator_state: &validator::State<E, U>,
+        reduce: bool,
+        context: &C,
+    ) -> Result<Self, Error<E::Error>>
+    where
+        E: ContextualEstimator<C>,
+    {
         let latest_messages_honest = LatestMessagesHonest::from_latest_messages(
             validator_state.latests_messages(),
             validator_state.equivocators(),
@@ -144,13 +359,21 @@ impl<E: Estimator> Message<E> {
         if latest_messages_honest.is_empty() {
             Err(Error::NoNewMessage)
         } else {
-            let justification = Justification::from(latest_messages_honest.clone());
-
-            let estimate =
-                latest_messages_honest.make_estimate(&validator_state.validators_weights());
-            estimate
+            let mut justification = Justification::from(latest_messages_honest.clone());
+            if reduce {
+                justification = justification.reduce(&sender);
+            }
+
+            let estimate = latest_messages_honest
+                .make_estimate_with_context(&validator_state.validators_weights(), context);
+            let message = estimate
                 .map(|estimate| Self::new(sender, justification, estimate))
-                .map_err(Error::Estimator)
+                .map_err(Error::Estimator)?;
+            if message.is_self_justified() {
+                Ok(message)
+            } else {
+                Err(Error::NotSelfJustified)
+            }
         }
     }
 
@@ -169,80 +392,160 @@ impl<E: Estimator> Message<E> {
         other: &Self,
         mut equivocators: HashSet<E::ValidatorName>,
     ) -> (bool, HashSet<E::ValidatorName>) {
-        let is_equivocation = self.equivocates(other);
-        let init = if is_equivocation {
-            equivocators.insert(self.sender().clone());
-            (true, equivocators)
-        } else {
-            (false, equivocators)
-        };
-        self.justification().iter().fold(
-            init,
-            |(acc_has_equivocations, acc_equivocators), self_prime| {
-                // Note the rotation between other and self, done because descending only on self,
-                // thus other has to become self on the recursion to get its justification visited.
-                let (has_equivocation, equivocators) =
-                    other.equivocates_indirect(self_prime, acc_equivocators.clone());
-                let acc_equivocators = acc_equivocators.union(&equivocators).cloned().collect();
-                (acc_has_equivocations || has_equivocation, acc_equivocators)
-            },
-        )
+        let mut has_equivocation = false;
+        let mut visited = HashSet::new();
+        let mut stack = vec![(self.clone(), other.clone())];
+        while let Some((lhs, rhs)) = stack.pop() {
+            if !visited.insert((lhs.id(), rhs.id())) {
+                continue;
+            }
+            if lhs.equivocates(&rhs) {
+                has_equivocation = true;
+                equivocators.insert(lhs.sender().clone());
+            }
+            // Note the rotation between rhs and lhs, done because descending only on lhs, thus
+            // rhs has to become lhs on the next step to get its justification visited.
+            for lhs_prime in lhs.justification().iter() {
+                stack.push((rhs.clone(), lhs_prime.clone()));
+            }
+        }
+        (has_equivocation, equivocators)
     }
 
-    /// Math definition of the equivocation.
+    /// Math definition of the equivocation. Two messages with the same sequence number from the
+    /// same sender cannot depend on each other, so only messages with different sequence numbers
+    /// require a dependency check.
+    ///
+    /// Self-justified messages of a sender form a chain through their predecessors, so two of
+    /// them equivocate exactly when their chains contain two messages with the same predecessor.
+    /// This only requires finding the predecessor of the latest message at the sequence number of
+    /// the other one, in O(log n) using the skip pointers. Messages outside of such a chain fall
+    /// back to [`depends`].
+    ///
+    /// [`depends`]: #method.depends
     pub fn equivocates(&self, other: &Self) -> bool {
-        self != other
-            && self.sender() == other.sender()
-            && !other.depends(self)
-            && !self.depends(other)
+        let (latest, earliest) = match self.seq().cmp(&other.seq()) {
+            Ordering::Equal => return self != other && self.sender() == other.sender(),
+            Ordering::Greater => (self, other),
+            Ordering::Less => (other, self),
+        };
+        self.sender() == other.sender()
+            && match latest.self_justified_ancestor(earliest.seq()) {
+                Some(ancestor) => ancestor != earliest,
+                None => !latest.depends(earliest),
+            }
     }
 
-    /// Checks whether self depends on other or not. Returns true if other is somewhere in the
-    /// [`justification`] of self. Then recursively checks the justifications of the messages in the
-    /// [`justification`] of self.  This check is heavy and works well only with messages where the
-    /// dependency is found on the surface, which is what it was designed for.
+    /// Follows the predecessors of self down to the given sequence number. Returns None if a
+    /// message on the way is not self-justified.
+    fn self_justified_ancestor(&self, seq: u64) -> Option<&Self> {
+        match self.0.unjustified_seq {
+            Some(unjustified_seq) if unjustified_seq > seq => None,
+            _ => self.ancestor_at_seq(seq),
+        }
+    }
+
+    /// Returns the message of the sender at the given sequence number among self and its
+    /// predecessors, or None if the predecessors do not reach it, e.g. because the sender
+    /// equivocated. Follows the skip pointers when they do not overshoot, thus visiting O(log n)
+    /// messages.
+    fn ancestor_at_seq(&self, seq: u64) -> Option<&Self> {
+        if seq > self.seq() {
+            return None;
+        }
+
+        let mut message = self;
+        while message.seq() > seq {
+            message = match &message.0.skip {
+                Some(skip) if follow_skip(message.seq(), seq) => skip,
+                _ => message.predecessor()?,
+            };
+        }
+        Some(message)
+    }
+
+    /// Checks whether self depends on other or not, i.e. whether other is in the transitive
+    /// [`justification`] of self. The answer is read from the frontier of self, i.e. the latest
+    /// messages from the sender of other in its dependencies. When the sender of other did not
+    /// equivocate in these dependencies, its messages form a chain through their predecessors and
+    /// other is a dependency exactly when it is the message of that chain at its sequence number,
+    /// found in O(log n) using the skip pointers. Otherwise, only the messages from the sender of
+    /// other that are between the two messages are visited, instead of the whole dependency
+    /// graph. Gives the same result as [`depends_recursive`].
     ///
     /// [`justification`]: ../justification/struct.Justification.html
+    /// [`depends_recursive`]: #method.depends_recursive
     pub fn depends(&self, other: &Self) -> bool {
-        // Although the recursion ends supposedly only at genesis message, the trick is the
-        // following: it short-circuits while descending on the dependency tree, if it finds a
-        // dependent message. when dealing with honest validators, this would return true very
-        // fast. all the new derived branches of the justification will be evaluated in parallel.
-        // Say a message is justified by two other messages, then the two other messages will be
-        // processed on different threads. This applies recursively, so if each of the two
-        // messages have say three messages in their justifications, then each of the two threads
-        // will spawn three new threads to process each of the messages.  Thus, highly
-        // parallelizable. When it shortcuts because in one thread a dependency was found, the
-        // function returns true and all the computation on the other threads will be cancelled.
-        fn recurse<E: Estimator>(
-            lhs: &Message<E>,
-            rhs: &Message<E>,
-            visited: Arc<RwLock<HashSet<Message<E>>>>,
-        ) -> bool {
-            let justification = lhs.justification();
+        let frontier = match self.0.frontier.get(other.sender()) {
+            Some(frontier) => frontier,
+            None => return false,
+        };
+        match frontier.iter().map(Message::seq).max() {
+            Some(seq) if seq >= other.seq() => (),
+            _ => return false,
+        }
+        if frontier.contains(other) {
+            return true;
+        }
+        if let [latest] = frontier.as_slice() {
+            if let Some(ancestor) = latest.ancestor_at_seq(other.seq()) {
+                return ancestor == other;
+            }
+        }
+
+        let candidates = |message: &Self| -> Vec<Self> {
+            message
+                .0
+                .frontier
+                .get(other.sender())
+                .map(|messages| {
+                    messages
+                        .iter()
+                        .filter(|message| message.seq() >= other.seq())
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let mut visited = HashSet::new();
+        let mut stack = candidates(self);
+        while let Some(message) = stack.pop() {
+            if message == *other {
+                return true;
+            }
+            if message.seq() > other.seq() && visited.insert(message.id()) {
+                stack.extend(candidates(&message));
+            }
+        }
+        false
+    }
 
+    /// Checks whether self depends on other or not. Returns true if other is somewhere in the
+    /// [`justification`] of self. Then checks the justifications of the messages in the
+    /// [`justification`] of self, and so on, visiting each message at most once. This check is
+    /// heavy as it may visit the whole dependency graph.
+    ///
+    /// This is the reference implementation of [`depends`].
+    ///
+    /// [`justification`]: ../justification/struct.Justification.html
+    /// [`depends`]: #method.depends
+    pub fn depends_recursive(&self, other: &Self) -> bool {
+        let mut visited = HashSet::new();
+        let mut stack = vec![self];
+        while let Some(message) = stack.pop() {
+            let justification = message.justification();
             // Math definition of dependency
-            justification.contains(rhs)
-                || justification
-                    .par_iter()
-                    .filter(|lhs_prime| {
-                        visited
-                            .read()
-                            .map(|v| !v.contains(lhs_prime))
-                            .ok()
-                            .unwrap_or(true)
-                    })
-                    .any(|lhs_prime| {
-                        let visited_prime = visited.clone();
-                        let _ = visited_prime
-                            .write()
-                            .map(|mut v| v.insert(lhs_prime.clone()))
-                            .ok();
-                        recurse(lhs_prime, rhs, visited_prime)
-                    })
+            if justification.contains(other) {
+                return true;
+            }
+            stack.extend(
+                justification
+                    .iter()
+                    .filter(|message_prime| visited.insert(message_prime.id())),
+            );
         }
-        let visited = Arc::new(RwLock::new(HashSet::new()));
-        recurse(self, other, visited)
+        false
     }
 }
 
@@ -273,6 +576,19 @@ impl<E: Estimator> PartialEq for Message<E> {
     }
 }
 
+/// Messages are ordered by their ids.
+impl<E: Estimator> PartialOrd for Message<E> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<E: Estimator> Ord for Message<E> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.id().cmp(&other.id())
+    }
+}
+
 impl<E: Estimator> Debug for Message<E> {
     // Note: format used for rendering illustrative gifs from generative tests; modify with care.
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -284,14 +600,38 @@ impl<E: Estimator> Debug for Message<E> {
 mod test {
     use super::*;
 
+    use crate::tests_common::utils::Lcg;
     use crate::tests_common::vote_count::VoteCount;
+    use crate::IntegerWrapper;
 
-    use std::collections::HashSet;
+    use std::collections::{BTreeSet, HashSet};
     use std::iter::FromIterator;
 
     use crate::justification::LatestMessages;
     use crate::validator;
 
+    #[test]
+    fn message_id_justification_order() {
+        let v0 = VoteCount::create_vote_message(0, false);
+        let v1 = VoteCount::create_vote_message(1, true);
+        let v2 = VoteCount::create_vote_message(2, true);
+
+        let mut justification = Justification::empty();
+        justification.insert(v0.clone());
+        justification.insert(v1.clone());
+        justification.insert(v2.clone());
+        let mut reversed_justification = Justification::empty();
+        reversed_justification.insert(v2);
+        reversed_justification.insert(v1);
+        reversed_justification.insert(v0);
+
+        let message = Message::new(0, justification, VoteCount { yes: 2, no: 1 });
+        let reversed_message = Message::new(0, reversed_justification, VoteCount { yes: 2, no: 1 });
+        assert_eq!(message.id(), reversed_message.id());
+        assert_eq!(message, reversed_message);
+        assert_eq!(message.justification(), reversed_message.justification());
+    }
+
     #[test]
     fn message_equality() {
         let validator_state = validator::State::new(
@@ -299,7 +639,7 @@ mod test {
             0.0,
             LatestMessages::empty(),
             0.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         let v0 = &VoteCount::create_vote_message(0, false);
@@ -333,6 +673,172 @@ mod test {
         );
     }
 
+    #[test]
+    fn seq_and_vector_clock() {
+        let v0 = VoteCount::create_vote_message(0, false);
+        let v1 = VoteCount::create_vote_message(1, true);
+        let mut justification = Justification::empty();
+        justification.insert(v0.clone());
+        justification.insert(v1.clone());
+        let m0 = Message::new(0, justification, VoteCount { yes: 1, no: 1 });
+        let mut justification = Justification::empty();
+        justification.insert(m0.clone());
+        let m1 = Message::new(0, justification, VoteCount { yes: 1, no: 1 });
+
+        assert_eq!(v0.seq(), 0);
+        assert_eq!(m0.seq(), 1);
+        assert_eq!(m1.seq(), 2);
+        assert_eq!(
+            m1.vector_clock(),
+            vec![(&0, 2), (&1, 0)].into_iter().collect()
+        );
+        assert!(m1.equivocates(&Message::new(
+            0,
+            Justification::empty(),
+            VoteCount { yes: 0, no: 0 }
+        )));
+        assert!(!m1.equivocates(&v0));
+    }
+
+    #[test]
+    fn self_justification() {
+        let v0 = VoteCount::create_vote_message(0, false);
+        let mut justification = Justification::empty();
+        justification.insert(v0.clone());
+        let m1 = Message::new(1, justification, VoteCount { yes: 0, no: 1 });
+
+        // First messages of a sender are self-justified.
+        assert!(v0.is_self_justified());
+        assert!(m1.is_self_justified());
+        assert_eq!(v0.predecessor(), None);
+        assert_eq!(m1.predecessor(), None);
+
+        let mut justification = Justification::empty();
+        justification.insert(v0.clone());
+        justification.insert(m1.clone());
+        let m0 = Message::new(0, justification, VoteCount { yes: 0, no: 2 });
+        assert!(m0.is_self_justified());
+        assert_eq!(m0.predecessor(), Some(&v0));
+
+        // v0 is only referenced through m1.
+        let mut justification = Justification::empty();
+        justification.insert(m1);
+        let m0_indirect = Message::new(0, justification, VoteCount { yes: 0, no: 2 });
+        assert!(!m0_indirect.is_self_justified());
+        assert_eq!(m0_indirect.predecessor(), Some(&v0));
+    }
+
+    #[test]
+    fn equivocates_same_predecessor() {
+        let v0 = VoteCount::create_vote_message(0, false);
+        let successor = |predecessor: &Message<VoteCount>, yes| {
+            let mut justification = Justification::empty();
+            justification.insert(predecessor.clone());
+            Message::new(0, justification, VoteCount { yes, no: 0 })
+        };
+        let a1 = successor(&v0, 1);
+        let a2 = successor(&a1, 2);
+        let a3 = successor(&a2, 3);
+        let b2 = successor(&a1, 4);
+
+        assert!(!a3.equivocates(&v0));
+        assert!(!a3.equivocates(&a1));
+        assert!(!v0.equivocates(&a3));
+        // a2 and b2 share a1 as predecessor, so every successor of a2 equivocates with b2.
+        assert!(a3.equivocates(&b2));
+        assert!(b2.equivocates(&a3));
+        assert!(b2.equivocates(&a2));
+        assert!(!b2.equivocates(&a1));
+    }
+
+    #[test]
+    fn deep_messages() {
+        let depth = 100_000;
+        let first = VoteCount::create_vote_message(0, true);
+        let mut top = first.clone();
+        for i in 1..=depth {
+            let mut justification = Justification::empty();
+            justification.insert(top);
+            top = Message::new(i % 2, justification, VoteCount::default());
+        }
+
+        assert_eq!(top.seq(), u64::from(depth / 2));
+        assert!(top.depends(&first));
+        assert!(top.depends_recursive(&first));
+        assert!(!top.equivocates(&first));
+        // Answered from the frontiers, without walking the chain.
+        assert!(top.depends(top.predecessor().unwrap()));
+        assert!(!first.depends(&top));
+        assert!(!top.predecessor().unwrap().depends(&top));
+        // Answered with the skip pointers, visiting O(log n) messages.
+        let middle = top.ancestor_at_seq(u64::from(depth / 4)).unwrap();
+        assert_eq!(middle.seq(), u64::from(depth / 4));
+        assert!(top.depends(middle));
+        assert!(!top.equivocates(middle));
+        let fork = Message::new(
+            0,
+            middle.justification().clone(),
+            VoteCount { yes: 7, no: 0 },
+        );
+        assert_eq!(fork.seq(), middle.seq());
+        assert!(!top.depends(&fork));
+        assert!(top.equivocates(&fork));
+
+        let mut justification = Justification::empty();
+        justification.insert(top.clone());
+        let latest_messages = LatestMessages::from(&justification);
+        assert_eq!(latest_messages.get(&0).map(HashSet::len), Some(1));
+        let latest_messages_honest =
+            LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new());
+        let weights = validator::Weights::new(vec![(0, 1.0), (1, 1.0)].into_iter().collect());
+        assert_eq!(
+            latest_messages_honest.make_estimate(&weights).unwrap(),
+            VoteCount { yes: 1, no: 0 }
+        );
+    }
+
+    #[test]
+    fn depends_matches_recursive() {
+        let mut random = Lcg::new(42);
+
+        let mut messages: Vec<Message<VoteCount>> = (0..4)
+            .map(|validator| VoteCount::create_vote_message(validator, validator % 2 == 0))
+            .collect();
+        for index in 0..80 {
+            let mut justification = Justification::empty();
+            for _ in 0..=random.below(3) {
+                justification.insert(messages[random.below(messages.len())].clone());
+            }
+            messages.push(Message::new(
+                random.below(4) as u32,
+                justification,
+                VoteCount { yes: index, no: 0 },
+            ));
+        }
+
+        for message in messages.iter() {
+            for other in messages.iter() {
+                assert_eq!(
+                    message.depends(other),
+                    message.depends_recursive(other),
+                    "{:?} depends on {:?}",
+                    message,
+                    other
+                );
+                assert_eq!(
+                    message.equivocates(other),
+                    message != other
+                        && message.sender() == other.sender()
+                        && !message.depends_recursive(other)
+                        && !other.depends_recursive(message),
+                );
+            }
+        }
+        assert!(messages
+            .iter()
+            .any(|message| messages.iter().any(|other| message.equivocates(other))));
+    }
+
     #[test]
     fn message_depends() {
         let validator_state = validator::State::new(
@@ -340,7 +846,7 @@ mod test {
             0.0,
             LatestMessages::empty(),
             0.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         let v0 = &VoteCount::create_vote_message(0, false);
@@ -380,7 +886,7 @@ mod test {
             0.0,
             LatestMessages::empty(),
             0.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         let v0 = &VoteCount::create_vote_message(0, false);
@@ -415,7 +921,7 @@ mod test {
             0.0,
             LatestMessages::empty(),
             0.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         // v0   v1
@@ -448,7 +954,7 @@ mod test {
             0.0,
             LatestMessages::empty(),
             0.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         // v0   v1
@@ -501,7 +1007,7 @@ mod test {
             0.0,
             LatestMessages::empty(),
             0.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         // v0   v1
@@ -549,7 +1055,7 @@ mod test {
                 0.0,
                 latest_messages,
                 0.0,
-                HashSet::new(),
+                BTreeSet::new(),
             ),
         )
         .expect("No errors expected");
@@ -578,7 +1084,7 @@ mod test {
                 0.0,
                 latest_messages,
                 0.0,
-                HashSet::new(),
+                BTreeSet::new(),
             ),
         )
         .expect("No errors expected");
@@ -609,7 +1115,7 @@ mod test {
                 0.0,
                 latest_messages,
                 4.0,
-                HashSet::new(),
+                BTreeSet::new(),
             ),
         )
         .expect("No errors expected");
@@ -643,7 +1149,7 @@ mod test {
                 0.0,
                 latest_messages,
                 0.0,
-                HashSet::new(),
+                BTreeSet::new(),
             ),
         )
         .expect("No errors expected");
@@ -676,7 +1182,7 @@ mod test {
                 0.0,
                 latest_messages,
                 0.0,
-                HashSet::new(),
+                BTreeSet::new(),
             ),
         );
         match res {
@@ -684,4 +1190,113 @@ mod test {
             _ => panic!("Expected NoNewMessage"),
         }
     }
+
+    #[test]
+    fn from_validator_state_not_self_justified() {
+        // The sender equivocated, so its own messages are not part of the latest honest
+        // messages and its new message cannot reference its previous one.
+        let v0 = VoteCount::create_vote_message(0, false);
+        let v0_prime = VoteCount::create_vote_message(0, true);
+        let mut justification = Justification::empty();
+        justification.insert(v0.clone());
+        let v1 = Message::new(1, justification, VoteCount { yes: 0, no: 1 });
+
+        let mut validator_state = validator::State::new(
+            validator::Weights::new(vec![(0, 1.0), (1, 1.0)].into_iter().collect()),
+            0.0,
+            LatestMessages::empty(),
+            1.0,
+            BTreeSet::new(),
+        );
+        validator_state.update(&[&v0, &v0_prime, &v1]);
+
+        match Message::from_validator_state(0, &validator_state) {
+            Err(Error::NotSelfJustified) => (),
+            _ => panic!("Expected NotSelfJustified"),
+        }
+        assert!(Message::from_validator_state(1, &validator_state)
+            .expect("No errors expected")
+            .is_self_justified());
+    }
+
+    #[test]
+    fn from_validator_state_reduced() {
+        let v0 = VoteCount::create_vote_message(0, false);
+        let mut justification = Justification::empty();
+        justification.insert(v0.clone());
+        let v1 = Message::new(1, justification, VoteCount { yes: 0, no: 1 });
+        let v2 = VoteCount::create_vote_message(2, true);
+
+        let mut validator_state = validator::State::new(
+            validator::Weights::new(vec![(0, 1.0), (1, 1.0), (2, 1.0)].into_iter().collect()),
+            0.0,
+            LatestMessages::empty(),
+            0.0,
+            BTreeSet::new(),
+        );
+        validator_state.update(&[&v0, &v1, &v2]);
+
+        let full = Message::from_validator_state(2, &validator_state).unwrap();
+        let reduced = Message::from_validator_state_reduced(2, &validator_state).unwrap();
+        assert_eq!(
+            HashSet::<&Message<VoteCount>>::from_iter(reduced.justification().iter()),
+            HashSet::from_iter(vec![&v1, &v2]),
+        );
+        assert_eq!(reduced.estimate(), full.estimate());
+        assert_eq!(reduced.vector_clock(), full.vector_clock());
+        assert!(reduced.is_self_justified());
+        assert_eq!(
+            LatestMessages::from(reduced.justification()),
+            LatestMessages::from(full.justification())
+        );
+
+        // The previous message of the sender is kept even when another message depends on it.
+        let reduced = Message::from_validator_state_reduced(0, &validator_state).unwrap();
+        assert!(reduced.justification().contains(&v0));
+        assert!(reduced.is_self_justified());
+    }
+
+    /// Caps the integer estimate with the context.
+    impl ContextualEstimator<u32> for IntegerWrapper {
+        fn estimate_with_context<U: WeightUnit>(
+            latest_messages: &LatestMessagesHonest<Self>,
+            validators_weights: &validator::Weights<Self::ValidatorName, U>,
+            cap: &u32,
+        ) -> Result<Self, Self::Error> {
+            IntegerWrapper::estimate(latest_messages, validators_weights)
+                .map(|estimate| IntegerWrapper(estimate.0.min(*cap)))
+        }
+    }
+
+    #[test]
+    fn from_validator_state_with_context() {
+        let m0 = Message::new(0, Justification::empty(), IntegerWrapper(5));
+        let m1 = Message::new(1, Justification::empty(), IntegerWrapper(5));
+        let mut validator_state = validator::State::new(
+            validator::Weights::new(vec![(0, 1.0), (1, 1.0)].into_iter().collect()),
+            0.0,
+            LatestMessages::empty(),
+            0.0,
+            BTreeSet::new(),
+        );
+        validator_state.update(&[&m0, &m1]);
+
+        let message = Message::from_validator_state(0, &validator_state).unwrap();
+        assert_eq!(message.estimate(), &IntegerWrapper(5));
+        let message = Message::from_validator_state_with_context(0, &validator_state, &()).unwrap();
+        assert_eq!(message.estimate(), &IntegerWrapper(5));
+        let message = Message::from_validator_state_with_context(0, &validator_state, &3).unwrap();
+        assert_eq!(message.estimate(), &IntegerWrapper(3));
+        assert_eq!(
+            message
+                .justification()
+                .make_estimate_with_context(
+                    &BTreeSet::new(),
+                    validator_state.validators_weights(),
+                    &4
+                )
+                .unwrap(),
+            IntegerWrapper(4)
+        );
+    }
 }
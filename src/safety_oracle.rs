@@ -0,0 +1,212 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::estimator::Estimator;
+use crate::justification::{Justification, LatestMessages, LatestMessagesHonest};
+use crate::message::Message;
+use crate::util::weight::{WeightUnit, Zero};
+use crate::validator;
+
+/// Returns the maximal cliques of validators weighting more than `safety_oracle_threshold` on a
+/// property of the estimates, e.g. containing a block or starting with a prefix. In a clique,
+/// the latest honest message of every validator `agrees` with the property, and its
+/// justification contains a latest honest message agreeing with the property from every other
+/// validator of the clique.
+///
+/// Validators in a clique cannot change their estimate to disagree with the property without
+/// equivocating, as long as the equivocators weigh less than the clique's weight.
+///
+/// Validators missing from `weights` are weightless, as in the estimates.
+pub fn clique_oracles<E, U, F>(
+    latest_messages_honest: &LatestMessagesHonest<E>,
+    equivocators: &BTreeSet<E::ValidatorName>,
+    safety_oracle_threshold: U,
+    weights: &validator::Weights<E::ValidatorName, U>,
+    agrees: F,
+) -> HashSet<BTreeSet<E::ValidatorName>>
+where
+    E: Estimator,
+    U: WeightUnit,
+    F: Fn(&Message<E>) -> bool,
+{
+    fn latest_in_justification<E: Estimator>(
+        justification: &Justification<E>,
+        equivocators: &BTreeSet<E::ValidatorName>,
+    ) -> HashMap<E::ValidatorName, Message<E>> {
+        LatestMessagesHonest::from_latest_messages(
+            &LatestMessages::from(justification),
+            equivocators,
+        )
+        .iter()
+        .map(|message| (message.sender().clone(), message.clone()))
+        .collect()
+    }
+
+    let latest_agreeing: HashSet<&Message<E>> = latest_messages_honest
+        .iter()
+        .filter(|&message| agrees(message))
+        .collect();
+
+    let latest_agreeing_in_validator_view: HashMap<_, HashMap<_, Message<E>>> = latest_agreeing
+        .iter()
+        .map(|message| {
+            (
+                message.sender().clone(),
+                latest_in_justification(message.justification(), equivocators)
+                    .into_iter()
+                    .filter(|(_validator, message)| agrees(message))
+                    .collect(),
+            )
+        })
+        .collect();
+
+    let neighbours: HashMap<&E::ValidatorName, HashSet<&E::ValidatorName>> =
+        latest_agreeing_in_validator_view
+            .iter()
+            .map(|(validator, seen_agreeing)| {
+                (
+                    validator,
+                    seen_agreeing
+                        .keys()
+                        .filter(|validatorb| {
+                            if latest_agreeing_in_validator_view.contains_key(validatorb) {
+                                latest_agreeing_in_validator_view[validatorb]
+                                    .contains_key(&validator.clone())
+                            } else {
+                                false
+                            }
+                        })
+                        .collect(),
+                )
+            })
+            .collect();
+
+    fn bron_kerbosch<V: validator::ValidatorName>(
+        r: HashSet<&V>,
+        p: HashSet<&V>,
+        x: HashSet<&V>,
+        mx_clqs: &mut HashSet<BTreeSet<V>>,
+        neighbours: HashMap<&V, HashSet<&V>>,
+    ) {
+        if p.is_empty() && x.is_empty() {
+            let rnew: BTreeSet<V> = r.into_iter().cloned().collect();
+            mx_clqs.insert(rnew);
+        } else {
+            let piter = p.clone();
+            let mut p = p;
+            let mut x = x;
+            piter.into_iter().for_each(|i| {
+                p.remove(i);
+                let mut rnew = r.clone();
+                rnew.insert(i);
+                let pnew: HashSet<&V> = p.intersection(&neighbours[i]).cloned().collect();
+                let xnew: HashSet<&V> = x.intersection(&neighbours[i]).cloned().collect();
+                x.insert(i);
+                bron_kerbosch(rnew, pnew, xnew, mx_clqs, neighbours.clone())
+            })
+        }
+    }
+
+    let p = neighbours
+        .iter()
+        .fold(HashSet::new(), |acc, (_validator, x)| {
+            acc.union(x).cloned().collect()
+        });
+
+    let mut mx_clqs = HashSet::new();
+
+    bron_kerbosch(HashSet::new(), p, HashSet::new(), &mut mx_clqs, neighbours);
+
+    mx_clqs
+        .into_iter()
+        .filter(|x| {
+            x.iter().fold(<U as Zero<U>>::ZERO, |acc, validator| {
+                acc + weights.weight(validator).unwrap_or(<U as Zero<U>>::ZERO)
+            }) > safety_oracle_threshold
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::IntegerWrapper;
+
+    #[test]
+    fn clique_oracles_on_property() {
+        let weights =
+            validator::Weights::new(vec![(0, 1.0), (1, 1.0), (2, 1.0)].into_iter().collect());
+        let greater_than_one = |message: &Message<IntegerWrapper>| message.estimate().0 > 1;
+        let oracles = |messages: &[&Message<IntegerWrapper>], threshold| {
+            let mut latest_messages = LatestMessages::empty();
+            for message in messages {
+                latest_messages.update(message);
+            }
+            clique_oracles(
+                &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+                &BTreeSet::new(),
+                threshold,
+                &weights,
+                greater_than_one,
+            )
+        };
+
+        let m0 = Message::new(0, Justification::empty(), IntegerWrapper(2));
+        let m1 = Message::new(1, Justification::empty(), IntegerWrapper(4));
+        let m2 = Message::new(2, Justification::empty(), IntegerWrapper(1));
+        // Validators that have not seen each other agreeing are not in a clique, not even on
+        // their own.
+        assert!(oracles(&[&m0, &m1, &m2], 0.0).is_empty());
+
+        let mut justification = Justification::empty();
+        justification.insert(m0.clone());
+        justification.insert(m1.clone());
+        justification.insert(m2.clone());
+        let m3 = Message::new(0, justification.clone(), IntegerWrapper(6));
+        let m4 = Message::new(1, justification.clone(), IntegerWrapper(8));
+        let m5 = Message::new(2, justification, IntegerWrapper(10));
+        // Validator 2 saw the others agreeing but they did not see it agreeing.
+        assert_eq!(
+            oracles(&[&m3, &m4, &m5], 1.5),
+            vec![vec![0, 1].into_iter().collect()].into_iter().collect()
+        );
+        assert!(oracles(&[&m3, &m4, &m5], 2.0).is_empty());
+
+        // Validator 3 has no weight, so it adds nothing to the clique it is part of.
+        let m6 = Message::new(3, Justification::empty(), IntegerWrapper(3));
+        let mut justification = Justification::empty();
+        justification.insert(m3.clone());
+        justification.insert(m4.clone());
+        justification.insert(m6);
+        let m7 = Message::new(0, justification.clone(), IntegerWrapper(6));
+        let m8 = Message::new(1, justification.clone(), IntegerWrapper(8));
+        let m9 = Message::new(3, justification, IntegerWrapper(12));
+        assert_eq!(
+            oracles(&[&m7, &m8, &m9], 1.5),
+            vec![vec![0, 1, 3].into_iter().collect()]
+                .into_iter()
+                .collect()
+        );
+        assert!(oracles(&[&m7, &m8, &m9], 2.0).is_empty());
+    }
+}
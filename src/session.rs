@@ -0,0 +1,500 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+//! Delta encoding of the [`messages`] sent to a peer.
+//!
+//! A [`PeerSession`] remembers which messages a peer acknowledged. It encodes each outgoing
+//! message relative to the latest acknowledged message of the same sender, the base: the
+//! justification is sent as the base plus the identifiers of the messages received since then,
+//! instead of the full list of identifiers. The messages of the justification of the base that
+//! these new messages replace do not need to be listed. The peer rebuilds the message with
+//! [`EncodedMessage::decode`] from the messages it already holds.
+//!
+//! [`messages`]: ../message/struct.Message.html
+//! [`PeerSession`]: struct.PeerSession.html
+//! [`EncodedMessage::decode`]: struct.EncodedMessage.html#method.decode
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde_derive::Serialize;
+
+use crate::estimator::Estimator;
+use crate::justification::Justification;
+use crate::message::Message;
+use crate::util::hash::Hash;
+use crate::util::id::Id;
+
+/// Reasons for which an [`EncodedMessage`] cannot be decoded.
+///
+/// [`EncodedMessage`]: struct.EncodedMessage.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// A message referenced by the encoded message is not known by the receiver.
+    UnknownMessage(Hash),
+    /// The decoded message does not have the identifier of the encoded message, e.g. because the
+    /// base message of a delta is not the one the sender used.
+    IdMismatch(Hash),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DecodeError::UnknownMessage(id) => writeln!(f, "Unknown message {:?}", id),
+            DecodeError::IdMismatch(id) => writeln!(f, "Decoded message is not {:?}", id),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Justification of an [`EncodedMessage`].
+///
+/// [`EncodedMessage`]: struct.EncodedMessage.html
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum EncodedJustification {
+    /// The identifiers of all the messages of the justification.
+    Full(Vec<Hash>),
+    /// The justification relative to the `base` message. The messages of the justification are
+    /// the base message, the messages of the justification of the base whose sender has no
+    /// message in `added`, and the `added` messages, minus the `removed` ones.
+    Delta {
+        base: Hash,
+        added: Vec<Hash>,
+        removed: Vec<Hash>,
+    },
+}
+
+impl EncodedJustification {
+    /// Number of identifiers in the encoded justification.
+    pub fn len(&self) -> usize {
+        match self {
+            EncodedJustification::Full(ids) => ids.len(),
+            EncodedJustification::Delta { added, removed, .. } => 1 + added.len() + removed.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A [`message`] as sent to a peer, with its justification encoded by a [`PeerSession`]. The
+/// identifier of the message is sent along to check the decoding and to acknowledge the message.
+///
+/// [`message`]: ../message/struct.Message.html
+/// [`PeerSession`]: struct.PeerSession.html
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename = "Message")]
+pub struct EncodedMessage<E: Estimator> {
+    pub id: Hash,
+    pub sender: E::ValidatorName,
+    pub estimate: E,
+    pub justification: EncodedJustification,
+}
+
+impl<E: Estimator> EncodedMessage<E> {
+    /// Rebuilds the message by looking up the messages it references with `known_messages`.
+    pub fn decode<F>(self, known_messages: F) -> Result<Message<E>, DecodeError>
+    where
+        F: Fn(&Hash) -> Option<Message<E>>,
+    {
+        let lookup = |id: &Hash| {
+            known_messages(id)
+                .filter(|message| message.id() == *id)
+                .ok_or(DecodeError::UnknownMessage(*id))
+        };
+
+        let mut justification = Justification::empty();
+        match self.justification {
+            EncodedJustification::Full(ids) => {
+                for id in ids.iter() {
+                    justification.insert(lookup(id)?);
+                }
+            }
+            EncodedJustification::Delta {
+                base,
+                added,
+                removed,
+            } => {
+                let base = lookup(&base)?;
+                let added = added.iter().map(lookup).collect::<Result<Vec<_>, _>>()?;
+                let mut messages = implicit_messages(&base, added.iter());
+                for id in removed.iter() {
+                    messages.remove(id);
+                }
+                for message in messages.values().chain(added.iter()) {
+                    justification.insert(message.clone());
+                }
+            }
+        }
+
+        let message = Message::new(self.sender, justification, self.estimate);
+        if message.id() == self.id {
+            Ok(message)
+        } else {
+            Err(DecodeError::IdMismatch(self.id))
+        }
+    }
+}
+
+/// Messages of a delta relative to `base` that are not listed: the base itself and the messages
+/// of its justification whose sender has no message in `added`, nor is the sender of the base.
+fn implicit_messages<'a, E, I>(base: &Message<E>, added: I) -> BTreeMap<Hash, Message<E>>
+where
+    E: Estimator + 'a,
+    I: Iterator<Item = &'a Message<E>>,
+{
+    let mut replaced: BTreeSet<_> = added.map(Message::sender).collect();
+    replaced.insert(base.sender());
+    base.justification()
+        .iter()
+        .filter(|message| !replaced.contains(message.sender()))
+        .chain(std::iter::once(base))
+        .map(|message| (message.id(), message.clone()))
+        .collect()
+}
+
+/// Encoding state of the messages sent to one peer.
+///
+/// Messages are delta encoded relative to the latest message of their sender that the peer
+/// acknowledged, and fully encoded when there is no such message or when the delta would not be
+/// smaller. A lost acknowledgement only makes the following deltas relative to an older base.
+/// The session must be [`reset`] when reconnecting to the peer, as the peer might not hold the
+/// acknowledged messages anymore.
+///
+/// # Example
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// use core_cbc_casper::justification::Justification;
+/// use core_cbc_casper::message::Message;
+/// use core_cbc_casper::session::{EncodedJustification, PeerSession};
+/// use core_cbc_casper::util::id::Id;
+/// use core_cbc_casper::IntegerWrapper;
+///
+/// fn message(sender: u32, justification: &[&Message<IntegerWrapper>]) -> Message<IntegerWrapper> {
+///     let mut messages = Justification::empty();
+///     for message in justification {
+///         messages.insert((*message).clone());
+///     }
+///     Message::new(sender, messages, IntegerWrapper(sender))
+/// }
+///
+/// let v0 = message(0, &[]);
+/// let v1 = message(1, &[]);
+/// let v2 = message(2, &[]);
+/// let m0 = message(0, &[&v0, &v1, &v2]);
+/// let m1 = message(1, &[&v1]);
+/// // v1 is replaced by m1, only m1 needs to be sent along with the base m0.
+/// let m0_prime = message(0, &[&m0, &m1, &v2]);
+///
+/// let mut session = PeerSession::new();
+/// let mut received = HashMap::new();
+/// for message in [&v0, &v1, &v2, &m0, &m1, &m0_prime].iter() {
+///     let encoded = session.encode(message);
+///     if **message == m0_prime {
+///         assert_eq!(
+///             encoded.justification,
+///             EncodedJustification::Delta { base: m0.id(), added: vec![m1.id()], removed: vec![] },
+///         );
+///     }
+///     let decoded = encoded.decode(|id| received.get(id).cloned()).unwrap();
+///     assert_eq!(decoded, **message);
+///
+///     // The peer acknowledges the message once it is stored.
+///     received.insert(decoded.id(), decoded);
+///     session.acknowledge(&message.id());
+/// }
+/// ```
+///
+/// [`reset`]: #method.reset
+#[derive(Clone, Debug)]
+pub struct PeerSession<E: Estimator> {
+    acknowledged: BTreeMap<E::ValidatorName, Message<E>>,
+    unacknowledged: HashMap<Hash, Message<E>>,
+}
+
+impl<E: Estimator> Default for PeerSession<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Estimator> PeerSession<E> {
+    pub fn new() -> Self {
+        PeerSession {
+            acknowledged: BTreeMap::new(),
+            unacknowledged: HashMap::new(),
+        }
+    }
+
+    /// Returns the latest acknowledged message of the validator, used as base for the deltas of
+    /// its next messages.
+    pub fn base(&self, validator: &E::ValidatorName) -> Option<&Message<E>> {
+        self.acknowledged.get(validator)
+    }
+
+    /// Encodes a message to send to the peer. The message waits for an acknowledgement to become
+    /// a base for the next messages of its sender.
+    pub fn encode(&mut self, message: &Message<E>) -> EncodedMessage<E> {
+        let full = message.justification().sorted_ids();
+        let delta = self.base(message.sender()).map(|base| {
+            let new_messages = message
+                .justification()
+                .iter()
+                .filter(|message| *message != base && !base.justification().contains(message));
+            let implicit = implicit_messages(base, new_messages);
+            EncodedJustification::Delta {
+                base: base.id(),
+                added: full
+                    .iter()
+                    .filter(|id| !implicit.contains_key(id))
+                    .cloned()
+                    .collect(),
+                removed: implicit
+                    .keys()
+                    .filter(|id| full.binary_search(id).is_err())
+                    .cloned()
+                    .collect(),
+            }
+        });
+        let justification = match delta {
+            Some(ref delta) if delta.len() < full.len() => delta.clone(),
+            _ => EncodedJustification::Full(full),
+        };
+
+        self.unacknowledged.insert(message.id(), message.clone());
+        EncodedMessage {
+            id: message.id(),
+            sender: message.sender().clone(),
+            estimate: message.estimate().clone(),
+            justification,
+        }
+    }
+
+    /// Records that the peer holds the message with the given identifier. Returns false if the
+    /// message was not sent in this session or was already acknowledged.
+    pub fn acknowledge(&mut self, id: &Hash) -> bool {
+        let message = match self.unacknowledged.remove(id) {
+            Some(message) => message,
+            None => return false,
+        };
+        // Messages sent before this one by the same sender are not needed as bases anymore, even
+        // if their acknowledgement is lost.
+        self.unacknowledged
+            .retain(|_, other| other.sender() != message.sender() || other.seq() > message.seq());
+        let is_newer = self
+            .base(message.sender())
+            .map(|base| base.seq() < message.seq())
+            .unwrap_or(true);
+        if is_newer {
+            self.acknowledged.insert(message.sender().clone(), message);
+        }
+        true
+    }
+
+    /// Forgets what the peer holds, e.g. after reconnecting to it. The next messages are fully
+    /// encoded until the peer acknowledges them.
+    pub fn reset(&mut self) {
+        self.acknowledged.clear();
+        self.unacknowledged.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use crate::justification::LatestMessages;
+    use crate::validator;
+    use crate::IntegerWrapper;
+
+    /// Messages held by a peer.
+    struct Peer(HashMap<Hash, Message<IntegerWrapper>>);
+
+    impl Peer {
+        fn receive(
+            &mut self,
+            encoded: EncodedMessage<IntegerWrapper>,
+        ) -> Result<Hash, DecodeError> {
+            let messages = &self.0;
+            let message = encoded.decode(|id| messages.get(id).cloned())?;
+            let id = message.id();
+            self.0.insert(id, message);
+            Ok(id)
+        }
+    }
+
+    /// Every validator sends a first message, then the given senders send, in order, a message
+    /// justified by the latest messages of every validator.
+    fn messages(validators: u32, senders: &[u32]) -> Vec<Message<IntegerWrapper>> {
+        let mut state = validator::State::new(
+            validator::Weights::new((0..validators).map(|v| (v, 1.0)).collect()),
+            0.0,
+            LatestMessages::empty(),
+            0.0,
+            BTreeSet::new(),
+        );
+        let mut messages = Vec::new();
+        for sender in 0..validators {
+            let message = Message::new(sender, Justification::empty(), IntegerWrapper(sender));
+            state.update(&[&message]);
+            messages.push(message);
+        }
+        for sender in senders.iter() {
+            let message = Message::from_validator_state(*sender, &state).unwrap();
+            state.update(&[&message]);
+            messages.push(message);
+        }
+        messages
+    }
+
+    /// Validator 0 sends a message after each message of another validator.
+    fn interleaved(validators: u32, rounds: usize) -> Vec<Message<IntegerWrapper>> {
+        let senders: Vec<_> = (0..rounds)
+            .flat_map(|round| vec![1 + round as u32 % (validators - 1), 0])
+            .collect();
+        messages(validators, &senders)
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut session = PeerSession::new();
+        let mut peer = Peer(HashMap::new());
+        let senders: Vec<_> = (0..20).map(|index| index % 3).collect();
+
+        for message in messages(4, &senders).iter() {
+            let has_base = session.base(message.sender()).is_some();
+            let encoded = session.encode(message);
+            match encoded.justification {
+                EncodedJustification::Full(_) => (),
+                EncodedJustification::Delta { .. } => assert!(has_base),
+            }
+            let id = peer.receive(encoded).unwrap();
+            assert_eq!(peer.0[&id], *message);
+            assert!(session.acknowledge(&id));
+            assert!(!session.acknowledge(&id));
+        }
+    }
+
+    #[test]
+    fn delta_is_smaller() {
+        let mut session = PeerSession::new();
+        let mut peer = Peer(HashMap::new());
+
+        let mut full_ids = 0;
+        let mut sent_ids = 0;
+        for message in interleaved(20, 40).iter() {
+            let encoded = session.encode(message);
+            assert!(encoded.justification.len() <= message.justification().len());
+            if *message.sender() == 0 {
+                full_ids += message.justification().len();
+                sent_ids += encoded.justification.len();
+            }
+            let id = peer.receive(encoded).unwrap();
+            session.acknowledge(&id);
+        }
+        assert!(
+            sent_ids * 4 < full_ids,
+            "{} ids sent for {}",
+            sent_ids,
+            full_ids
+        );
+    }
+
+    #[test]
+    fn lost_acknowledgements() {
+        let mut session = PeerSession::new();
+        let mut peer = Peer(HashMap::new());
+        let messages = interleaved(5, 12);
+
+        let mut deltas = 0;
+        for (index, message) in messages.iter().enumerate() {
+            let encoded = session.encode(message);
+            if let EncodedJustification::Delta { .. } = encoded.justification {
+                deltas += 1;
+            }
+            let id = peer.receive(encoded).unwrap();
+            assert_eq!(peer.0[&id], *message);
+            // Only one acknowledgement out of three reaches the session.
+            if index % 3 == 0 {
+                session.acknowledge(&id);
+            }
+        }
+        assert!(deltas > 0);
+        assert!(session.base(&0).unwrap().seq() < messages.last().unwrap().seq() - 1);
+    }
+
+    #[test]
+    fn reconnect() {
+        let mut session = PeerSession::new();
+        let mut peer = Peer(HashMap::new());
+        let messages = interleaved(5, 8);
+        let (before, after) = messages.split_at(12);
+        assert_eq!(*after[0].sender(), 0);
+
+        for message in before.iter() {
+            let id = peer.receive(session.encode(message)).unwrap();
+            session.acknowledge(&id);
+        }
+
+        // The peer restarts and only recovers the first message of every validator and the
+        // messages sent to it after reconnecting.
+        peer.0
+            .retain(|_, message| message.justification().is_empty());
+        let base = session.base(&0).unwrap().id();
+        assert_eq!(
+            peer.receive(session.encode(&after[0])),
+            Err(DecodeError::UnknownMessage(base))
+        );
+
+        session.reset();
+        for message in before[5..].iter().chain(after.iter()) {
+            let id = peer.receive(session.encode(message)).unwrap();
+            assert_eq!(peer.0[&id], *message);
+            session.acknowledge(&id);
+        }
+    }
+
+    #[test]
+    fn wrong_base() {
+        let mut session = PeerSession::new();
+        let mut peer = Peer(HashMap::new());
+        let messages = interleaved(5, 4);
+        let (last, before) = messages.split_last().unwrap();
+
+        for message in before.iter() {
+            let id = peer.receive(session.encode(message)).unwrap();
+            session.acknowledge(&id);
+        }
+        let mut encoded = session.encode(last);
+        match encoded.justification {
+            EncodedJustification::Delta { ref mut base, .. } => *base = messages[0].id(),
+            _ => panic!("Expected a delta"),
+        }
+        assert_eq!(
+            peer.receive(encoded),
+            Err(DecodeError::IdMismatch(last.id()))
+        );
+    }
+}
@@ -0,0 +1,603 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+//! Sharded blockchain estimator in the spirit of the CBC Casper sharding construction.
+//!
+//! Shards are organized as a binary tree: the root shard is `0` and the children of shard `n`
+//! are the shards `2n + 1` and `2n + 2`. Each shard runs its own blockchain made of [`Block`]s
+//! and messages carry one estimate per shard. Blocks may receive cross-shard messages from
+//! blocks of their parent or child shards. The fork choice runs GHOST on each shard from the
+//! root down to the leaves, restricted to the blocks whose received messages are consistent with
+//! the chains chosen for the neighbouring shards:
+//! * a block can only receive messages sent by blocks of the chain chosen for its parent shard;
+//! * the chain chosen for a shard must contain every block its parent shard's chain received
+//!   messages from;
+//! * a block that received messages from unknown blocks of its child shards, or from blocks of a
+//!   child shard that are not on the same chain, is never chosen.
+//!
+//! [`Block`]: ../blockchain/struct.Block.html
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use serde_derive::Serialize;
+
+use crate::blockchain::{self, Block, BlockData, Ranking, TieBreaker};
+use crate::estimator::Estimator;
+use crate::justification::LatestMessagesHonest;
+use crate::util::hash::Hash;
+use crate::util::id::Id;
+use crate::util::weight::WeightUnit;
+use crate::validator;
+
+/// Identifier of a shard.
+pub type ShardId = u32;
+
+/// Returns the parent of `shard` in the shards tree, None for the root shard.
+pub fn parent_shard(shard: ShardId) -> Option<ShardId> {
+    if shard == 0 {
+        None
+    } else {
+        Some((shard - 1) / 2)
+    }
+}
+
+/// Returns the two children of `shard` in the shards tree.
+pub fn child_shards(shard: ShardId) -> [ShardId; 2] {
+    [2 * shard + 1, 2 * shard + 2]
+}
+
+/// Reference to a message sent by a block of another shard.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize)]
+pub struct CrossShardMessage {
+    pub source_shard: ShardId,
+    /// Identifier of the block sending the message.
+    pub source_block: Hash,
+}
+
+/// This trait must be implemented on the data of the blocks of a sharded blockchain.
+pub trait ShardBlockData: BlockData {
+    /// The cross-shard messages received by the block holding this data.
+    fn received(&self) -> &[CrossShardMessage];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The fork choice failed on the given shard.
+    ForkChoice(ShardId),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::ForkChoice(shard) => writeln!(f, "Fork choice failed on shard {}", shard),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Estimate of a sharded blockchain: one block per shard.
+///
+/// When used as an [`Estimator`], the estimate contains the head block chosen by the sharded
+/// fork choice for every shard present in the latest honest messages. A validator proposes new
+/// blocks by [`insert`]ing them in such an estimate before creating its [`message`].
+///
+/// [`Estimator`]: ../estimator/trait.Estimator.html
+/// [`insert`]: #method.insert
+/// [`message`]: ../message/struct.Message.html
+///
+/// # Example
+///
+/// ```
+/// use std::collections::BTreeSet;
+///
+/// use core_cbc_casper::blockchain::{Block, BlockData};
+/// use core_cbc_casper::justification::{Justification, LatestMessages};
+/// use core_cbc_casper::message::Message;
+/// use core_cbc_casper::sharding::{CrossShardMessage, ShardBlockData, ShardedBlocks};
+/// use core_cbc_casper::util::id::Id;
+/// use core_cbc_casper::validator;
+///
+/// #[derive(Hash, Clone, PartialEq, Eq, Default, Debug, serde_derive::Serialize)]
+/// struct Data {
+///     validator: u32,
+///     received: Vec<CrossShardMessage>,
+/// }
+///
+/// impl BlockData for Data {
+///     type ValidatorName = u32;
+///
+///     fn validator_name(&self) -> &u32 {
+///         &self.validator
+///     }
+/// }
+///
+/// impl ShardBlockData for Data {
+///     fn received(&self) -> &[CrossShardMessage] {
+///         &self.received
+///     }
+/// }
+///
+/// let root = Block::new(None, Data::default());
+/// let child = Block::new(
+///     None,
+///     Data {
+///         validator: 0,
+///         received: vec![CrossShardMessage { source_shard: 0, source_block: root.id() }],
+///     },
+/// );
+/// let mut blocks = ShardedBlocks::new();
+/// blocks.insert(0, root.clone());
+/// blocks.insert(1, child.clone());
+///
+/// let message = Message::new(0, Justification::empty(), blocks);
+/// let mut state = validator::State::new(
+///     validator::Weights::new(vec![(0, 1.0), (1, 1.0)].into_iter().collect()),
+///     0.0,
+///     LatestMessages::empty(),
+///     1.0,
+///     BTreeSet::new(),
+/// );
+/// state.update(&[&message]);
+///
+/// let estimate = Message::from_validator_state(1, &state).unwrap().estimate().clone();
+/// assert_eq!(estimate.get(0), Some(&root));
+/// assert_eq!(estimate.get(1), Some(&child));
+/// ```
+#[derive(Clone, Default, Hash, PartialEq, Eq, Serialize)]
+pub struct ShardedBlocks<D: BlockData>(BTreeMap<ShardId, Block<D>>);
+
+impl<D: BlockData> std::fmt::Debug for ShardedBlocks<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_map().entries(self.0.iter()).finish()
+    }
+}
+
+impl<D: BlockData> ShardedBlocks<D> {
+    pub fn new() -> Self {
+        ShardedBlocks(BTreeMap::new())
+    }
+
+    /// Sets the block of `shard`, returning the previous one if any.
+    pub fn insert(&mut self, shard: ShardId, block: Block<D>) -> Option<Block<D>> {
+        self.0.insert(shard, block)
+    }
+
+    pub fn get(&self, shard: ShardId) -> Option<&Block<D>> {
+        self.0.get(&shard)
+    }
+
+    /// Iterates over the shards and their block, by increasing shard identifier.
+    pub fn iter(&self) -> std::collections::btree_map::Iter<'_, ShardId, Block<D>> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<D: ShardBlockData> ShardedBlocks<D> {
+    /// Runs the fork choice on every shard, from the root shard to the leaves, and returns the
+    /// chosen head block of each shard.
+    pub fn fork_choice<U: WeightUnit>(
+        latest_messages_honest: &LatestMessagesHonest<Self>,
+        weights: &validator::Weights<D::ValidatorName, U>,
+    ) -> Result<Self, Error> {
+        ShardedBlocks::fork_choice_with_tie_breaker(
+            latest_messages_honest,
+            weights,
+            &TieBreaker::LowestHash,
+        )
+    }
+
+    /// Same as `fork_choice`, with the ties of every shard broken by `tie_breaker`.
+    pub fn fork_choice_with_tie_breaker<U: WeightUnit>(
+        latest_messages_honest: &LatestMessagesHonest<Self>,
+        weights: &validator::Weights<D::ValidatorName, U>,
+        tie_breaker: &TieBreaker,
+    ) -> Result<Self, Error> {
+        let shards: BTreeSet<ShardId> = latest_messages_honest
+            .iter()
+            .flat_map(|message| message.estimate().0.keys().cloned())
+            .collect();
+        let latest_blocks: HashMap<ShardId, Vec<_>> = shards
+            .iter()
+            .map(|&shard| {
+                let latest_blocks = latest_messages_honest
+                    .iter()
+                    .filter_map(|message| {
+                        message
+                            .estimate()
+                            .get(shard)
+                            .map(|block| (message.sender().clone(), block.clone()))
+                    })
+                    .collect();
+                (shard, latest_blocks)
+            })
+            .collect();
+        let known: HashMap<ShardId, HashMap<Hash, &Block<D>>> = latest_blocks
+            .iter()
+            .map(|(&shard, latest_blocks)| (shard, known_blocks(latest_blocks)))
+            .collect();
+        let sources: HashMap<ShardId, Sources<D>> = known
+            .keys()
+            .map(|&shard| (shard, child_sources(shard, &known)))
+            .collect();
+
+        // Parent shards have lower identifiers than their children, so iterating in order
+        // chooses the parent's head before its children's.
+        let mut heads = ShardedBlocks::new();
+        for shard in shards {
+            let parent = parent_shard(shard).and_then(|parent| {
+                heads.get(parent).map(|head: &Block<D>| {
+                    let chain: HashSet<Hash> = chain(head).map(Block::id).collect();
+                    (parent, chain, head.id())
+                })
+            });
+            // The head of the parent shard was a candidate, so its sources are consistent.
+            let required = parent
+                .as_ref()
+                .and_then(|(parent, _, parent_head)| sources[parent].get(parent_head))
+                .and_then(Option::as_ref)
+                .and_then(|required| required.get(&shard));
+            let shard_sources = &sources[&shard];
+
+            let is_candidate = |block: &Block<D>| {
+                let consistent_with_required = required
+                    .map(|required| required.is_member(block) || block.is_member(required))
+                    .unwrap_or(true);
+                consistent_with_required
+                    && shard_sources
+                        .get(&block.id())
+                        .map_or(false, Option::is_some)
+                    && block
+                        .data_as_ref()
+                        .received()
+                        .iter()
+                        .all(|message| match &parent {
+                            Some((parent, parent_chain, _)) if message.source_shard == *parent => {
+                                parent_chain.contains(&message.source_block)
+                            }
+                            _ => child_shards(shard).contains(&message.source_shard),
+                        })
+            };
+
+            let ranking = Ranking::with_estimates(
+                tie_breaker,
+                weights,
+                latest_messages_honest.iter(),
+                |estimate: &Self| estimate.get(shard),
+            );
+            let head =
+                Block::filtered_ghost(&latest_blocks[&shard], weights, &ranking, is_candidate)
+                    .map_err(|_: blockchain::Error| Error::ForkChoice(shard))?;
+            heads.insert(shard, head);
+        }
+
+        Ok(heads)
+    }
+}
+
+/// Highest block of each child shard the chain ending at a block received messages from, by
+/// block identifier. None if the chain received a message from an unknown block of a child shard
+/// or from blocks of a child shard that are not on the same chain.
+type Sources<'z, D> = HashMap<Hash, Option<BTreeMap<ShardId, &'z Block<D>>>>;
+
+/// Iterates over the chain ending at `block`, from `block` to its genesis block.
+fn chain<D: BlockData>(block: &Block<D>) -> impl Iterator<Item = &Block<D>> {
+    std::iter::successors(Some(block), |block| block.prev_block_as_ref())
+}
+
+/// Returns the blocks of the chains ending at `latest_blocks`, by identifier.
+fn known_blocks<D: BlockData>(
+    latest_blocks: &[(D::ValidatorName, Block<D>)],
+) -> HashMap<Hash, &Block<D>> {
+    let mut known = HashMap::new();
+    for (_, latest_block) in latest_blocks.iter() {
+        for block in chain(latest_block) {
+            if known.insert(block.id(), block).is_some() {
+                break;
+            }
+        }
+    }
+    known
+}
+
+/// Computes the sources of every known block of `shard`. Blocks are visited by increasing height
+/// so that the sources of a block extend the ones of its prevblock.
+fn child_sources<'z, D: ShardBlockData>(
+    shard: ShardId,
+    known: &HashMap<ShardId, HashMap<Hash, &'z Block<D>>>,
+) -> Sources<'z, D> {
+    let mut blocks: Vec<&Block<D>> = known[&shard].values().cloned().collect();
+    blocks.sort_by_key(|block| block.height());
+
+    let mut sources: Sources<D> = HashMap::with_capacity(blocks.len());
+    for block in blocks {
+        let inherited = match block.prevblock_id() {
+            Some(prevblock) => sources[&prevblock].clone(),
+            None => Some(BTreeMap::new()),
+        };
+        let block_sources = inherited.and_then(|mut required| {
+            for message in block
+                .data_as_ref()
+                .received()
+                .iter()
+                .filter(|message| child_shards(shard).contains(&message.source_shard))
+            {
+                let source = *known
+                    .get(&message.source_shard)
+                    .and_then(|blocks| blocks.get(&message.source_block))?;
+                match required.get(&message.source_shard) {
+                    Some(highest) if source.is_member(highest) => (),
+                    Some(highest) if !highest.is_member(source) => return None,
+                    _ => {
+                        required.insert(message.source_shard, source);
+                    }
+                }
+            }
+            Some(required)
+        });
+        sources.insert(block.id(), block_sources);
+    }
+    sources
+}
+
+impl<D: ShardBlockData> Estimator for ShardedBlocks<D> {
+    type Error = Error;
+    type ValidatorName = D::ValidatorName;
+
+    fn estimate<U: WeightUnit>(
+        latest_messages: &LatestMessagesHonest<Self>,
+        validators_weights: &validator::Weights<D::ValidatorName, U>,
+    ) -> Result<Self, Self::Error> {
+        ShardedBlocks::fork_choice(latest_messages, validators_weights)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::justification::{Justification, LatestMessages};
+    use crate::message::Message;
+
+    #[derive(Hash, Clone, PartialEq, Eq, Default, Debug, Serialize)]
+    struct Data {
+        validator: u32,
+        received: Vec<CrossShardMessage>,
+    }
+
+    impl BlockData for Data {
+        type ValidatorName = u32;
+
+        fn validator_name(&self) -> &u32 {
+            &self.validator
+        }
+    }
+
+    impl ShardBlockData for Data {
+        fn received(&self) -> &[CrossShardMessage] {
+            &self.received
+        }
+    }
+
+    fn block(
+        validator: u32,
+        prevblock: Option<&Block<Data>>,
+        received: &[(ShardId, &Block<Data>)],
+    ) -> Block<Data> {
+        Block::new(
+            prevblock.cloned(),
+            Data {
+                validator,
+                received: received
+                    .iter()
+                    .map(|(shard, block)| CrossShardMessage {
+                        source_shard: *shard,
+                        source_block: block.id(),
+                    })
+                    .collect(),
+            },
+        )
+    }
+
+    fn message(sender: u32, blocks: &[(ShardId, &Block<Data>)]) -> Message<ShardedBlocks<Data>> {
+        let mut estimate = ShardedBlocks::new();
+        for (shard, block) in blocks {
+            estimate.insert(*shard, (*block).clone());
+        }
+        Message::new(sender, Justification::empty(), estimate)
+    }
+
+    fn estimate(messages: &[&Message<ShardedBlocks<Data>>]) -> Result<ShardedBlocks<Data>, Error> {
+        estimate_with_tie_breaker(messages, &TieBreaker::LowestHash)
+    }
+
+    fn estimate_with_tie_breaker(
+        messages: &[&Message<ShardedBlocks<Data>>],
+        tie_breaker: &TieBreaker,
+    ) -> Result<ShardedBlocks<Data>, Error> {
+        let mut state = validator::State::new(
+            validator::Weights::new(vec![(0, 1.0), (1, 1.0), (2, 1.0)].into_iter().collect()),
+            0.0,
+            LatestMessages::empty(),
+            1.0,
+            BTreeSet::new(),
+        );
+        state.update(messages);
+        ShardedBlocks::fork_choice_with_tie_breaker(
+            &LatestMessagesHonest::from_latest_messages(
+                state.latests_messages(),
+                state.equivocators(),
+            ),
+            state.validators_weights(),
+            tie_breaker,
+        )
+    }
+
+    #[test]
+    fn shards_tree() {
+        assert_eq!(parent_shard(0), None);
+        assert_eq!(parent_shard(1), Some(0));
+        assert_eq!(parent_shard(2), Some(0));
+        assert_eq!(parent_shard(5), Some(2));
+        assert_eq!(child_shards(0), [1, 2]);
+        assert_eq!(child_shards(2), [5, 6]);
+    }
+
+    #[test]
+    fn tie_breakers() {
+        let root = block(0, None, &[]);
+        let child = block(0, None, &[]);
+        let genesis_message = message(0, &[(0, &root), (1, &child)]);
+        for &(early, late) in &[(1, 2), (2, 1)] {
+            let child_early = block(early, Some(&child), &[]);
+            let child_late = block(late, Some(&child), &[]);
+            let mut justification = Justification::empty();
+            justification.insert(genesis_message.clone());
+            let mut blocks = ShardedBlocks::new();
+            blocks.insert(0, root.clone());
+            blocks.insert(1, child_early.clone());
+            // Only the message on child_early shows that it was proposed after child was seen.
+            let messages = [
+                &genesis_message,
+                &Message::new(1, justification, blocks),
+                &message(2, &[(0, &root), (1, &child_late)]),
+            ];
+
+            let heads = estimate_with_tie_breaker(&messages, &TieBreaker::EarliestSeen).unwrap();
+            assert_eq!(heads.get(0), Some(&root));
+            assert_eq!(heads.get(1), Some(&child_early));
+
+            let lowest = if child_early.id() < child_late.id() {
+                child_early
+            } else {
+                child_late
+            };
+            assert_eq!(estimate(&messages).unwrap().get(1), Some(&lowest));
+        }
+    }
+
+    #[test]
+    fn received_from_parent_fork() {
+        let root = block(0, None, &[]);
+        let root_1 = block(1, Some(&root), &[]);
+        let root_2 = block(2, Some(&root), &[]);
+        let child = block(0, None, &[]);
+        let child_1 = block(1, Some(&child), &[(0, &root_1)]);
+        let child_2 = block(2, Some(&child), &[(0, &root_2)]);
+
+        let heads = estimate(&[
+            &message(0, &[(0, &root_1), (1, &child_1)]),
+            &message(1, &[(0, &root_1), (1, &child_2)]),
+            &message(2, &[(0, &root_2), (1, &child_2)]),
+        ])
+        .unwrap();
+
+        // child_2 is heavier but received a message from the losing root fork.
+        assert_eq!(heads.get(0), Some(&root_1));
+        assert_eq!(heads.get(1), Some(&child_1));
+    }
+
+    #[test]
+    fn received_by_parent() {
+        let root = block(0, None, &[]);
+        let child = block(0, None, &[]);
+        let child_1 = block(1, Some(&child), &[]);
+        let child_2 = block(2, Some(&child), &[]);
+        let root_1 = block(1, Some(&root), &[(1, &child_1)]);
+
+        let heads = estimate(&[
+            &message(0, &[(0, &root_1), (1, &child_1)]),
+            &message(1, &[(0, &root_1), (1, &child_2)]),
+            &message(2, &[(0, &root_1), (1, &child_2)]),
+        ])
+        .unwrap();
+
+        // child_2 is heavier but the root chain received a message from child_1.
+        assert_eq!(heads.get(0), Some(&root_1));
+        assert_eq!(heads.get(1), Some(&child_1));
+    }
+
+    #[test]
+    fn not_neighbour_shard() {
+        let root = block(0, None, &[]);
+        let left = block(0, None, &[]);
+        let right = block(0, None, &[]);
+        let left_1 = block(1, Some(&left), &[]);
+        let left_2 = block(2, Some(&left), &[(2, &right)]);
+
+        let heads = estimate(&[
+            &message(0, &[(0, &root), (1, &left_1), (2, &right)]),
+            &message(1, &[(0, &root), (1, &left_2), (2, &right)]),
+            &message(2, &[(0, &root), (1, &left_2), (2, &right)]),
+        ])
+        .unwrap();
+
+        assert_eq!(heads.len(), 3);
+        assert_eq!(heads.get(1), Some(&left_1));
+    }
+
+    #[test]
+    fn invalid_source_blocks_skipped() {
+        let root = block(0, None, &[]);
+        let child = block(0, None, &[]);
+        let child_1 = block(1, Some(&child), &[]);
+        let child_2 = block(2, Some(&child), &[]);
+        let unknown = block(0, Some(&child_1), &[]);
+
+        // root_unknown received a message from a block no validator knows of.
+        let root_unknown = block(0, Some(&root), &[(1, &unknown)]);
+        let heads = estimate(&[
+            &message(0, &[(0, &root_unknown), (1, &child_1)]),
+            &message(1, &[(0, &root), (1, &child_1)]),
+        ])
+        .unwrap();
+        assert_eq!(heads.get(0), Some(&root));
+        assert_eq!(heads.get(1), Some(&child_1));
+
+        // root_conflicting received messages from both forks of the child shard.
+        let root_conflicting = block(0, Some(&root), &[(1, &child_1), (1, &child_2)]);
+        let heads = estimate(&[
+            &message(0, &[(0, &root_conflicting), (1, &child_1)]),
+            &message(1, &[(0, &root_conflicting), (1, &child_1)]),
+            &message(2, &[(0, &root), (1, &child_2)]),
+        ])
+        .unwrap();
+        assert_eq!(heads.get(0), Some(&root));
+        assert_eq!(heads.get(1), Some(&child_1));
+
+        // The blocks built on top of a skipped block are skipped as well.
+        let root_on_conflicting = block(1, Some(&root_conflicting), &[]);
+        let heads = estimate(&[
+            &message(0, &[(0, &root_on_conflicting), (1, &child_2)]),
+            &message(1, &[(0, &root_on_conflicting), (1, &child_2)]),
+        ])
+        .unwrap();
+        assert_eq!(heads.get(0), Some(&root));
+        assert_eq!(heads.get(1), Some(&child_2));
+    }
+}
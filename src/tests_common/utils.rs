@@ -36,3 +36,24 @@ macro_rules! float_eq {
         )
     }};
 }
+
+/// Linear congruential generator, to build the same pseudo-random test cases on every run as
+/// `rand` is only available with the `integration_test` feature.
+#[cfg(test)]
+pub struct Lcg(u64);
+
+#[cfg(test)]
+impl Lcg {
+    pub fn new(seed: u64) -> Self {
+        Lcg(seed)
+    }
+
+    /// Returns a pseudo-random number lower than `modulo`.
+    pub fn below(&mut self, modulo: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        ((self.0 >> 33) as usize) % modulo
+    }
+}
@@ -28,6 +28,7 @@ use proptest::prelude::*;
 use crate::estimator::Estimator;
 use crate::justification::{Justification, LatestMessagesHonest};
 use crate::message;
+use crate::util::id::Id;
 use crate::util::weight::{WeightUnit, Zero};
 use crate::validator;
 
@@ -128,52 +129,39 @@ impl VoteCount {
     fn get_vote_messages(
         latest_messages: &LatestMessagesHonest<Self>,
     ) -> HashSet<message::Message<Self>> {
-        fn recursor(
-            latest_messages: &Justification<VoteCount>,
-            acc: HashSet<message::Message<VoteCount>>,
-        ) -> HashSet<message::Message<VoteCount>> {
-            latest_messages.iter().fold(acc, |mut acc_prime, message| {
-                match message.justification().len() {
-                    0 => {
-                        // vote found, vote is a message with 0 justification
-                        let estimate = *message.estimate();
-                        if estimate.is_valid() {
-                            let equivocation = message::Message::new(
-                                *message.sender(),
-                                message.justification().clone(),
-                                estimate.toggled_vote(),
-                            );
-                            // search for the equivocation of the current latest_messages
-                            match acc_prime.get(&equivocation) {
-                                // remove the equivoted vote, none of the pair
-                                // will stay on the set
-                                Some(_) => {
-                                    println!("equiv: {:?}", equivocation);
-                                    acc_prime.remove(&equivocation)
-                                }
-                                // add the vote
-                                None => {
-                                    // println!("no_equiv: {:?}", equivocation);
-                                    acc_prime.insert((*message).clone())
-                                }
-                            };
-                        }
-                        acc_prime // returns it
+        let mut votes = HashSet::new();
+        let mut visited = HashSet::new();
+        // Depth-first traversal of the justifications, in the order of the latest messages and
+        // of the justifications.
+        let mut stack: Vec<_> = latest_messages.iter().rev().cloned().collect();
+        while let Some(message) = stack.pop() {
+            if !visited.insert(message.id()) {
+                continue;
+            }
+            match message.justification().len() {
+                0 => {
+                    // vote found, vote is a message with 0 justification
+                    let estimate = *message.estimate();
+                    if estimate.is_valid() {
+                        let equivocation = message::Message::new(
+                            *message.sender(),
+                            message.justification().clone(),
+                            estimate.toggled_vote(),
+                        );
+                        // search for the equivocation of the current latest_messages
+                        match votes.get(&equivocation) {
+                            // remove the equivoted vote, none of the pair
+                            // will stay on the set
+                            Some(_) => votes.remove(&equivocation),
+                            // add the vote
+                            None => votes.insert(message),
+                        };
                     }
-                    _ => recursor(message.justification(), acc_prime),
                 }
-            })
+                _ => stack.extend(message.justification().iter().rev().cloned()),
+            }
         }
-
-        let justification =
-            latest_messages
-                .iter()
-                .fold(Justification::empty(), |mut acc, message| {
-                    acc.insert(message.clone());
-                    acc
-                });
-        // start recursion
-        recursor(&justification, HashSet::new())
+        votes
     }
 }
 
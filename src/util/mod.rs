@@ -20,4 +20,5 @@
 
 pub mod hash;
 pub mod id;
+pub(crate) mod skip_list;
 pub mod weight;
@@ -0,0 +1,72 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+//! Skip pointers over chains of blocks or of messages from one sender, letting any ancestor be
+//! reached in O(log n) jumps, following the skip list scheme used by Bitcoin Core.
+
+/// Height of the ancestor pointed to by the skip pointer of an element at `height`
+/// (`GetSkipHeight` in Bitcoin Core).
+pub(crate) fn skip_height(height: u64) -> u64 {
+    fn invert_lowest_one(n: u64) -> u64 {
+        n & n.wrapping_sub(1)
+    }
+
+    if height < 2 {
+        0
+    } else if height & 1 == 1 {
+        invert_lowest_one(invert_lowest_one(height - 1)) + 1
+    } else {
+        invert_lowest_one(height)
+    }
+}
+
+/// Whether the skip pointer of an element at `height`, rather than its direct predecessor, should
+/// be followed to reach its ancestor at `target` (`GetAncestor` in Bitcoin Core). `height` must be
+/// greater than `target`.
+pub(crate) fn follow_skip(height: u64, target: u64) -> bool {
+    let skip = skip_height(height);
+    let skip_prev = skip_height(height - 1);
+    skip == target || (skip > target && !(skip_prev + 2 < skip && skip_prev >= target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skip_height_below() {
+        assert_eq!(skip_height(0), 0);
+        assert_eq!(skip_height(1), 0);
+        for height in 2..1000 {
+            assert!(skip_height(height) < height);
+        }
+    }
+
+    #[test]
+    fn follow_skip_does_not_overshoot() {
+        for height in 1..200 {
+            for target in 0..height {
+                if follow_skip(height, target) {
+                    assert!(skip_height(height) >= target);
+                }
+            }
+        }
+    }
+}
@@ -19,7 +19,7 @@
 // Rust Library. If not, see <https://www.gnu.org/licenses/>.
 
 use std::cmp::Ordering;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::sync::{Arc, LockResult, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
@@ -86,7 +86,7 @@ impl ValidatorName for i64 {}
 /// use core_cbc_casper::validator::{State, Weights};
 /// use core_cbc_casper::VoteCount;
 ///
-/// use std::collections::HashSet;
+/// use std::collections::{BTreeSet, HashSet};
 /// use std::iter::FromIterator;
 ///
 /// let mut state = State::new(
@@ -94,7 +94,7 @@ impl ValidatorName for i64 {}
 ///     0.0,
 ///     LatestMessages::empty(),
 ///     2.0,
-///     HashSet::new(),
+///     BTreeSet::new(),
 /// );
 ///
 /// state.update(&[
@@ -111,7 +111,7 @@ impl ValidatorName for i64 {}
 /// );
 /// assert_eq!(
 ///     *state.equivocators(),
-///     HashSet::from_iter(vec![0]),
+///     BTreeSet::from_iter(vec![0]),
 /// );
 /// ```
 #[derive(Debug, Clone)]
@@ -127,7 +127,7 @@ where
     /// Current validator set, mapped to their respective weights
     pub(crate) validators_weights: Weights<E::ValidatorName, U>,
     pub(crate) latest_messages: LatestMessages<E>,
-    pub(crate) equivocators: HashSet<E::ValidatorName>,
+    pub(crate) equivocators: BTreeSet<E::ValidatorName>,
 }
 
 /// Error returned from the [`insert`], [`validators`] and [`weight`] function
@@ -175,7 +175,7 @@ where
         state_fault_weight: U,
         latest_messages: LatestMessages<E>,
         thr: U,
-        equivocators: HashSet<E::ValidatorName>,
+        equivocators: BTreeSet<E::ValidatorName>,
     ) -> Self {
         State {
             validators_weights,
@@ -192,7 +192,7 @@ where
         state_fault_weight: Option<U>,
         latest_messages: Option<LatestMessages<E>>,
         thr: Option<U>,
-        equivocators: Option<HashSet<E::ValidatorName>>,
+        equivocators: Option<BTreeSet<E::ValidatorName>>,
     ) -> Self {
         State {
             validators_weights: validators_weights.unwrap_or(default_state.validators_weights),
@@ -206,7 +206,11 @@ where
     /// Adds messages to the state's [`latests_messages`]. Returns true if
     /// all messages added are valid latest messages.
     ///
+    /// Messages that break the [`self-justification`] rule are handled as equivocations: their
+    /// sender is added to the equivocators if the threshold allows it.
+    ///
     /// [`latests_messages`]: ../justification/struct.LatestMessages.html
+    /// [`self-justification`]: ../message/struct.Message.html#method.is_self_justified
     pub fn update(&mut self, messages: &[&Message<E>]) -> bool {
         messages.iter().fold(true, |acc, message| {
             let sender = message.sender();
@@ -217,7 +221,7 @@ where
 
             let update_success = self.latest_messages.update(message);
 
-            if self.latest_messages.equivocate(message)
+            if (!message.is_self_justified() || self.latest_messages.equivocate(message))
                 && weight + self.state_fault_weight <= self.thr
                 && self.equivocators.insert(sender.clone())
             {
@@ -228,7 +232,7 @@ where
         })
     }
 
-    pub fn equivocators(&self) -> &HashSet<E::ValidatorName> {
+    pub fn equivocators(&self) -> &BTreeSet<E::ValidatorName> {
         &self.equivocators
     }
 
@@ -302,29 +306,29 @@ where
 /// );
 /// ```
 #[derive(Clone, Debug)]
-pub struct Weights<V: self::ValidatorName, U: WeightUnit>(Arc<RwLock<HashMap<V, U>>>);
+pub struct Weights<V: self::ValidatorName, U: WeightUnit>(Arc<RwLock<BTreeMap<V, U>>>);
 
 impl<V: self::ValidatorName, U: WeightUnit> Weights<V, U> {
-    /// Creates a new `Weights` struct from a `HashMap` of [`ValidatorName`] to [`WeightUnit`].
+    /// Creates a new `Weights` struct from a `BTreeMap` of [`ValidatorName`] to [`WeightUnit`].
     ///
     /// [`ValidatorName`]: trait.ValidatorName.html
     /// [`WeightUnit`]: ../util/weight/trait.WeightUnit.html
-    pub fn new(weights: HashMap<V, U>) -> Self {
+    pub fn new(weights: BTreeMap<V, U>) -> Self {
         Weights(Arc::new(RwLock::new(weights)))
     }
 
     /// Same as RwLock read function. Locks the Rwlock with read access.
-    fn read(&self) -> LockResult<RwLockReadGuard<HashMap<V, U>>> {
+    fn read(&self) -> LockResult<RwLockReadGuard<BTreeMap<V, U>>> {
         self.0.read()
     }
 
     /// Same as RwLock write function. Locks the RwLock with write access.
-    fn write(&self) -> LockResult<RwLockWriteGuard<HashMap<V, U>>> {
+    fn write(&self) -> LockResult<RwLockWriteGuard<BTreeMap<V, U>>> {
         self.0.write()
     }
 
     /// Returns success of insertion. Failure happens if we cannot acquire the lock to write data.
-    pub fn insert(&mut self, validator: V, weight: U) -> Result<bool, Error<HashMap<V, U>>> {
+    pub fn insert(&mut self, validator: V, weight: U) -> Result<bool, Error<BTreeMap<V, U>>> {
         self.write()
             .map_err(Error::WriteLockError)
             .map(|mut hash_map| {
@@ -335,7 +339,7 @@ impl<V: self::ValidatorName, U: WeightUnit> Weights<V, U> {
 
     /// Picks validators with positive weights strictly greater than zero.
     /// Failure happens if we cannot acquire the lock to read data.
-    pub fn validators(&self) -> Result<HashSet<V>, Error<HashMap<V, U>>> {
+    pub fn validators(&self) -> Result<BTreeSet<V>, Error<BTreeMap<V, U>>> {
         self.read().map_err(Error::ReadLockError).map(|hash_map| {
             hash_map
                 .iter()
@@ -352,7 +356,7 @@ impl<V: self::ValidatorName, U: WeightUnit> Weights<V, U> {
 
     /// Gets the weight of the validator. Returns an error in case there is a
     /// reading error or the validator does not exist.
-    pub fn weight(&self, validator: &V) -> Result<U, Error<HashMap<V, U>>> {
+    pub fn weight(&self, validator: &V) -> Result<U, Error<BTreeMap<V, U>>> {
         self.read()
             .map_err(Error::ReadLockError)
             .and_then(|hash_map| {
@@ -363,8 +367,9 @@ impl<V: self::ValidatorName, U: WeightUnit> Weights<V, U> {
             })
     }
 
-    /// Returns the total weight of all the given validators.
-    pub fn sum_weight_validators(&self, validators: &HashSet<V>) -> U {
+    /// Returns the total weight of all the given validators. The weights are summed in ascending
+    /// order of the validators so that the result does not depend on the order of insertion.
+    pub fn sum_weight_validators(&self, validators: &BTreeSet<V>) -> U {
         validators
             .iter()
             .fold(<U as Zero<U>>::ZERO, |acc, validator| {
@@ -386,6 +391,7 @@ impl<V: self::ValidatorName, U: WeightUnit> Weights<V, U> {
 mod tests {
     use super::*;
 
+    use crate::justification::Justification;
     use crate::VoteCount;
 
     use std::iter::FromIterator;
@@ -478,18 +484,18 @@ mod tests {
                 .collect(),
         );
         assert!(weights
-            .sum_weight_validators(&HashSet::from_iter(vec![0, 1, 3]))
+            .sum_weight_validators(&BTreeSet::from_iter(vec![0, 1, 3]))
             .is_infinite());
         float_eq!(
-            weights.sum_weight_validators(&HashSet::from_iter(vec![0, 1])),
+            weights.sum_weight_validators(&BTreeSet::from_iter(vec![0, 1])),
             0.0
         );
         float_eq!(
-            weights.sum_weight_validators(&HashSet::from_iter(vec![0, 2])),
+            weights.sum_weight_validators(&BTreeSet::from_iter(vec![0, 2])),
             4.3
         );
         assert!(weights
-            .sum_weight_validators(&HashSet::from_iter(vec![4]))
+            .sum_weight_validators(&BTreeSet::from_iter(vec![4]))
             .is_nan());
     }
 
@@ -499,7 +505,7 @@ mod tests {
         // Does not account for negatively weigthed validators
         float_eq!(
             weights.sum_all_weights(),
-            weights.sum_weight_validators(&HashSet::from_iter(vec![0, 2]))
+            weights.sum_weight_validators(&BTreeSet::from_iter(vec![0, 2]))
         );
     }
 
@@ -510,7 +516,7 @@ mod tests {
             0.0,
             LatestMessages::empty(),
             2.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         let v0 = VoteCount::create_vote_message(0, false);
@@ -558,7 +564,7 @@ mod tests {
             0.0,
             LatestMessages::empty(),
             2.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         let v0 = VoteCount::create_vote_message(0, false);
@@ -611,7 +617,7 @@ mod tests {
             0.0,
             LatestMessages::empty(),
             0.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         let v0 = VoteCount::create_vote_message(0, false);
@@ -657,6 +663,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn validator_state_update_not_self_justified() {
+        let mut validator_state = State::new(
+            Weights::new(vec![(0, 1.0), (1, 1.0)].into_iter().collect()),
+            0.0,
+            LatestMessages::empty(),
+            2.0,
+            BTreeSet::new(),
+        );
+
+        let v0 = VoteCount::create_vote_message(0, false);
+        let mut justification = Justification::empty();
+        justification.insert(v0);
+        let v1 = Message::new(1, justification, VoteCount { yes: 0, no: 1 });
+        // Validator 0's previous message is only referenced through v1.
+        let mut justification = Justification::empty();
+        justification.insert(v1.clone());
+        let m0 = Message::new(0, justification, VoteCount { yes: 0, no: 2 });
+
+        assert!(validator_state.update(&[&v1, &m0]));
+        float_eq!(
+            validator_state.fault_weight(),
+            1.0,
+            "fault weight should be 1"
+        );
+        assert_eq!(
+            *validator_state.equivocators(),
+            BTreeSet::from_iter(vec![0]),
+            "validator 0 should be in equivocators",
+        );
+    }
+
     #[test]
     fn state_sort_by_faultweight_unknown_equivocators() {
         let v0_prime = VoteCount::create_vote_message(0, false);
@@ -669,7 +707,7 @@ mod tests {
                 0.0,
                 LatestMessages::empty(),
                 10.0,
-                HashSet::new(),
+                BTreeSet::new(),
             );
             let v0 = VoteCount::create_vote_message(0, true);
             let v1 = VoteCount::create_vote_message(1, false);
@@ -698,7 +736,7 @@ mod tests {
                 0.0,
                 LatestMessages::empty(),
                 10.0,
-                HashSet::new(),
+                BTreeSet::new(),
             );
             let v0 = VoteCount::create_vote_message(0, true);
             let v0_prime = VoteCount::create_vote_message(0, false);
@@ -734,7 +772,7 @@ mod tests {
                 0.0,
                 LatestMessages::empty(),
                 1.0,
-                HashSet::new(),
+                BTreeSet::new(),
             );
             let v0 = VoteCount::create_vote_message(0, true);
             let v1 = VoteCount::create_vote_message(1, false);
@@ -23,7 +23,7 @@ extern crate core_cbc_casper;
 mod common;
 use common::binary::*;
 
-use std::collections::HashSet;
+use std::collections::BTreeSet;
 
 use core_cbc_casper::estimator::Estimator;
 use core_cbc_casper::justification::{Justification, LatestMessages, LatestMessagesHonest};
@@ -48,7 +48,7 @@ fn equal_weight() {
         0.0,
         LatestMessages::empty(),
         1.0,
-        HashSet::new(),
+        BTreeSet::new(),
     );
 
     let m0 = Message::new(validators[0], Justification::empty(), BoolWrapper(false));
@@ -109,7 +109,7 @@ fn vote_swaying() {
         0.0,
         LatestMessages::empty(),
         1.0,
-        HashSet::new(),
+        BTreeSet::new(),
     );
 
     let m0 = Message::new(validators[0], Justification::empty(), BoolWrapper(false));
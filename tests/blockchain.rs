@@ -20,7 +20,7 @@
 
 extern crate core_cbc_casper;
 
-use std::collections::HashSet;
+use std::collections::BTreeSet;
 
 use core_cbc_casper::blockchain::Block;
 use core_cbc_casper::justification::{Justification, LatestMessages};
@@ -45,7 +45,7 @@ fn partial_view() {
         0.0,
         LatestMessages::empty(),
         1.0,
-        HashSet::new(),
+        BTreeSet::new(),
     );
 
     let genesis_block = Block::new(None, ValidatorNameBlockData::new(0));
@@ -146,7 +146,7 @@ fn full_view() {
         0.0,
         LatestMessages::empty(),
         1.0,
-        HashSet::new(),
+        BTreeSet::new(),
     );
 
     let genesis_block = Block::new(None, ValidatorNameBlockData::new(0));
@@ -0,0 +1,170 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+//! Runs the same inputs in several threads, each having its own random `HashMap` and `HashSet`
+//! seeds, and checks that the resulting estimates and message ids are identical. The weights are
+//! chosen so that their floating point sum depends on the order of the additions:
+//! `0.1 + 0.2 + 0.3 > 0.6` but `0.3 + 0.2 + 0.1 == 0.6`.
+
+extern crate core_cbc_casper;
+
+use std::collections::BTreeSet;
+use std::thread;
+
+use core_cbc_casper::blockchain::Block;
+use core_cbc_casper::estimator::Estimator;
+use core_cbc_casper::justification::{Justification, LatestMessages, LatestMessagesHonest};
+use core_cbc_casper::message::Message;
+use core_cbc_casper::util::hash::Hash;
+use core_cbc_casper::util::id::Id;
+use core_cbc_casper::util::weight::{WeightUnit, Zero};
+use core_cbc_casper::validator;
+use core_cbc_casper::ValidatorNameBlockData;
+
+const RUNS: usize = 16;
+const WEIGHTS: [f64; 4] = [0.1, 0.2, 0.3, 0.6];
+
+fn weights() -> validator::Weights<u32, f64> {
+    validator::Weights::new((0..4).zip(WEIGHTS.iter().cloned()).collect())
+}
+
+/// Binary vote summing the weights of the latest honest messages in their iteration order.
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, serde_derive::Serialize)]
+struct Vote(bool);
+
+impl Estimator for Vote {
+    type ValidatorName = u32;
+    type Error = std::fmt::Error;
+
+    fn estimate<U: WeightUnit>(
+        latest_messages: &LatestMessagesHonest<Self>,
+        validators_weights: &validator::Weights<u32, U>,
+    ) -> Result<Self, Self::Error> {
+        let (yes, no) = latest_messages.iter().fold(
+            (<U as Zero<U>>::ZERO, <U as Zero<U>>::ZERO),
+            |(yes, no), message| {
+                let weight = validators_weights.weight(message.sender()).unwrap();
+                if message.estimate().0 {
+                    (yes + weight, no)
+                } else {
+                    (yes, no + weight)
+                }
+            },
+        );
+        Ok(Vote(yes > no))
+    }
+}
+
+fn vote() -> Vec<(Hash, Vote)> {
+    let mut state = validator::State::new(
+        weights(),
+        0.0,
+        LatestMessages::empty(),
+        1.0,
+        BTreeSet::new(),
+    );
+    let votes: Vec<_> = (0..4)
+        .map(|validator| Message::new(validator, Justification::empty(), Vote(validator < 3)))
+        .collect();
+    state.update(&votes.iter().collect::<Vec<_>>());
+
+    let mut results = Vec::new();
+    for validator in 0..4 {
+        let message = Message::from_validator_state(validator, &state).unwrap();
+        results.push((message.id(), *message.estimate()));
+    }
+    results
+}
+
+fn blockchain() -> Vec<Hash> {
+    let mut state = validator::State::new(
+        weights(),
+        0.0,
+        LatestMessages::empty(),
+        1.0,
+        BTreeSet::new(),
+    );
+    let genesis = Message::new(
+        3,
+        Justification::empty(),
+        Block::new(None, ValidatorNameBlockData::new(3)),
+    );
+    state.update(&[&genesis]);
+
+    // Validators 0, 1 and 2 build a chain on one side of the fork and validator 3 on the other.
+    let mut messages = vec![genesis.clone()];
+    for validator in 0..3 {
+        let prevblock = messages.last().unwrap().clone();
+        let mut justification = Justification::empty();
+        justification.insert(prevblock.clone());
+        messages.push(Message::new(
+            validator,
+            justification,
+            Block::new(
+                Some(prevblock.estimate().clone()),
+                ValidatorNameBlockData::new(validator),
+            ),
+        ));
+    }
+    let mut justification = Justification::empty();
+    justification.insert(genesis.clone());
+    messages.push(Message::new(
+        3,
+        justification,
+        Block::new(
+            Some(genesis.estimate().clone()),
+            ValidatorNameBlockData::new(3),
+        ),
+    ));
+    state.update(&messages.iter().collect::<Vec<_>>());
+
+    let mut ids = Vec::new();
+    for validator in 0..4 {
+        let message = Message::from_validator_state(validator, &state).unwrap();
+        ids.push(message.id());
+        ids.push(message.estimate().prevblock().unwrap().id());
+    }
+    ids
+}
+
+fn run_in_threads<T, F>(run: F) -> Vec<T>
+where
+    T: Send + 'static,
+    F: Fn() -> T + Send + Copy + 'static,
+{
+    (0..RUNS)
+        .map(|_| thread::spawn(run))
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect()
+}
+
+#[test]
+fn estimator_determinism() {
+    let results = run_in_threads(vote);
+    assert!(results.iter().all(|result| *result == results[0]));
+}
+
+#[test]
+fn blockchain_determinism() {
+    let results = run_in_threads(blockchain);
+    assert!(results.iter().all(|result| *result == results[0]));
+}
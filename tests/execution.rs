@@ -0,0 +1,210 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+extern crate core_cbc_casper;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use core_cbc_casper::blockchain::{
+    Block, BlockData, Executor, PayloadSource, Proposer, StateMachine,
+};
+use core_cbc_casper::justification::{Justification, LatestMessages};
+use core_cbc_casper::message::Message;
+use core_cbc_casper::validator;
+
+type Validator = u32;
+
+#[derive(Debug, Hash, Clone, PartialEq, Eq, serde_derive::Serialize)]
+enum Operation {
+    Set(String, u64),
+    Delete(String),
+}
+
+#[derive(Debug, Hash, Clone, PartialEq, Eq, Default, serde_derive::Serialize)]
+struct KvBlock {
+    proposer: Validator,
+    operations: Vec<Operation>,
+}
+
+impl BlockData for KvBlock {
+    type ValidatorName = Validator;
+
+    fn validator_name(&self) -> &Self::ValidatorName {
+        &self.proposer
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct KvStore {
+    values: BTreeMap<String, u64>,
+    applied_blocks: u64,
+}
+
+impl StateMachine<KvBlock> for KvStore {
+    fn apply(&self, data: &KvBlock) -> Self {
+        let mut values = self.values.clone();
+        for operation in data.operations.iter() {
+            match operation {
+                Operation::Set(key, value) => {
+                    values.insert(key.clone(), *value);
+                }
+                Operation::Delete(key) => {
+                    values.remove(key);
+                }
+            }
+        }
+        KvStore {
+            values,
+            applied_blocks: self.applied_blocks + 1,
+        }
+    }
+}
+
+/// Every proposer writes its own name and the round under a key shared by all validators and
+/// under a key of its own.
+struct Client {
+    proposer: Validator,
+    round: u64,
+}
+
+impl PayloadSource<KvBlock> for Client {
+    type Error = std::fmt::Error;
+
+    fn payload(&mut self, _parent: &Block<KvBlock>) -> Result<KvBlock, Self::Error> {
+        let own_key = format!("validator-{}", self.proposer);
+        let operations = if self.round % 5 == 4 {
+            vec![Operation::Delete(own_key)]
+        } else {
+            vec![
+                Operation::Set("last".to_string(), u64::from(self.proposer)),
+                Operation::Set(own_key, self.round),
+            ]
+        };
+        Ok(KvBlock {
+            proposer: self.proposer,
+            operations,
+        })
+    }
+}
+
+/// Replays the chain ending at `block` from the genesis block.
+fn replay(block: &Block<KvBlock>) -> KvStore {
+    let mut chain = vec![block.clone()];
+    while let Some(prevblock) = chain.last().unwrap().prevblock() {
+        chain.push(prevblock);
+    }
+    chain.iter().rev().fold(KvStore::default(), |store, block| {
+        store.apply(&block.data())
+    })
+}
+
+#[test]
+fn key_value_store() {
+    let validators: Vec<Validator> = (0..4).collect();
+    let weights = validator::Weights::new(validators.iter().map(|v| (*v, 1.0)).collect());
+    let mut states: Vec<_> = validators
+        .iter()
+        .map(|_| {
+            validator::State::new(
+                weights.clone(),
+                0.0,
+                LatestMessages::empty(),
+                1.0,
+                BTreeSet::new(),
+            )
+        })
+        .collect();
+    let mut executors: Vec<_> = validators
+        .iter()
+        .map(|_| Executor::new(KvStore::default(), 2.5))
+        .collect();
+    let mut finalized: Vec<Vec<Block<KvBlock>>> = validators.iter().map(|_| vec![]).collect();
+
+    let genesis = Message::new(
+        0,
+        Justification::empty(),
+        Block::new(None, KvBlock::default()),
+    );
+    for state in states.iter_mut() {
+        state.update(&[&genesis]);
+    }
+
+    // The last validator only receives the messages every third round, so it regularly
+    // proposes on a stale head and the others have to reorg their non-finalized blocks.
+    let mut delayed = Vec::new();
+    for round in 0..20 {
+        let proposer = validators[round as usize % validators.len()];
+        let message = Proposer::new(proposer)
+            .propose(&states[proposer as usize], &mut Client { proposer, round })
+            .unwrap();
+
+        for (validator, state) in states.iter_mut().enumerate() {
+            if validator == 3 && proposer != 3 {
+                continue;
+            }
+            state.update(&[&message]);
+        }
+        delayed.push(message);
+        if round % 3 == 2 {
+            states[3].update(&delayed.iter().collect::<Vec<_>>());
+            delayed.clear();
+        }
+
+        for (validator, executor) in executors.iter_mut().enumerate() {
+            let newly_finalized = executor.update(&states[validator]).unwrap();
+            finalized[validator].extend(newly_finalized);
+            assert_eq!(
+                executor.head_state(),
+                &replay(executor.head_block().unwrap())
+            );
+        }
+    }
+
+    states[3].update(&delayed.iter().collect::<Vec<_>>());
+    executors[3]
+        .update(&states[3])
+        .map(|blocks| finalized[3].extend(blocks))
+        .unwrap();
+
+    for (executor, finalized) in executors.iter().zip(finalized.iter()) {
+        let finalized_block = executor.finalized_block().unwrap();
+        assert!(finalized_block.height() >= 10);
+
+        // Every finalized block was applied exactly once, in order.
+        assert_eq!(
+            executor.finalized_state().applied_blocks,
+            finalized_block.height() + 1
+        );
+        assert_eq!(executor.finalized_state(), &replay(finalized_block));
+        for (height, block) in finalized.iter().enumerate() {
+            assert_eq!(block.height(), height as u64);
+            assert!(block.is_member(finalized_block));
+        }
+    }
+
+    // Finalized chains never conflict.
+    for executor in executors.iter() {
+        for other in executors.iter() {
+            let block = executor.finalized_block().unwrap();
+            let other_block = other.finalized_block().unwrap();
+            assert!(block.is_member(other_block) || other_block.is_member(block));
+        }
+    }
+}
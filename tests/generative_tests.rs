@@ -23,7 +23,7 @@ extern crate core_cbc_casper;
 extern crate proptest;
 extern crate rand;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::iter;
 use std::iter::FromIterator;
 
@@ -35,7 +35,9 @@ use proptest::test_runner::{RngAlgorithm, TestRng, TestRunner};
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 
-use core_cbc_casper::blockchain::Block;
+use core_cbc_casper::blockchain::{
+    Block, ForkChoice, Ghost, ImdGhost, LongestChain, PayloadSource, Proposer,
+};
 use core_cbc_casper::estimator::Estimator;
 use core_cbc_casper::justification::{Justification, LatestMessages, LatestMessagesHonest};
 use core_cbc_casper::message::{self, Message};
@@ -136,7 +138,7 @@ where
                         0.0,
                         LatestMessages::from(message.justification()),
                         0.0,
-                        HashSet::new(),
+                        BTreeSet::new(),
                     );
 
                     for justification_message in message.justification().iter() {
@@ -304,7 +306,7 @@ fn get_data_from_state(
             Block::safety_oracles(
                 genesis_block,
                 &latest_messages_honest,
-                &HashSet::new(),
+                &BTreeSet::new(),
                 safety_threshold,
                 validator_state.validators_weights(),
             ) != HashSet::new()
@@ -380,7 +382,7 @@ fn clique_collection(
             let safety_oracles = Block::safety_oracles(
                 genesis_block,
                 &latest_honest_messages,
-                &HashSet::new(),
+                &BTreeSet::new(),
                 // cliques, not safety oracles, because our threshold is 0
                 0.0,
                 validator_state.validators_weights(),
@@ -452,7 +454,7 @@ where
                             0.0,
                             LatestMessages::from(&justification),
                             0.0,
-                            HashSet::new(),
+                            BTreeSet::new(),
                         ),
                     )
                 })
@@ -890,7 +892,7 @@ proptest! {
             0.0,
             LatestMessages::empty(),
             0.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         // here, only take one equivocation
@@ -933,7 +935,7 @@ proptest! {
             0.0,
             LatestMessages::empty(),
             0.0,
-            HashSet::new(),
+            BTreeSet::new(),
         );
 
         for message in messages.iter() {
@@ -969,7 +971,7 @@ proptest! {
             0.0,
             LatestMessages::empty(),
             equivocators.len() as f64,
-            HashSet::new(),
+            BTreeSet::new(),
         );
         for message in messages.iter() {
             validator_state.update(&[&message]);
@@ -992,7 +994,7 @@ proptest! {
 }
 
 prop_compose! {
-    /// `latest_messages` produces a `LatestMessages<VoteCount>` and a `HashSet<u32>`
+    /// `latest_messages` produces a `LatestMessages<VoteCount>` and a `BTreeSet<u32>`
     /// (equivocators). To produce that we create a `validator::State` and a
     /// `Justification` and use `Justification::from_message` to populate the
     /// `latest_messages` and `equivocators` field in the state, which we then
@@ -1009,10 +1011,10 @@ prop_compose! {
             ),
             validators_count in Just(validators_count),
         )
-        -> (LatestMessages<VoteCount>, HashSet<u32>)
+        -> (LatestMessages<VoteCount>, BTreeSet<u32>)
     {
         let latest_messages = LatestMessages::empty();
-        let equivocators = HashSet::new();
+        let equivocators = BTreeSet::new();
 
         let validators_weights = validator::Weights::new(
             (0..validators_count)
@@ -1067,3 +1069,109 @@ proptest! {
         );
     }
 }
+
+/// Schedules of at most `proposals` block proposals between `validators` validators: for each
+/// proposal, the proposer and the bit mask of the validators receiving the block.
+fn proposal_schedule(validators: u32, proposals: usize) -> BoxedStrategy<Vec<(u32, u8)>> {
+    prop::collection::vec((0..validators, any::<u8>()), 1..proposals).boxed()
+}
+
+/// Names the proposer in the data of its blocks.
+struct ProposerName(u32);
+
+impl PayloadSource<ValidatorNameBlockData<u32>> for ProposerName {
+    type Error = std::fmt::Error;
+
+    fn payload(
+        &mut self,
+        _parent: &Block<ValidatorNameBlockData<u32>>,
+    ) -> Result<ValidatorNameBlockData<u32>, Self::Error> {
+        Ok(ValidatorNameBlockData::new(self.0))
+    }
+}
+
+/// Runs the schedule with every validator proposing on top of the head selected by
+/// `fork_choice`, and returns the number of blocks built on a parent that already had a child.
+fn forks_on_schedule<F: ForkChoice + Clone>(
+    fork_choice: F,
+    validators: u32,
+    schedule: &[(u32, u8)],
+) -> usize {
+    let weights =
+        validator::Weights::new((0..validators).map(|validator| (validator, 1.0)).collect());
+    let genesis = Message::new(
+        0,
+        Justification::empty(),
+        Block::new(None, ValidatorNameBlockData::new(0)),
+    );
+    let mut states: Vec<_> = (0..validators)
+        .map(|_| {
+            let mut state = validator::State::new(
+                weights.clone(),
+                0.0,
+                LatestMessages::empty(),
+                1.0,
+                BTreeSet::new(),
+            );
+            state.update(&[&genesis]);
+            state
+        })
+        .collect();
+
+    let mut children = HashMap::new();
+    let mut forks = 0;
+    for &(proposer, receivers) in schedule {
+        let message = Proposer::with_fork_choice(proposer, fork_choice.clone())
+            .propose(&states[proposer as usize], &mut ProposerName(proposer))
+            .unwrap();
+        let siblings = children
+            .entry(message.estimate().prevblock().unwrap())
+            .or_insert(0);
+        if *siblings > 0 {
+            forks += 1;
+        }
+        *siblings += 1;
+        for (validator, state) in (0..).zip(states.iter_mut()) {
+            if validator == proposer || receivers & (1 << validator) != 0 {
+                state.update(&[&message]);
+            }
+        }
+    }
+    forks
+}
+
+proptest! {
+    #![proptest_config(Config::with_cases(30))]
+    #[test]
+    fn fork_rates_on_same_schedule(ref schedule in proposal_schedule(4, 40)) {
+        let forks = [
+            forks_on_schedule(Ghost, 4, schedule),
+            forks_on_schedule(LongestChain, 4, schedule),
+            forks_on_schedule(ImdGhost, 4, schedule),
+        ];
+        let mut fork_rates_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open("fork_rates.log")
+            .unwrap();
+        writeln!(
+            fork_rates_file,
+            "blocks: {}, ghost: {}, longest chain: {}, imd-ghost: {}",
+            schedule.len(),
+            forks[0],
+            forks[1],
+            forks[2],
+        )
+        .unwrap();
+        assert!(forks.iter().all(|&forks| forks < schedule.len()));
+
+        // When every block reaches every validator, no rule forks.
+        let broadcast: Vec<_> = schedule
+            .iter()
+            .map(|&(proposer, _)| (proposer, 0b1111))
+            .collect();
+        assert_eq!(forks_on_schedule(Ghost, 4, &broadcast), 0);
+        assert_eq!(forks_on_schedule(LongestChain, 4, &broadcast), 0);
+        assert_eq!(forks_on_schedule(ImdGhost, 4, &broadcast), 0);
+    }
+}
@@ -20,7 +20,7 @@
 
 extern crate core_cbc_casper;
 
-use std::collections::HashSet;
+use std::collections::BTreeSet;
 
 use core_cbc_casper::IntegerWrapper;
 
@@ -47,7 +47,7 @@ fn equal_weight() {
         0.0,
         LatestMessages::empty(),
         1.0,
-        HashSet::new(),
+        BTreeSet::new(),
     );
 
     assert_eq!(
@@ -109,7 +109,7 @@ fn uneven_weights_1() {
         0.0,
         LatestMessages::empty(),
         1.0,
-        HashSet::new(),
+        BTreeSet::new(),
     );
 
     assert_eq!(
@@ -171,7 +171,7 @@ fn uneven_weights_4() {
         0.0,
         LatestMessages::empty(),
         1.0,
-        HashSet::new(),
+        BTreeSet::new(),
     );
 
     assert_eq!(
@@ -0,0 +1,113 @@
+// Core CBC Casper
+// Copyright (C) 2018 - 2020  Coordination Technology Ltd.
+// Authors: pZ4 <pz4@protonmail.ch>,
+//          Lederstrumpf,
+//          h4sh3d <h4sh3d@truelevel.io>
+//          roflolilolmao <q@truelevel.ch>
+//
+// This file is part of Core CBC Casper.
+//
+// Core CBC Casper is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Affero General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// Core CBC Casper is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with the Core CBC
+// Rust Library. If not, see <https://www.gnu.org/licenses/>.
+
+extern crate core_cbc_casper;
+
+use std::collections::BTreeSet;
+
+use core_cbc_casper::estimator::{Estimator, PluralityEstimator};
+use core_cbc_casper::justification::{Justification, LatestMessages, LatestMessagesHonest};
+use core_cbc_casper::message::Message;
+use core_cbc_casper::util::hash::Hash;
+use core_cbc_casper::util::id::Id;
+use core_cbc_casper::validator;
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PluralityEstimator, serde_derive::Serialize)]
+#[plurality(validator_name = "u32")]
+enum Declared {
+    Yes,
+    No,
+    Abstain,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PluralityEstimator, serde_derive::Serialize)]
+#[plurality(validator_name = "u32", tie_breaking = "priority")]
+enum Prioritized {
+    Yes,
+    #[plurality(priority = 2)]
+    No,
+    #[plurality(priority = 1)]
+    Abstain,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PluralityEstimator, serde_derive::Serialize)]
+#[plurality(validator_name = "u32", tie_breaking = "hash")]
+enum Hashed {
+    Yes,
+    No,
+}
+
+impl Id for Hashed {
+    type ID = Hash;
+}
+
+fn estimate<E: Estimator<ValidatorName = u32>>(estimates: &[E], weights: &[f64]) -> E {
+    let mut latest_messages = LatestMessages::empty();
+    for (validator, estimate) in (0..).zip(estimates.iter()) {
+        latest_messages.update(&Message::new(
+            validator,
+            Justification::empty(),
+            estimate.clone(),
+        ));
+    }
+    E::estimate(
+        &LatestMessagesHonest::from_latest_messages(&latest_messages, &BTreeSet::new()),
+        &validator::Weights::new((0..).zip(weights.iter().cloned()).collect()),
+    )
+    .unwrap()
+}
+
+#[test]
+fn highest_weight() {
+    use Declared::*;
+    assert_eq!(estimate(&[Yes, No, No], &[1.0, 1.0, 1.0]), No);
+    assert_eq!(estimate(&[Yes, No, No], &[3.0, 1.0, 1.0]), Yes);
+    assert_eq!(estimate(&[Yes, No, Abstain], &[1.0, 1.0, 1.5]), Abstain);
+    // Validators without weight are ignored.
+    assert_eq!(estimate(&[Yes, No, No], &[1.5]), Yes);
+}
+
+#[test]
+fn declaration_order() {
+    use Declared::*;
+    assert_eq!(estimate::<Declared>(&[], &[]), Yes);
+    assert_eq!(estimate(&[Abstain, No], &[1.0, 1.0]), No);
+    assert_eq!(estimate(&[Abstain, No, Yes], &[1.0, 1.0, 1.0]), Yes);
+}
+
+#[test]
+fn priority() {
+    use Prioritized::*;
+    assert_eq!(estimate::<Prioritized>(&[], &[]), No);
+    assert_eq!(estimate(&[Yes, Abstain], &[1.0, 1.0]), Abstain);
+    assert_eq!(estimate(&[Yes, Abstain, No], &[1.0, 1.0, 1.0]), No);
+    assert_eq!(estimate(&[Yes, Abstain, No], &[1.0, 1.0, 0.5]), Abstain);
+}
+
+#[test]
+fn hash() {
+    use Hashed::*;
+    let smallest_hash = if Yes.id() < No.id() { Yes } else { No };
+    assert_eq!(estimate::<Hashed>(&[], &[]), smallest_hash);
+    assert_eq!(estimate(&[Yes, No], &[1.0, 1.0]), smallest_hash);
+    assert_eq!(estimate(&[No, Yes], &[1.0, 1.0]), smallest_hash);
+    assert_eq!(estimate(&[Yes, No], &[1.0, 2.0]), No);
+    assert_eq!(estimate(&[Yes, No], &[2.0, 1.0]), Yes);
+}
@@ -83,14 +83,8 @@ pub fn get_height_selected_chain(
     .unwrap()
     .prevblock()
     .ok_or(core_cbc_casper::blockchain::Error);
-    fn reduce(block: &Block<ValidatorNameBlockData<u32>>, i: u32) -> u32 {
-        match block.prevblock() {
-            Some(previous_block) => reduce(&previous_block, i + 1),
-            None => i,
-        }
-    }
     match selected_block {
-        Ok(block) => reduce(&block, 1),
+        Ok(block) => block.height() as u32 + 1,
         _ => 0,
     }
 }